@@ -0,0 +1,191 @@
+//! Structured diagnostics produced by parsing and reconciliation.
+
+use crate::parse::Span;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    /// The severity as it appears in serialized diagnostics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+/// Stable identifier for the kind of problem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticCode {
+    /// A `%% @node`/`%% @edge`/`%% @ai`/`%% @participant`/`%% @mermaidman` line that does not parse.
+    InvalidDirective,
+    /// A second directive for the same node (the last one wins), a second
+    /// schema line (the first one wins), or a second edge with the same EID.
+    DuplicateDirective,
+    /// A directive for a node, edge or participant that is not in the diagram.
+    OrphanDirective,
+    /// An edge endpoint that is not a known node.
+    UnknownNode,
+    /// A topology statement the parser does not understand; it is kept verbatim.
+    UnparsedStatement,
+    /// A document written with a newer directive schema than this engine knows.
+    UnsupportedVersion,
+    /// Frontmatter or a `%%{init}%%` line that does not parse, or a config
+    /// value of the wrong type.
+    InvalidConfig,
+    /// A node of the previous store that is no longer in the topology.
+    OrphanedNode,
+    /// An edge of the previous store that is no longer in the topology.
+    OrphanedEdge,
+}
+
+impl DiagnosticCode {
+    /// The code as it appears in serialized diagnostics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticCode::InvalidDirective => "invalid_directive",
+            DiagnosticCode::DuplicateDirective => "duplicate_directive",
+            DiagnosticCode::OrphanDirective => "orphan_directive",
+            DiagnosticCode::UnknownNode => "unknown_node",
+            DiagnosticCode::UnparsedStatement => "unparsed_statement",
+            DiagnosticCode::UnsupportedVersion => "unsupported_version",
+            DiagnosticCode::InvalidConfig => "invalid_config",
+            DiagnosticCode::OrphanedNode => "orphaned_node",
+            DiagnosticCode::OrphanedEdge => "orphaned_edge",
+        }
+    }
+}
+
+/// A secondary location that explains a diagnostic.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelatedSpan {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Replace the text covered by `span` with `new_text`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextEdit {
+    pub span: Span,
+    pub new_text: String,
+}
+
+/// A quick fix the editor can offer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fix {
+    pub title: String,
+    pub edits: Vec<TextEdit>,
+}
+
+/// A problem found in a document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub severity: Severity,
+    pub message: String,
+    /// Where the problem is; `None` when it has no place in the current text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<RelatedSpan>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<Fix>,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic without a location.
+    pub fn new(code: DiagnosticCode, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            severity,
+            message: message.into(),
+            span: None,
+            related: Vec::new(),
+            fix: None,
+        }
+    }
+
+    /// Create an error diagnostic.
+    pub fn error(code: DiagnosticCode, message: impl Into<String>) -> Self {
+        Self::new(code, Severity::Error, message)
+    }
+
+    /// Create a warning diagnostic.
+    pub fn warning(code: DiagnosticCode, message: impl Into<String>) -> Self {
+        Self::new(code, Severity::Warning, message)
+    }
+
+    /// Set the location.
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Add a related location.
+    pub fn with_related(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.related.push(RelatedSpan {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Offer a fix that deletes the text covered by `span`.
+    pub fn with_removal(mut self, title: impl Into<String>, span: Span) -> Self {
+        self.fix = Some(Fix {
+            title: title.into(),
+            edits: vec![TextEdit {
+                span,
+                new_text: String::new(),
+            }],
+        });
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} [{}]: {}", span, self.code.as_str(), self.message),
+            None => write!(f, "[{}]: {}", self.code.as_str(), self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostic_serialization() {
+        let span = Span {
+            start: 9,
+            end: 20,
+            start_line: 2,
+            start_column: 1,
+            end_line: 2,
+            end_column: 12,
+        };
+        let diagnostic = Diagnostic::warning(DiagnosticCode::OrphanDirective, "No node `X`")
+            .with_span(span)
+            .with_removal("Remove orphan directive", span);
+
+        let json = serde_json::to_value(&diagnostic).unwrap();
+        assert_eq!(json["code"], "orphan_directive");
+        assert_eq!(json["severity"], "warning");
+        assert_eq!(json["fix"]["edits"][0]["new_text"], "");
+        assert!(json.get("related").is_none());
+        assert_eq!(diagnostic.to_string(), "2:1 [orphan_directive]: No node `X`");
+    }
+}
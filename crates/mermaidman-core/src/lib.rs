@@ -12,9 +12,13 @@
 //! - `store` - In-memory graph model
 //! - `index` - Search and backlinks (trait-based)
 //! - `ops` - Event-sourced operations for undo/redo
+//! - `migrate` - Directive schema versions and migrations
+//! - `diagnostic` - Structured parse/reconcile diagnostics
 //! - `error` - Error types
 
+pub mod diagnostic;
 pub mod error;
+pub mod migrate;
 pub mod ops;
 pub mod parse;
 pub mod reconcile;
@@ -23,5 +27,6 @@ pub mod types;
 pub mod write;
 
 // Re-exports for convenience
+pub use diagnostic::{Diagnostic, DiagnosticCode, Severity};
 pub use error::{Error, Result};
 pub use types::*;
@@ -0,0 +1,174 @@
+//! Directive schema versions and the migrations between them.
+//!
+//! A document declares the schema its directives were written with in a
+//! `%% @mermaidman: {"version":N}` line. Documents without one predate
+//! versioning and are read as [`UNVERSIONED`]. When parsing, every directive
+//! body is upgraded one version at a time to [`SCHEMA_VERSION`].
+
+use serde_json::{Map, Value};
+
+/// The directive schema this engine reads and writes.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// The version of documents without a `%% @mermaidman` line.
+pub const UNVERSIONED: u32 = 1;
+
+/// Which kind of directive a body belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectiveKind {
+    Node,
+    Edge,
+    Ai,
+}
+
+/// An upgrade of directive bodies from one schema version to the next.
+pub struct Migration {
+    /// The version this migration upgrades from; it produces `from + 1`.
+    pub from: u32,
+    /// What changed between the two versions.
+    pub description: &'static str,
+    /// Whether the migration only applies to documents that declare `from`.
+    /// Set for steps that undo what writers of exactly that version did;
+    /// older documents skip them.
+    pub declared_only: bool,
+    /// Rewrite one directive body in place, given the token after `@node:`,
+    /// `@edge:` or `@ai:`.
+    pub apply: fn(DirectiveKind, &str, &mut Map<String, Value>),
+}
+
+/// Every migration, oldest first, one per version step.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        description: "unwrap the earlier directive that unversioned writers nested into `meta`",
+        declared_only: false,
+        apply: unnest_meta,
+    },
+    Migration {
+        from: 2,
+        description: "an edge's EID moves from the token after `@edge:` into the `eid` key",
+        declared_only: true,
+        apply: eid_from_token,
+    },
+];
+
+/// Upgrade a directive body written with schema `version` to [`SCHEMA_VERSION`].
+///
+/// `token` is the word after `@node:`, `@edge:` or `@ai:`. Bodies that are
+/// not JSON objects, or that are already current (or newer), are left as
+/// they are.
+pub fn migrate_directive(kind: DirectiveKind, token: &str, body: &mut Value, version: u32) {
+    let Some(map) = body.as_object_mut() else {
+        return;
+    };
+    let pending = MIGRATIONS
+        .iter()
+        .filter(|m| m.from == version || (m.from > version && !m.declared_only));
+    for migration in pending {
+        (migration.apply)(kind, token, map);
+    }
+}
+
+/// Keys written by the engine itself for each kind of directive.
+fn own_keys(kind: DirectiveKind) -> &'static [&'static str] {
+    match kind {
+        DirectiveKind::Node => &[
+            "uid", "x", "y", "kind", "style", "code", "media", "diagram", "markdown",
+        ],
+        DirectiveKind::Edge => &["eid", "source", "target", "label", "style"],
+        DirectiveKind::Ai => &[],
+    }
+}
+
+/// 1 → 2: unversioned writers stored the previous directive as `meta`, so
+/// every save nested another copy (`{"meta":{"uid":..,"meta":{..}}}`). Drop
+/// the copied keys at every level and keep what is left as `meta`.
+fn unnest_meta(kind: DirectiveKind, _token: &str, body: &mut Map<String, Value>) {
+    if kind == DirectiveKind::Ai {
+        return;
+    }
+    let Some(Value::Object(mut meta)) = body.remove("meta") else {
+        return;
+    };
+
+    let own = own_keys(kind);
+    loop {
+        meta.retain(|key, _| !own.contains(&key.as_str()));
+        match meta.remove("meta") {
+            Some(Value::Object(inner)) => {
+                for (key, value) in inner {
+                    meta.entry(key).or_insert(value);
+                }
+            }
+            Some(other) => {
+                meta.insert("meta".to_string(), other);
+                break;
+            }
+            None => break,
+        }
+    }
+
+    if !meta.is_empty() {
+        body.insert("meta".to_string(), Value::Object(meta));
+    }
+}
+
+/// 2 → 3: version 2 readers took an edge's EID from the token after `@edge:`
+/// and ignored the `eid` key, which writers filled with the store's EID. Keep
+/// the identity readers saw by moving the token into `eid`. Unversioned
+/// readers never matched edge directives, so their `eid` is kept.
+fn eid_from_token(kind: DirectiveKind, token: &str, body: &mut Map<String, Value>) {
+    if kind == DirectiveKind::Edge {
+        body.insert("eid".to_string(), Value::String(token.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrations_are_contiguous() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.from, UNVERSIONED + i as u32);
+        }
+        assert_eq!(UNVERSIONED + MIGRATIONS.len() as u32, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_unnest_meta() {
+        let mut body = json!({
+            "uid": "n_1",
+            "x": 10,
+            "meta": {"uid": "n_1", "x": 5, "meta": {"uid": "n_1", "tags": ["draft"]}}
+        });
+        migrate_directive(DirectiveKind::Node, "A", &mut body, UNVERSIONED);
+        assert_eq!(body, json!({"uid": "n_1", "x": 10, "meta": {"tags": ["draft"]}}));
+
+        let mut body = json!({"eid": "e1", "meta": {"eid": "e1", "source": "n_1"}});
+        migrate_directive(DirectiveKind::Edge, "e1", &mut body, UNVERSIONED);
+        assert_eq!(body, json!({"eid": "e1"}));
+
+        // Current bodies are not touched
+        let mut body = json!({"uid": "n_1", "meta": {"x": 1}});
+        migrate_directive(DirectiveKind::Node, "A", &mut body, SCHEMA_VERSION);
+        assert_eq!(body, json!({"uid": "n_1", "meta": {"x": 1}}));
+    }
+
+    #[test]
+    fn test_eid_from_token() {
+        let mut body = json!({"eid": "e_store", "source": "n_1", "target": "n_2"});
+        migrate_directive(DirectiveKind::Edge, "e1", &mut body, 2);
+        assert_eq!(body, json!({"eid": "e1", "source": "n_1", "target": "n_2"}));
+
+        let mut body = json!({"uid": "n_1"});
+        migrate_directive(DirectiveKind::Node, "A", &mut body, 2);
+        assert_eq!(body, json!({"uid": "n_1"}));
+
+        // Unversioned edges keep their `eid`
+        let mut body = json!({"eid": "e_keep", "source": "n_1", "target": "n_2"});
+        migrate_directive(DirectiveKind::Edge, "e1", &mut body, UNVERSIONED);
+        assert_eq!(body, json!({"eid": "e_keep", "source": "n_1", "target": "n_2"}));
+    }
+}
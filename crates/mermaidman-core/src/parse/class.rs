@@ -0,0 +1,283 @@
+//! Class diagram statements (`classDiagram`), read into a [`ClassDiagram`]
+//! and projected onto the topology: each class is a node, each relationship
+//! an edge and each `namespace` a subgraph, so `%% @node` directives lay
+//! classes out.
+
+use crate::parse::span::{LineIndex, Span};
+use crate::parse::topology::{TopoEdge, TopoNode, TopoSubgraph, Topology};
+use crate::types::{ClassDiagram, ClassMember, ClassRelation, Classifier, Direction, RelationEnd, Visibility};
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Reads class diagram statements one at a time.
+pub(crate) struct ClassStatements<'a> {
+    index: LineIndex<'a>,
+    diagram: ClassDiagram,
+    /// The class whose `{ ... }` body is being read.
+    open: Option<String>,
+}
+
+impl<'a> ClassStatements<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        Self {
+            index: LineIndex::new(input),
+            diagram: ClassDiagram::default(),
+            open: None,
+        }
+    }
+
+    /// The class whose body is being read.
+    pub(crate) fn owner(&self) -> Option<&str> {
+        self.open.as_deref()
+    }
+
+    /// Apply a class diagram statement; statements it does not know are
+    /// kept as raw lines. `open` holds the namespaces being read.
+    pub(crate) fn statement(&mut self, topology: &mut Topology, statement: &'a str, open: &mut Vec<usize>) {
+        let span = self.index.span_of(statement);
+
+        if let Some(ref name) = self.open {
+            if statement == "}" {
+                self.open = None;
+            } else if let Some(annotation) = parse_annotation(statement) {
+                self.diagram.class_mut(name).annotations.push(annotation.to_string());
+            } else {
+                let member = parse_member(statement);
+                self.diagram.class_mut(name).members.push(member);
+            }
+            return;
+        }
+
+        if statement == "}" {
+            if let Some(current) = open.pop() {
+                topology.subgraphs[current].end_span = Some(span);
+                return;
+            }
+        }
+
+        static NAMESPACE: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&NAMESPACE, r"^namespace\s+([\w.]+)\s*\{$").captures(statement) {
+            let id = caps[1].to_string();
+            let mut subgraph = TopoSubgraph {
+                id: id.clone(),
+                span,
+                ..Default::default()
+            };
+            if let Some(&parent) = open.last() {
+                subgraph.parent = Some(topology.subgraphs[parent].id.clone());
+                topology.subgraphs[parent].subgraphs.push(id);
+            }
+            topology.subgraphs.push(subgraph);
+            open.push(topology.subgraphs.len() - 1);
+            return;
+        }
+
+        if let Some(direction) = statement.strip_prefix("direction ").and_then(Direction::parse) {
+            topology.header.direction = Some(direction);
+            return;
+        }
+
+        static DECLARATION: OnceLock<Regex> = OnceLock::new();
+        let declaration = regex(&DECLARATION, r#"^class\s+(\w+)(?:~([^~]+)~)?(?:\["([^"]*)"\])?\s*(\{\s*\}?)?$"#);
+        if let Some(caps) = declaration.captures(statement) {
+            let name = &caps[1];
+            let class = self.diagram.class_mut(name);
+            if let Some(generic) = caps.get(2) {
+                class.generic = Some(generic.as_str().to_string());
+            }
+            if let Some(label) = caps.get(3) {
+                class.label = Some(label.as_str().to_string());
+            }
+            let label = class.label.clone();
+            if caps.get(4).is_some_and(|body| !body.as_str().ends_with('}')) {
+                self.open = Some(name.to_string());
+            }
+            visit_class(topology, name, label, span, open);
+            return;
+        }
+
+        static ANNOTATION: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&ANNOTATION, r"^<<([^>]+)>>\s*(\w+)$").captures(statement) {
+            let class = self.diagram.class_mut(&caps[2]);
+            class.annotations.push(caps[1].trim().to_string());
+            let label = class.label.clone();
+            visit_class(topology, &caps[2], label, span, open);
+            return;
+        }
+
+        if let Some(relation) = parse_relation(statement) {
+            for name in [&relation.from, &relation.to] {
+                let label = self.diagram.class_mut(name).label.clone();
+                visit_class(topology, name, label, span, &[]);
+            }
+            topology.edges.push(TopoEdge {
+                source: relation.from.clone(),
+                target: relation.to.clone(),
+                label: relation.label.clone(),
+                span,
+                ..Default::default()
+            });
+            self.diagram.relations.push(relation);
+            return;
+        }
+
+        static MEMBER: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&MEMBER, r"^(\w+)\s*:\s*(.+)$").captures(statement) {
+            let class = self.diagram.class_mut(&caps[1]);
+            class.members.push(parse_member(&caps[2]));
+            let label = class.label.clone();
+            visit_class(topology, &caps[1], label, span, &[]);
+            return;
+        }
+
+        topology.keep_raw(statement, span, open);
+    }
+
+    /// Hand the diagram to the topology.
+    pub(crate) fn finish(self, topology: &mut Topology) {
+        topology.class_diagram = Some(self.diagram);
+    }
+}
+
+/// The regex in `cell`, compiled from `pattern` on first use.
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("valid class diagram pattern"))
+}
+
+fn visit_class(topology: &mut Topology, name: &str, label: Option<String>, span: Span, open: &[usize]) {
+    let node = TopoNode {
+        id: name.to_string(),
+        spans: vec![span],
+        ..Default::default()
+    };
+    topology.visit_node(node, open);
+    // The label may be set after the first mention
+    if label.is_some() {
+        if let Some(node) = topology.nodes.iter_mut().find(|n| n.id == name) {
+            node.label = label;
+        }
+    }
+}
+
+/// Parse `<<interface>>`.
+fn parse_annotation(statement: &str) -> Option<&str> {
+    let inner = statement.strip_prefix("<<")?.strip_suffix(">>")?;
+    Some(inner.trim())
+}
+
+/// Parse a member: `+String name`, `-count$`, `+area(r: float)* float`.
+fn parse_member(text: &str) -> ClassMember {
+    let mut text = text.trim();
+    let mut member = ClassMember::default();
+
+    if let Some(visibility) = text.chars().next().and_then(Visibility::from_char) {
+        member.visibility = Some(visibility);
+        text = text[1..].trim_start();
+    }
+
+    if let (Some(open), Some(close)) = (text.find('('), text.rfind(')')) {
+        if open < close {
+            member.name = text[..open].trim().to_string();
+            member.params = Some(text[open + 1..close].trim().to_string());
+            let mut rest = &text[close + 1..];
+            if let Some(classifier) = rest.chars().next().and_then(Classifier::from_char) {
+                member.classifier = Some(classifier);
+                rest = &rest[1..];
+            }
+            let rest = rest.trim();
+            member.type_name = (!rest.is_empty()).then(|| rest.to_string());
+            return member;
+        }
+    }
+
+    if let Some(classifier) = text.chars().last().and_then(Classifier::from_char) {
+        member.classifier = Some(classifier);
+        text = text[..text.len() - 1].trim_end();
+    }
+    match text.rsplit_once(char::is_whitespace) {
+        Some((type_name, name)) => {
+            member.type_name = Some(type_name.trim().to_string());
+            member.name = name.to_string();
+        }
+        None => member.name = text.to_string(),
+    }
+    member
+}
+
+/// Parse `A "1" *-- "many" B : label`.
+fn parse_relation(statement: &str) -> Option<ClassRelation> {
+    static RELATION: OnceLock<Regex> = OnceLock::new();
+    let re = regex(
+        &RELATION,
+        r#"^(\w+)(?:~[^~]+~)?\s*(?:"([^"]*)"\s*)?(<\||\*|o|<|\(\))?(--|\.\.)(\|>|\*|o|>|\(\))?\s*(?:"([^"]*)"\s*)?(\w+)(?:~[^~]+~)?\s*(?::\s*(.*))?$"#,
+    );
+    let caps = re.captures(statement)?;
+
+    Some(ClassRelation {
+        from: caps[1].to_string(),
+        to: caps[7].to_string(),
+        from_end: RelationEnd::parse_left(caps.get(3).map_or("", |m| m.as_str()))?,
+        to_end: RelationEnd::parse_right(caps.get(5).map_or("", |m| m.as_str()))?,
+        dashed: &caps[4] == "..",
+        from_cardinality: caps.get(2).map(|m| m.as_str().to_string()),
+        to_cardinality: caps.get(6).map(|m| m.as_str().to_string()),
+        label: caps.get(8).map(|m| m.as_str().trim().to_string()).filter(|l| !l.is_empty()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_document;
+
+    #[test]
+    fn test_parse_class_diagram() {
+        let input = "classDiagram
+    direction RL
+    class Shape~T~ {
+        <<interface>>
+        +String name
+        -int sides$
+        +area(scale: float)* float
+    }
+    class Circle[\"Round thing\"]
+    Circle : +float radius
+    Shape~T~ <|-- Circle
+    Drawing \"1\" *-- \"many\" Shape : contains
+    Circle ..> Canvas
+    note for Circle \"keep it round\"
+
+%% @node: Circle {\"uid\":\"n_circle\",\"x\":200,\"y\":40}";
+
+        let parsed = parse_document(input).unwrap();
+        let diagram = parsed.class_diagram.as_ref().unwrap();
+
+        let shape = diagram.class("Shape").unwrap();
+        assert_eq!(shape.generic.as_deref(), Some("T"));
+        assert_eq!(shape.annotations, ["interface"]);
+        assert_eq!(shape.members[1].visibility, Some(Visibility::Private));
+        assert_eq!(shape.members[1].classifier, Some(Classifier::Static));
+        let area = &shape.members[2];
+        assert!(area.is_method());
+        assert_eq!((area.name.as_str(), area.params.as_deref()), ("area", Some("scale: float")));
+        assert_eq!((area.classifier, area.type_name.as_deref()), (Some(Classifier::Abstract), Some("float")));
+
+        let contains = &diagram.relations[1];
+        assert_eq!((contains.from.as_str(), contains.to.as_str()), ("Drawing", "Shape"));
+        assert_eq!(contains.from_end, RelationEnd::Composition);
+        assert_eq!(contains.to_cardinality.as_deref(), Some("many"));
+        assert_eq!(contains.label.as_deref(), Some("contains"));
+        assert!(diagram.relations[2].dashed);
+        assert_eq!(diagram.relations[2].to_end, RelationEnd::Arrow);
+
+        let ids: Vec<_> = parsed.nodes.iter().map(|n| n.mermaid_id.as_str()).collect();
+        assert_eq!(ids, ["Shape", "Circle", "Drawing", "Canvas"]);
+        assert_eq!(parsed.nodes[1].uid.0, "n_circle");
+        assert_eq!(parsed.nodes[1].label.as_deref(), Some("Round thing"));
+        assert_eq!(parsed.edges.len(), 3);
+        assert_eq!(parsed.header.direction, Some(Direction::RightLeft));
+
+        // Notes are kept as written
+        assert_eq!(parsed.raw_lines[0].text, "note for Circle \"keep it round\"");
+    }
+}
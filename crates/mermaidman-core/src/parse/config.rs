@@ -0,0 +1,204 @@
+//! Mermaid configuration: YAML frontmatter and `%%{init: ...}%%` directives.
+
+use crate::types::{DocumentConfig, FlowchartConfig};
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+use std::sync::OnceLock;
+
+/// A `---` block at the start of a document.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frontmatter {
+    pub title: Option<String>,
+    /// The `config` mapping.
+    pub config: Mapping,
+}
+
+/// Length of the `---` block that opens `input`, up to the end of its
+/// closing `---` line; `None` if there is none or it is never closed.
+pub fn frontmatter_len(input: &str) -> Option<usize> {
+    let mut lines = input.split_inclusive('\n');
+    let first = lines.next()?;
+    if first.trim_end() != "---" {
+        return None;
+    }
+
+    let mut offset = first.len();
+    for line in lines {
+        if line.trim_end() == "---" {
+            return Some(offset + line.trim_end().len());
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Parse a frontmatter block, fences included. Keys other than `title` and
+/// `config` are ignored.
+pub fn parse_frontmatter(block: &str) -> Result<Frontmatter, String> {
+    let mut frontmatter = Frontmatter::default();
+    for (key, value) in frontmatter_mapping(block)? {
+        match (key.as_str(), value) {
+            (Some("title"), Value::String(title)) => frontmatter.title = Some(title),
+            (Some("title"), Value::Number(title)) => frontmatter.title = Some(title.to_string()),
+            (Some("config"), Value::Mapping(config)) => frontmatter.config = config,
+            _ => {}
+        }
+    }
+    Ok(frontmatter)
+}
+
+/// The YAML mapping of a frontmatter block, fences included.
+pub fn frontmatter_mapping(block: &str) -> Result<Mapping, String> {
+    let body = block.trim().strip_prefix("---").unwrap_or(block);
+    let body = body.strip_suffix("---").unwrap_or(body);
+
+    match serde_yaml::from_str(body).map_err(|e| e.to_string())? {
+        Value::Mapping(mapping) => Ok(mapping),
+        Value::Null => Ok(Mapping::new()),
+        _ => Err("Frontmatter is not a YAML mapping".to_string()),
+    }
+}
+
+/// Parse a `%%{init: {...}}%%` (or `%%{initialize: ...}%%`) line into its
+/// config mapping. `None` if the line is not one; an error if its body is
+/// not a mapping. Mermaid's single-quoted JSON is accepted.
+pub fn parse_init_directive(line: &str) -> Option<Result<Mapping, String>> {
+    let line = line.trim();
+    if !line.starts_with("%%{") {
+        return None;
+    }
+    static INIT: OnceLock<Regex> = OnceLock::new();
+    let re = INIT.get_or_init(|| Regex::new(r"^%%\{\s*(?:init|initialize)\s*:\s*(.*)\}%%$").expect("valid init pattern"));
+    let body = re.captures(line)?.get(1)?.as_str();
+
+    Some(match serde_yaml::from_str(body) {
+        Ok(Value::Mapping(mapping)) => Ok(mapping),
+        Ok(_) => Err("The init directive's config is not a mapping".to_string()),
+        Err(e) => Err(e.to_string()),
+    })
+}
+
+/// The document's config: the frontmatter's, overridden by each init
+/// directive in turn, as Mermaid applies them.
+///
+/// Also returns the keys whose values do not fit their typed field; they are
+/// left out of the config.
+pub fn document_config(
+    frontmatter: Option<&Frontmatter>,
+    init: impl IntoIterator<Item = Mapping>,
+) -> (DocumentConfig, Vec<String>) {
+    let mut mapping = frontmatter.map(|f| f.config.clone()).unwrap_or_default();
+    for over in init {
+        merge(&mut mapping, over);
+    }
+
+    let title = match mapping.shift_remove("title") {
+        Some(Value::String(title)) => Some(title),
+        _ => None,
+    };
+    let mut rejected = Vec::new();
+    let flowchart = match mapping.shift_remove("flowchart") {
+        Some(Value::Mapping(options)) => Some(lenient::<FlowchartConfig>(options, "flowchart.", &mut rejected)),
+        Some(other) => {
+            mapping.insert("flowchart".into(), other);
+            None
+        }
+        None => None,
+    };
+
+    let mut config = lenient::<DocumentConfig>(mapping, "", &mut rejected);
+    config.title = frontmatter.and_then(|f| f.title.clone()).or(title);
+    config.flowchart = flowchart;
+    (config, rejected)
+}
+
+/// Merge `over` into `base`; nested mappings merge key by key.
+fn merge(base: &mut Mapping, over: Mapping) {
+    for (key, value) in over {
+        if let Value::Mapping(value) = value {
+            if let Some(Value::Mapping(inner)) = base.get_mut(&key) {
+                merge(inner, value);
+                continue;
+            }
+            base.insert(key, Value::Mapping(value));
+        } else {
+            base.insert(key, value);
+        }
+    }
+}
+
+/// Deserialize `mapping` into `T`, leaving out (and naming in `rejected`)
+/// entries whose value does not fit.
+fn lenient<T: DeserializeOwned + Default>(mapping: Mapping, prefix: &str, rejected: &mut Vec<String>) -> T {
+    let mut fits = Mapping::new();
+    for (key, value) in mapping {
+        let single: Mapping = [(key.clone(), value.clone())].into_iter().collect();
+        if serde_yaml::from_value::<T>(Value::Mapping(single)).is_ok() {
+            fits.insert(key, value);
+        } else {
+            rejected.push(format!("{}{}", prefix, key.as_str().unwrap_or("?")));
+        }
+    }
+    serde_yaml::from_value(Value::Mapping(fits)).unwrap_or_default()
+}
+
+/// The Mermaid config keys of `config` (everything but the title), in the
+/// order they are written.
+pub fn config_mapping(config: &DocumentConfig) -> Mapping {
+    let mut mapping = match serde_yaml::to_value(config) {
+        Ok(Value::Mapping(mapping)) => mapping,
+        _ => Mapping::new(),
+    };
+    mapping.shift_remove("title");
+    mapping
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_frontmatter_and_init() {
+        let input = "---\ntitle: Release flow\nconfig:\n  theme: dark\n  themeVariables:\n    primaryColor: \"#ff0000\"\n  flowchart:\n    curve: basis\n    nodeSpacing: 40\n---\ngraph TD\nA --> B\n";
+        let len = frontmatter_len(input).unwrap();
+        assert!(input[len..].starts_with("\ngraph TD"));
+
+        let frontmatter = parse_frontmatter(&input[..len]).unwrap();
+        assert_eq!(frontmatter.title.as_deref(), Some("Release flow"));
+
+        let init = parse_init_directive("%%{init: {'theme':'forest', 'flowchart': {'rankSpacing': 80}}}%%")
+            .unwrap()
+            .unwrap();
+        let (config, rejected) = document_config(Some(&frontmatter), [init]);
+        assert!(rejected.is_empty());
+        assert_eq!(config.title.as_deref(), Some("Release flow"));
+        assert_eq!(config.theme.as_deref(), Some("forest"));
+        assert_eq!(config.theme_variables["primaryColor"], "#ff0000");
+        let flowchart = config.flowchart.unwrap();
+        assert_eq!(flowchart.curve.as_deref(), Some("basis"));
+        assert_eq!((flowchart.node_spacing, flowchart.rank_spacing), (Some(40), Some(80)));
+
+        assert!(parse_init_directive("%% plain comment").is_none());
+        assert!(parse_init_directive("%%{init: [1, 2]}%%").unwrap().is_err());
+        assert_eq!(frontmatter_len("---\ntitle: open\n"), None);
+    }
+
+    #[test]
+    fn test_config_keeps_unknown_keys_and_rejects_bad_values() {
+        let init = parse_init_directive(r#"%%{init: {"theme": 3, "fontFamily": "Inter", "flowchart": {"curve": "step", "nodeSpacing": "wide", "arrowMarkerAbsolute": true}}}%%"#)
+            .unwrap()
+            .unwrap();
+        let (config, rejected) = document_config(None, [init]);
+
+        assert_eq!(rejected, vec!["flowchart.nodeSpacing", "theme"]);
+        assert_eq!(config.theme, None);
+        assert_eq!(config.extra["fontFamily"], "Inter");
+        let flowchart = config.flowchart.as_ref().unwrap();
+        assert_eq!(flowchart.curve.as_deref(), Some("step"));
+        assert_eq!(flowchart.extra["arrowMarkerAbsolute"], true);
+
+        let keys: Vec<_> = config_mapping(&config).keys().map(|k| k.as_str().unwrap().to_string()).collect();
+        assert_eq!(keys, ["flowchart", "fontFamily"]);
+    }
+}
@@ -1,27 +1,73 @@
 //! Directive parsing for Mermaidman comments.
 
+use crate::migrate::{migrate_directive, DirectiveKind, SCHEMA_VERSION};
+use crate::types::{
+    AiRecord, AiReview, BlobId, CodeMeta, DiagramMeta, EdgeStyle, MediaMeta, NodeStyle,
+};
 use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::sync::OnceLock;
+
+/// The IDs a `%% @node` directive can name: every ID the topology parsers
+/// accept, including the dots of state names and the hyphens of ER entities.
+const NODE_ID: &str = r"[\w.\-]+";
+
+/// Compile one of the directive patterns, which are kept for the process.
+fn compile(pattern: &str) -> Regex {
+    Regex::new(pattern).expect("valid directive pattern")
+}
 
 /// Parsed node directive.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeDirective {
     pub id: String,
     pub uid: Option<String>,
-    pub x: Option<i32>,
-    pub y: Option<i32>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
     pub kind: Option<String>,
+    pub style: Option<NodeStyle>,
+    pub code: Option<CodeMeta>,
+    pub media: Option<MediaMeta>,
+    pub diagram: Option<DiagramMeta>,
+    pub markdown: Option<String>,
+    /// The `meta` object plus any keys that have no typed field.
     pub meta: Option<Value>,
 }
 
 /// Parsed edge directive.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EdgeDirective {
+    /// The token after `@edge:`: the Mermaid edge ID, or a handle for edges without one.
+    pub id: String,
     pub eid: Option<String>,
+    /// Source node UID (hand-written directives may use the Mermaid ID).
     pub source: Option<String>,
+    /// Target node UID (hand-written directives may use the Mermaid ID).
     pub target: Option<String>,
     pub label: Option<String>,
+    pub style: Option<EdgeStyle>,
+    /// The `meta` object plus any keys that have no typed field.
+    pub meta: Option<Value>,
+}
+
+/// Parsed participant directive (sequence diagrams).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParticipantDirective {
+    /// The participant's Mermaid ID.
+    pub id: String,
+    pub uid: Option<String>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    /// The `meta` object plus any keys that have no typed field.
+    pub meta: Option<Value>,
+}
+
+/// Parsed `%% @mermaidman` line: the directive schema version of the document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaDirective {
+    pub version: u32,
     pub meta: Option<Value>,
 }
 
@@ -32,88 +78,325 @@ pub struct AiDirective {
     pub action: String,
     pub provider: Option<String>,
     pub timestamp: Option<u64>,
+    pub status: AiReview,
+    pub before_hash: Option<BlobId>,
+    pub after_hash: Option<BlobId>,
+    /// The `meta` object plus any keys that have no typed field.
     pub meta: Option<Value>,
 }
 
+impl From<AiDirective> for AiRecord {
+    fn from(d: AiDirective) -> Self {
+        Self {
+            action: d.action,
+            provider: d.provider,
+            timestamp: d.timestamp,
+            status: d.status,
+            before_hash: d.before_hash,
+            after_hash: d.after_hash,
+            meta: d.meta,
+        }
+    }
+}
+
 /// Parse a node directive line.
 /// Format: `%% @node: ID {"uid":"n_xxx","x":100,"y":50,...}`
 pub fn parse_node_directive(line: &str) -> Option<NodeDirective> {
-    let re = Regex::new(r"^%%\s*@node:\s*([A-Za-z0-9_]+)\s*(\{.*\})\s*$").ok()?;
+    parse_node_directive_with(line, SCHEMA_VERSION)
+}
+
+/// Parse a node directive written with schema `version`, upgrading its body first.
+pub fn parse_node_directive_with(line: &str, version: u32) -> Option<NodeDirective> {
+    static NODE: OnceLock<Regex> = OnceLock::new();
+    let re = NODE.get_or_init(|| compile(&format!(r"^%%\s*@node:\s*({})\s*(\{{.*\}})\s*$", NODE_ID)));
     let caps = re.captures(line)?;
     
     let id = caps.get(1)?.as_str().to_string();
     let body = caps.get(2)?.as_str();
 
-    let value: Value = serde_json::from_str(body).ok()?;
-    
-    let uid = value.get("uid").and_then(|v| v.as_str()).map(String::from);
-    let x = value.get("x").and_then(json_to_i32);
-    let y = value.get("y").and_then(json_to_i32);
-    let kind = value.get("kind").and_then(|v| v.as_str()).map(String::from);
+    let mut value: Value = serde_json::from_str(body).ok()?;
+    migrate_directive(DirectiveKind::Node, &id, &mut value, version);
+    let Value::Object(mut body) = value else {
+        return None;
+    };
 
     Some(NodeDirective {
         id,
-        uid,
-        x,
-        y,
-        kind,
-        meta: Some(value),
+        uid: take(&mut body, "uid"),
+        x: take(&mut body, "x"),
+        y: take(&mut body, "y"),
+        kind: take(&mut body, "kind"),
+        style: take(&mut body, "style"),
+        code: take(&mut body, "code"),
+        media: take(&mut body, "media"),
+        diagram: take(&mut body, "diagram"),
+        markdown: take(&mut body, "markdown"),
+        meta: leftover_meta(body),
     })
 }
 
 /// Parse an edge directive line.
-/// Format: `%% @edge: ID {"eid":"e_xxx","source":"n_1","target":"n_2",...}`
+/// Format: `%% @edge: ID {"eid":"e_xxx","source":"n_1","target":"n_2",...}`,
+/// where `source` and `target` are node UIDs.
 pub fn parse_edge_directive(line: &str) -> Option<EdgeDirective> {
-    let re = Regex::new(r"^%%\s*@edge:\s*([A-Za-z0-9_]+)\s*(\{.*\})\s*$").ok()?;
+    parse_edge_directive_with(line, SCHEMA_VERSION)
+}
+
+/// Parse an edge directive written with schema `version`, upgrading its body first.
+pub fn parse_edge_directive_with(line: &str, version: u32) -> Option<EdgeDirective> {
+    static EDGE: OnceLock<Regex> = OnceLock::new();
+    let re = EDGE.get_or_init(|| compile(r"^%%\s*@edge:\s*([A-Za-z0-9_]+)\s*(\{.*\})\s*$"));
     let caps = re.captures(line)?;
     
-    let eid = caps.get(1).map(|m| m.as_str().to_string());
+    let id = caps.get(1)?.as_str().to_string();
     let body = caps.get(2)?.as_str();
 
-    let value: Value = serde_json::from_str(body).ok()?;
-    
-    let source = value.get("source").and_then(|v| v.as_str()).map(String::from);
-    let target = value.get("target").and_then(|v| v.as_str()).map(String::from);
-    let label = value.get("label").and_then(|v| v.as_str()).map(String::from);
+    let mut value: Value = serde_json::from_str(body).ok()?;
+    migrate_directive(DirectiveKind::Edge, &id, &mut value, version);
+    let Value::Object(mut body) = value else {
+        return None;
+    };
 
     Some(EdgeDirective {
-        eid,
-        source,
-        target,
-        label,
-        meta: Some(value),
+        eid: take(&mut body, "eid"),
+        id,
+        source: take(&mut body, "source"),
+        target: take(&mut body, "target"),
+        label: take(&mut body, "label"),
+        style: take(&mut body, "style"),
+        meta: leftover_meta(body),
     })
 }
 
 /// Parse an AI directive line.
 /// Format: `%% @ai: UID {"action":"summarize","provider":"gemini",...}`
 pub fn parse_ai_directive(line: &str) -> Option<AiDirective> {
-    let re = Regex::new(r"^%%\s*@ai:\s*([A-Za-z0-9_]+)\s*(\{.*\})\s*$").ok()?;
+    parse_ai_directive_with(line, SCHEMA_VERSION)
+}
+
+/// Parse an AI directive written with schema `version`, upgrading its body first.
+pub fn parse_ai_directive_with(line: &str, version: u32) -> Option<AiDirective> {
+    static AI: OnceLock<Regex> = OnceLock::new();
+    let re = AI.get_or_init(|| compile(r"^%%\s*@ai:\s*([A-Za-z0-9_]+)\s*(\{.*\})\s*$"));
     let caps = re.captures(line)?;
     
     let target_uid = caps.get(1)?.as_str().to_string();
     let body = caps.get(2)?.as_str();
 
-    let value: Value = serde_json::from_str(body).ok()?;
-    
-    let action = value.get("action").and_then(|v| v.as_str())?.to_string();
-    let provider = value.get("provider").and_then(|v| v.as_str()).map(String::from);
-    let timestamp = value.get("timestamp").and_then(|v| v.as_u64());
+    let mut value: Value = serde_json::from_str(body).ok()?;
+    migrate_directive(DirectiveKind::Ai, &target_uid, &mut value, version);
+    let Value::Object(mut body) = value else {
+        return None;
+    };
 
     Some(AiDirective {
         target_uid,
-        action,
-        provider,
-        timestamp,
+        action: take(&mut body, "action")?,
+        provider: take(&mut body, "provider"),
+        timestamp: take(&mut body, "timestamp"),
+        status: take(&mut body, "status").unwrap_or_default(),
+        before_hash: take(&mut body, "before_hash"),
+        after_hash: take(&mut body, "after_hash"),
+        meta: leftover_meta(body),
+    })
+}
+
+/// Parse a participant directive line.
+/// Format: `%% @participant: Alice {"uid":"n_xxx","x":100,"y":0}`
+pub fn parse_participant_directive(line: &str) -> Option<ParticipantDirective> {
+    static PARTICIPANT: OnceLock<Regex> = OnceLock::new();
+    let re = PARTICIPANT.get_or_init(|| compile(r"^%%\s*@participant:\s*([^\s{]+)\s*(\{.*\})\s*$"));
+    let caps = re.captures(line)?;
+
+    let id = caps.get(1)?.as_str().to_string();
+    let Value::Object(mut body) = serde_json::from_str(caps.get(2)?.as_str()).ok()? else {
+        return None;
+    };
+
+    Some(ParticipantDirective {
+        id,
+        uid: take(&mut body, "uid"),
+        x: take(&mut body, "x"),
+        y: take(&mut body, "y"),
+        meta: leftover_meta(body),
+    })
+}
+
+/// Parse the document's schema line.
+/// Format: `%% @mermaidman: {"version":2}`
+pub fn parse_schema_directive(line: &str) -> Option<SchemaDirective> {
+    static SCHEMA: OnceLock<Regex> = OnceLock::new();
+    let re = SCHEMA.get_or_init(|| compile(r"^%%\s*@mermaidman:\s*(\{.*\})\s*$"));
+    let caps = re.captures(line)?;
+    
+    let value: Value = serde_json::from_str(caps.get(1)?.as_str()).ok()?;
+    let version = value.get("version").and_then(|v| v.as_u64())?;
+
+    Some(SchemaDirective {
+        version: u32::try_from(version).ok().filter(|&v| v > 0)?,
         meta: Some(value),
     })
 }
 
-fn json_to_i32(value: &Value) -> Option<i32> {
-    value
-        .as_i64()
-        .map(|v| v as i32)
-        .or_else(|| value.as_f64().map(|v| v.round() as i32))
+/// Explain why a `%% @node`, `%% @edge`, `%% @ai`, `%% @participant` or `%% @mermaidman` line
+/// does not parse.
+///
+/// Returns `None` for valid directives and for lines that are not directives.
+pub fn directive_error(line: &str) -> Option<String> {
+    static SCHEMA_LIKE: OnceLock<Regex> = OnceLock::new();
+    let schema = SCHEMA_LIKE.get_or_init(|| compile(r"^%%\s*@mermaidman\b:?\s*(.*)$"));
+    if let Some(caps) = schema.captures(line) {
+        if parse_schema_directive(line).is_some() {
+            return None;
+        }
+        return match serde_json::from_str::<Value>(caps.get(1)?.as_str()) {
+            Ok(Value::Object(_)) => Some("expected a positive integer `version`".to_string()),
+            _ => Some("expected `%% @mermaidman: {\"version\":N}`".to_string()),
+        };
+    }
+
+    static DIRECTIVE_LIKE: OnceLock<Regex> = OnceLock::new();
+    let re = DIRECTIVE_LIKE.get_or_init(|| compile(r"^%%\s*@(node|edge|ai|participant)\b:?\s*(.*)$"));
+    let caps = re.captures(line)?;
+    let kind = caps.get(1)?.as_str();
+
+    let valid = match kind {
+        "node" => parse_node_directive(line).is_some(),
+        "edge" => parse_edge_directive(line).is_some(),
+        "participant" => parse_participant_directive(line).is_some(),
+        _ => parse_ai_directive(line).is_some(),
+    };
+    if valid {
+        return None;
+    }
+
+    static SHAPE: OnceLock<Regex> = OnceLock::new();
+    let shape = SHAPE.get_or_init(|| compile(&format!(r"^({})\s*(\{{.*\}})\s*$", NODE_ID)));
+    let Some(body) = shape.captures(caps.get(2)?.as_str()).and_then(|c| c.get(2)) else {
+        return Some(format!("expected `%% @{}: ID {{...}}`", kind));
+    };
+
+    match serde_json::from_str::<Value>(body.as_str()) {
+        Err(e) => Some(format!("invalid JSON: {}", e)),
+        Ok(_) if kind == "ai" => Some("missing `action`".to_string()),
+        Ok(_) => Some("directive body must be a JSON object".to_string()),
+    }
+}
+
+/// A directive whose JSON body continues over the following `%%` lines:
+///
+/// ```text
+/// %% @node: A {
+/// %%   "uid": "n_1",
+/// %%   "x": 120
+/// %% }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct DirectiveBlock {
+    /// The directive joined onto one line.
+    text: String,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl DirectiveBlock {
+    /// Open a block if `line` starts a directive whose JSON object is not closed on it.
+    pub(crate) fn start(line: &str) -> Option<Self> {
+        static OPENING: OnceLock<Regex> = OnceLock::new();
+        let re = OPENING.get_or_init(|| compile(&format!(r"^%%\s*@(node|edge|ai):\s*{}\s*\{{", NODE_ID)));
+        re.find(line)?;
+
+        let mut block = Self {
+            text: String::new(),
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        };
+        let body = &line[line.find('{')?..];
+        block.text.push_str(&line[..line.len() - body.len()]);
+        (!block.scan(body)).then_some(block)
+    }
+
+    /// Add a continuation line; returns `true` once the JSON object is closed.
+    ///
+    /// Returns `false` without consuming `line` if it is not a `%%` line.
+    pub(crate) fn push(&mut self, line: &str) -> bool {
+        match line.trim().strip_prefix("%%") {
+            Some(rest) => self.scan(rest.trim()),
+            None => false,
+        }
+    }
+
+    /// The directive as a single line, for the single-line parsers.
+    pub(crate) fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Append JSON text, tracking nesting; `true` when the outer object closes.
+    fn scan(&mut self, json: &str) -> bool {
+        for c in json.chars() {
+            self.text.push(c);
+            if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => self.in_string = true,
+                '{' | '[' => self.depth += 1,
+                '}' | ']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+/// Remove `key` from a directive body as a typed value.
+///
+/// A value of the wrong shape stays in the body, so it is kept in `meta`;
+/// `null` is the same as a missing key.
+fn take<T: DeserializeOwned>(body: &mut Map<String, Value>, key: &str) -> Option<T> {
+    let value = body.remove(key).filter(|v| !v.is_null())?;
+    match T::deserialize(&value) {
+        Ok(typed) => Some(typed),
+        Err(_) => {
+            body.insert(key.to_string(), value);
+            None
+        }
+    }
+}
+
+/// What is left of a directive body once its typed keys are taken: the
+/// `meta` object, with unknown keys merged in.
+fn leftover_meta(mut body: Map<String, Value>) -> Option<Value> {
+    let meta = body.remove("meta");
+    if body.is_empty() {
+        return meta;
+    }
+    match meta {
+        Some(Value::Object(mut meta)) => {
+            for (key, value) in body {
+                meta.entry(key).or_insert(value);
+            }
+            Some(Value::Object(meta))
+        }
+        Some(other) => {
+            body.insert("meta".to_string(), other);
+            Some(Value::Object(body))
+        }
+        None => Some(Value::Object(body)),
+    }
 }
 
 #[cfg(test)]
@@ -127,20 +410,40 @@ mod tests {
         
         assert_eq!(d.id, "A");
         assert_eq!(d.uid, Some("n_001".to_string()));
-        assert_eq!(d.x, Some(100));
-        assert_eq!(d.y, Some(50));
+        assert_eq!(d.x, Some(100.0));
+        assert_eq!(d.y, Some(50.0));
         assert_eq!(d.kind, Some("card".to_string()));
     }
 
+    #[test]
+    fn test_node_directive_unknown_keys_go_to_meta() {
+        let line = r#"%% @node: A {"uid":"n_1","code":{"language":"rust"},"media":{"alt":"no src"},"tags":["x"],"meta":{"owner":"ops"}}"#;
+        let d = parse_node_directive(line).unwrap();
+
+        assert_eq!(d.code.unwrap().language.as_deref(), Some("rust"));
+        // `media` without `src` does not fit the typed field, so it is kept as is
+        assert!(d.media.is_none());
+        assert_eq!(
+            d.meta,
+            Some(serde_json::json!({"owner": "ops", "media": {"alt": "no src"}, "tags": ["x"]}))
+        );
+    }
+
     #[test]
     fn test_parse_edge_directive() {
         let line = r#"%% @edge: e1 {"eid":"e_001","source":"n_1","target":"n_2","label":"next"}"#;
         let d = parse_edge_directive(line).unwrap();
         
-        assert_eq!(d.eid, Some("e1".to_string()));
+        assert_eq!(d.id, "e1");
+        assert_eq!(d.eid, Some("e_001".to_string()));
         assert_eq!(d.source, Some("n_1".to_string()));
         assert_eq!(d.target, Some("n_2".to_string()));
         assert_eq!(d.label, Some("next".to_string()));
+
+        // Before schema 3 the token was the EID
+        let d = parse_edge_directive_with(line, 2).unwrap();
+        assert_eq!(d.eid, Some("e1".to_string()));
+        assert_eq!(d.meta, None);
     }
 
     #[test]
@@ -151,5 +454,69 @@ mod tests {
         assert_eq!(d.target_uid, "n_001");
         assert_eq!(d.action, "summarize");
         assert_eq!(d.provider, Some("gemini".to_string()));
+
+        let line = r#"%% @ai: n_001 {"action":"expand","status":"accepted","after_hash":"abc","model":"2.0"}"#;
+        let d = parse_ai_directive(line).unwrap();
+        assert_eq!(d.status, AiReview::Accepted);
+        assert_eq!(d.after_hash, Some(BlobId("abc".to_string())));
+        assert_eq!(d.meta, Some(serde_json::json!({"model": "2.0"})));
+    }
+
+    #[test]
+    fn test_parse_schema_directive() {
+        let d = parse_schema_directive(r#"%% @mermaidman: {"version":2}"#).unwrap();
+        assert_eq!(d.version, 2);
+
+        assert!(parse_schema_directive(r#"%% @mermaidman: {"version":0}"#).is_none());
+        assert_eq!(
+            directive_error(r#"%% @mermaidman: {"version":"2"}"#),
+            Some("expected a positive integer `version`".to_string())
+        );
+        assert_eq!(
+            directive_error("%% @mermaidman: 2"),
+            Some("expected `%% @mermaidman: {\"version\":N}`".to_string())
+        );
+    }
+
+    #[test]
+    fn test_directive_error() {
+        assert_eq!(directive_error(r#"%% @node: A {"uid":"n_001"}"#), None);
+        assert_eq!(directive_error("%% just a comment"), None);
+        assert_eq!(
+            directive_error("%% @node A"),
+            Some("expected `%% @node: ID {...}`".to_string())
+        );
+        assert!(directive_error(r#"%% @edge: e1 {"eid":}"#)
+            .unwrap()
+            .starts_with("invalid JSON"));
+        assert_eq!(
+            directive_error(r#"%% @ai: n_001 {"provider":"gemini"}"#),
+            Some("missing `action`".to_string())
+        );
+    }
+
+    #[test]
+    fn test_directive_block() {
+        let lines = [
+            "%% @node: A {",
+            r#"%%   "uid":"n_1","#,
+            r#"%%   "diagram":{"title":"Sub {flow}","mermaidman":"graph TD\nX-->Y\n%% @node: X {\"x\":50}\n"}"#,
+            "%% }",
+        ];
+
+        let mut block = DirectiveBlock::start(lines[0]).unwrap();
+        assert!(!block.push(lines[1]));
+        assert!(!block.push(lines[2]));
+        assert!(block.push(lines[3]));
+
+        let directive = parse_node_directive(block.text()).unwrap();
+        assert_eq!(directive.uid, Some("n_1".to_string()));
+        let diagram = directive.diagram.unwrap();
+        assert_eq!(diagram.title.as_deref(), Some("Sub {flow}"));
+
+        // Single-line directives and comments are not blocks
+        assert!(DirectiveBlock::start(r#"%% @node: A {"uid":"n_1"}"#).is_none());
+        assert!(DirectiveBlock::start("%% a comment {").is_none());
+        assert!(!DirectiveBlock::start(lines[0]).unwrap().push("A --> B"));
     }
 }
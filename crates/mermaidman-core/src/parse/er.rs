@@ -0,0 +1,195 @@
+//! Entity relationship diagram statements (`erDiagram`), read into an
+//! [`ErDiagram`] and projected onto the topology: each entity is a node and
+//! each relationship an edge, so `%% @node` directives lay entities out.
+
+use crate::parse::span::{LineIndex, Span};
+use crate::parse::topology::{TopoEdge, TopoNode, Topology};
+use crate::types::{AttributeKey, Cardinality, Direction, ErAttribute, ErDiagram, ErRelationship};
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Reads ER diagram statements one at a time.
+pub(crate) struct ErStatements<'a> {
+    index: LineIndex<'a>,
+    diagram: ErDiagram,
+    /// The entity whose `{ ... }` body is being read.
+    open: Option<String>,
+}
+
+impl<'a> ErStatements<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        Self {
+            index: LineIndex::new(input),
+            diagram: ErDiagram::default(),
+            open: None,
+        }
+    }
+
+    /// The entity whose body is being read.
+    pub(crate) fn owner(&self) -> Option<&str> {
+        self.open.as_deref()
+    }
+
+    /// Apply an ER diagram statement; statements it does not know are kept
+    /// as raw lines, in the body of the entity being read if any.
+    pub(crate) fn statement(&mut self, topology: &mut Topology, statement: &'a str) {
+        let span = self.index.span_of(statement);
+
+        if let Some(ref name) = self.open {
+            if statement == "}" {
+                self.open = None;
+                return;
+            }
+            if let Some(attribute) = parse_attribute(statement) {
+                self.diagram.entity_mut(name).attributes.push(attribute);
+                return;
+            }
+            topology.keep_raw_in(statement, span, &[], Some(name));
+            return;
+        }
+
+        if let Some(direction) = statement.strip_prefix("direction ").and_then(Direction::parse) {
+            topology.header.direction = Some(direction);
+            return;
+        }
+
+        static RELATIONSHIP: OnceLock<Regex> = OnceLock::new();
+        let relationship = regex(
+            &RELATIONSHIP,
+            r"^([\w\-]+)\s*(\|o|\|\||\}o|\}\|)(--|\.\.)(o\||\|\||o\{|\|\{)\s*([\w\-]+)\s*:\s*(.*)$",
+        );
+        if let Some(caps) = relationship.captures(statement) {
+            let label = caps[6].trim();
+            let label = label.strip_prefix('"').and_then(|l| l.strip_suffix('"')).unwrap_or(label);
+            let relationship = ErRelationship {
+                from: caps[1].to_string(),
+                to: caps[5].to_string(),
+                from_cardinality: Cardinality::parse_left(&caps[2]).expect("matched a left symbol"),
+                to_cardinality: Cardinality::parse_right(&caps[4]).expect("matched a right symbol"),
+                identifying: &caps[3] == "--",
+                label: label.to_string(),
+            };
+            for name in [&relationship.from, &relationship.to] {
+                let alias = self.diagram.entity_mut(name).alias.clone();
+                visit_entity(topology, name, alias, span);
+            }
+            topology.edges.push(TopoEdge {
+                source: relationship.from.clone(),
+                target: relationship.to.clone(),
+                label: Some(relationship.label.clone()).filter(|l| !l.is_empty()),
+                span,
+                ..Default::default()
+            });
+            self.diagram.relationships.push(relationship);
+            return;
+        }
+
+        static ENTITY: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&ENTITY, r"^([\w\-]+)(?:\[([^\]]*)\])?\s*(\{\s*\}?)?$").captures(statement) {
+            let name = &caps[1];
+            let entity = self.diagram.entity_mut(name);
+            if let Some(alias) = caps.get(2) {
+                entity.alias = Some(alias.as_str().trim().to_string());
+            }
+            let alias = entity.alias.clone();
+            if caps.get(3).is_some_and(|body| !body.as_str().ends_with('}')) {
+                self.open = Some(name.to_string());
+            }
+            visit_entity(topology, name, alias, span);
+            return;
+        }
+
+        topology.keep_raw(statement, span, &[]);
+    }
+
+    /// Hand the diagram to the topology.
+    pub(crate) fn finish(self, topology: &mut Topology) {
+        topology.er_diagram = Some(self.diagram);
+    }
+}
+
+/// The regex in `cell`, compiled from `pattern` on first use.
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("valid ER diagram pattern"))
+}
+
+fn visit_entity(topology: &mut Topology, name: &str, alias: Option<String>, span: Span) {
+    let node = TopoNode {
+        id: name.to_string(),
+        spans: vec![span],
+        ..Default::default()
+    };
+    topology.visit_node(node, &[]);
+    // The alias may be set after the first mention
+    if alias.is_some() {
+        if let Some(node) = topology.nodes.iter_mut().find(|n| n.id == name) {
+            node.label = alias;
+        }
+    }
+}
+
+/// Parse `type name [PK, FK] ["comment"]`.
+fn parse_attribute(statement: &str) -> Option<ErAttribute> {
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    let re = regex(&ATTRIBUTE, r#"^([\w\-\[\](),~]+)\s+(\*?[\w\-\[\]]+)((?:\s*,?\s*(?:PK|FK|UK))*)\s*(?:"([^"]*)")?$"#);
+    let caps = re.captures(statement)?;
+
+    Some(ErAttribute {
+        type_name: caps[1].to_string(),
+        name: caps[2].to_string(),
+        keys: caps[3]
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(AttributeKey::parse)
+            .collect(),
+        comment: caps.get(4).map(|m| m.as_str().to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_document;
+
+    #[test]
+    fn test_parse_er_diagram() {
+        let input = "erDiagram
+    CUSTOMER[Customer] {
+        string email PK, UK \"login\"
+        varchar(64) name
+    }
+    CUSTOMER ||--o{ ORDER : places
+    ORDER }|..|{ LINE-ITEM : \"is part of\"
+    ORDER {
+        int id PK
+        int customer_id FK
+    }
+    SHIPMENT
+
+%% @node: ORDER {\"uid\":\"n_order\",\"x\":300,\"y\":0}";
+
+        let parsed = parse_document(input).unwrap();
+        assert!(parsed.diagnostics.is_empty(), "{:?}", parsed.diagnostics);
+        let diagram = parsed.er_diagram.as_ref().unwrap();
+
+        let customer = diagram.entity("CUSTOMER").unwrap();
+        assert_eq!(customer.alias.as_deref(), Some("Customer"));
+        assert_eq!(customer.attributes[0].keys, [AttributeKey::Primary, AttributeKey::Unique]);
+        assert_eq!(customer.attributes[0].comment.as_deref(), Some("login"));
+        assert_eq!(customer.attributes[1].type_name, "varchar(64)");
+        assert_eq!(diagram.entity("ORDER").unwrap().attributes[1].keys, [AttributeKey::Foreign]);
+
+        let places = &diagram.relationships[0];
+        assert_eq!((places.from_cardinality, places.to_cardinality), (Cardinality::ExactlyOne, Cardinality::ZeroOrMore));
+        assert!(places.identifying);
+        let part_of = &diagram.relationships[1];
+        assert_eq!((part_of.from_cardinality, part_of.to_cardinality), (Cardinality::OneOrMore, Cardinality::OneOrMore));
+        assert!(!part_of.identifying);
+        assert_eq!(part_of.label, "is part of");
+
+        let ids: Vec<_> = parsed.nodes.iter().map(|n| n.mermaid_id.as_str()).collect();
+        assert_eq!(ids, ["CUSTOMER", "ORDER", "LINE-ITEM", "SHIPMENT"]);
+        assert_eq!(parsed.nodes[0].label.as_deref(), Some("Customer"));
+        assert_eq!((parsed.nodes[1].uid.0.as_str(), parsed.nodes[1].x), ("n_order", Some(300.0)));
+        assert_eq!(parsed.edges[1].label.as_deref(), Some("is part of"));
+    }
+}
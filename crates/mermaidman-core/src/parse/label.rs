@@ -0,0 +1,165 @@
+//! Label lexing: quoted and Markdown strings, entity codes and escaping.
+
+/// Named entity codes decoded in labels (`#quot;` etc.).
+const NAMED_ENTITIES: &[(&str, char)] = &[
+    ("quot", '"'),
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("apos", '\''),
+    ("nbsp", '\u{a0}'),
+];
+
+/// Characters that may appear in a label without quoting.
+fn is_bare_label_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, ' ' | '_' | '-' | '.' | ',' | '!' | '?' | '\'' | ':')
+}
+
+/// Decode the raw text between shape delimiters or pipes.
+///
+/// Surrounding double quotes are removed; Markdown strings (`` "`**bold**`" ``)
+/// keep their backticks so the label stays a Markdown string when written.
+pub fn decode_label(raw: &str) -> String {
+    let raw = raw.trim();
+    let unquoted = raw
+        .strip_prefix('"')
+        .and_then(|r| r.strip_suffix('"'))
+        .filter(|_| raw.len() >= 2)
+        .unwrap_or(raw);
+
+    decode_entities(unquoted)
+}
+
+/// Replace entity codes (`#quot;`, `#35;`) with the characters they stand for.
+///
+/// Unknown names are left as written.
+pub fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(pos) = rest.find('#') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        match entity_at(rest) {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('#');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// Decode an entity code at the start of `text`, returning the character and
+/// the length of the code.
+fn entity_at(text: &str) -> Option<(char, usize)> {
+    let body = text.strip_prefix('#')?;
+    let end = body.find(';')?;
+    let name = &body[..end];
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    let c = if name.chars().all(|c| c.is_ascii_digit()) {
+        char::from_u32(name.parse().ok()?)?
+    } else {
+        NAMED_ENTITIES.iter().find(|(n, _)| *n == name)?.1
+    };
+
+    Some((c, end + 2))
+}
+
+/// Escape text so [`decode_entities`] gives it back unchanged.
+///
+/// `"` becomes `#quot;`, and a `#` that would start a known entity code becomes `#35;`.
+pub fn escape_label(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => out.push_str("#quot;"),
+            '#' if entity_at(&text[i..]).is_some() => out.push_str("#35;"),
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Format label text for Mermaid, quoting and escaping it when needed.
+pub fn encode_label(text: &str) -> String {
+    let bare = !text.is_empty()
+        && text.trim() == text
+        && text.chars().all(is_bare_label_char);
+
+    if bare {
+        text.to_string()
+    } else {
+        format!("\"{}\"", escape_label(text))
+    }
+}
+
+/// Length of the quoted string at the start of `input`, including its quotes.
+///
+/// Markdown strings end at `` `" ``, so they may contain plain double quotes.
+pub fn quoted_len(input: &str) -> Option<usize> {
+    let body = input.strip_prefix('"')?;
+
+    if let Some(markdown) = body.strip_prefix('`') {
+        if let Some(end) = markdown.find("`\"") {
+            return Some(end + 4);
+        }
+    }
+
+    body.find('"').map(|end| end + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_label() {
+        assert_eq!(decode_label("Plain"), "Plain");
+        assert_eq!(decode_label(r#" "Hello [world]" "#), "Hello [world]");
+        assert_eq!(decode_label("\"`**bold** text`\""), "`**bold** text`");
+        assert_eq!(decode_label("Say #quot;hi#quot; #35; #9829; #hearts;"), "Say \"hi\" # ♥ #hearts;");
+        assert_eq!(decode_label("\""), "\"");
+    }
+
+    #[test]
+    fn test_encode_label_round_trip() {
+        assert_eq!(encode_label("Start"), "Start");
+        assert_eq!(encode_label("Hello [world]"), r#""Hello [world]""#);
+        assert_eq!(encode_label(r#"say "hi""#), r#""say #quot;hi#quot;""#);
+        assert_eq!(encode_label("#quot; #x"), r##""#35;quot; #x""##);
+
+        for text in [
+            "a]b",
+            "pipe | here",
+            "line1<br/>line2",
+            "multi\nline",
+            "`**bold**`",
+            "#35; and #hearts;",
+            " padded ",
+            "",
+        ] {
+            assert_eq!(decode_label(&encode_label(text)), text);
+        }
+    }
+
+    #[test]
+    fn test_quoted_len() {
+        assert_eq!(quoted_len(r#""abc"]"#), Some(5));
+        assert_eq!(quoted_len("\"`say \"hi\"`\"]"), Some(12));
+        assert_eq!(quoted_len("\"open"), None);
+        assert_eq!(quoted_len("abc"), None);
+    }
+}
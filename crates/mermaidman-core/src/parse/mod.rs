@@ -1,13 +1,29 @@
 //! Parsing module for Mermaid topology and Mermaidman directives.
 
+mod class;
+mod config;
 mod directives;
+mod er;
+mod label;
+mod sequence;
+mod span;
+mod state;
 mod topology;
 
+pub use config::*;
 pub use directives::*;
+pub use label::*;
+pub use sequence::*;
+pub use span::*;
 pub use topology::*;
 
-use crate::types::{Edge, Node, EID, UID};
-use crate::{Error, Result};
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::migrate::{SCHEMA_VERSION, UNVERSIONED};
+use crate::types::{
+    Accessibility, AiRecord, ClassDef, ClassDiagram, DiagramHeader, DiagramKeyword, DocumentConfig, Edge,
+    EdgeStyle, ErDiagram, Group, Node, RawLine, SequenceDiagram, StateDiagram, EID, UID,
+};
+use crate::Result;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
@@ -16,69 +32,293 @@ use serde::{Deserialize, Serialize};
 pub struct ParseResult {
     /// The original mermaid topology (without directives).
     pub topology: String,
+    /// Directive schema version from the `%% @mermaidman` line; `None` for
+    /// documents without one, which are read as version 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>,
+    /// Header keyword and direction.
+    #[serde(default)]
+    pub header: DiagramHeader,
+    /// Title and Mermaid config from the frontmatter and `%%{init}%%` lines.
+    #[serde(default, skip_serializing_if = "DocumentConfig::is_empty")]
+    pub config: DocumentConfig,
+    /// `accTitle` and `accDescr`.
+    #[serde(default, skip_serializing_if = "Accessibility::is_empty")]
+    pub accessibility: Accessibility,
     /// Parsed nodes with merged directive data.
     pub nodes: Vec<Node>,
     /// Parsed edges with merged directive data.
     pub edges: Vec<Edge>,
-    /// Any warnings during parsing.
-    pub warnings: Vec<String>,
+    /// The sequence diagram, for `sequenceDiagram` documents; `nodes` and
+    /// `edges` are then empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<SequenceDiagram>,
+    /// State descriptions, for `stateDiagram` documents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_diagram: Option<StateDiagram>,
+    /// The class model, for `classDiagram` documents; each class is also a node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class_diagram: Option<ClassDiagram>,
+    /// The entity model, for `erDiagram` documents; each entity is also a node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub er_diagram: Option<ErDiagram>,
+    /// Subgraph groups, parents before children.
+    #[serde(default)]
+    pub groups: Vec<Group>,
+    /// `classDef` definitions in declaration order.
+    #[serde(default)]
+    pub class_defs: Vec<ClassDef>,
+    /// CSS from `linkStyle default`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_link_style: Option<EdgeStyle>,
+    /// Unrecognised statements and plain comments, kept verbatim.
+    #[serde(default)]
+    pub raw_lines: Vec<RawLine>,
+    /// AI provenance from `%% @ai` directives, by node UID, oldest first.
+    #[serde(default)]
+    pub provenance: IndexMap<UID, Vec<AiRecord>>,
+    /// Source positions of nodes, edges and their directives.
+    #[serde(default)]
+    pub source_map: SourceMap,
+    /// Problems found while parsing.
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A directive line with its location: the trimmed text, and the whole line
+/// including its line break (what a removal fix deletes).
+#[derive(Debug, Clone, Copy)]
+struct DirectiveSpans {
+    text: Span,
+    line: Span,
 }
 
 /// Parse a complete Mermaidman document.
 pub fn parse_document(input: &str) -> Result<ParseResult> {
     let lines: Vec<&str> = input.lines().collect();
     let mut topology_lines = Vec::new();
-    let mut node_directives: IndexMap<String, NodeDirective> = IndexMap::new();
-    let mut edge_directives: Vec<EdgeDirective> = Vec::new();
-    let mut warnings = Vec::new();
+    let mut node_directives: IndexMap<String, (NodeDirective, DirectiveSpans)> = IndexMap::new();
+    let mut edge_directives: Vec<(EdgeDirective, DirectiveSpans)> = Vec::new();
+    let mut ai_directives: Vec<(AiDirective, DirectiveSpans)> = Vec::new();
+    let mut participant_directives: IndexMap<String, (ParticipantDirective, DirectiveSpans)> = IndexMap::new();
+    let mut diagnostics = Vec::new();
+    let index = LineIndex::new(input);
+
+    // Directives are upgraded from the schema the document declares
+    let schema_version = lines.iter().find_map(|l| parse_schema_directive(l.trim())).map(|d| d.version);
+    let version = schema_version.unwrap_or(UNVERSIONED);
+    let mut schema_spans: Option<DirectiveSpans> = None;
+    let mut init: Vec<(serde_yaml::Mapping, Span)> = Vec::new();
+
+    // The frontmatter is config, not topology
+    let body_start = frontmatter_len(input);
+    let frontmatter = body_start.map(|len| (parse_frontmatter(&input[..len]), index.span(0, len)));
 
     // First pass: separate topology from directives
-    for line in &lines {
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
         let trimmed = line.trim();
-        if trimmed.starts_with("%%") {
-            if let Some(directive) = parse_node_directive(trimmed) {
-                node_directives.insert(directive.id.clone(), directive);
-            } else if let Some(directive) = parse_edge_directive(trimmed) {
-                edge_directives.push(directive);
+        if offset_in(input, line) < body_start.unwrap_or(0) {
+            continue;
+        } else if trimmed.starts_with("%%") {
+            // A directive may continue over the following `%%` lines
+            let mut last = line;
+            let mut joined = None;
+            if let Some(mut block) = DirectiveBlock::start(trimmed) {
+                let closes = lines[i..].iter().position(|l| !l.trim().starts_with("%%") || block.push(l));
+                if let Some(n) = closes.filter(|&n| lines[i + n].trim().starts_with("%%")) {
+                    last = lines[i + n];
+                    i += n + 1;
+                    joined = Some(block.text().to_string());
+                }
+            }
+            let trimmed = joined.as_deref().unwrap_or(trimmed);
+
+            let start = offset_in(input, line);
+            let end = offset_in(input, last) + last.len();
+            let line_end = end + input[end..].find('\n').map_or(0, |n| n + 1);
+            let text_start = start + (line.len() - line.trim_start().len());
+            let text_end = end - (last.len() - last.trim_end().len());
+            let spans = DirectiveSpans {
+                text: index.span(text_start, text_end),
+                line: index.span(start, line_end),
+            };
+
+            if parse_schema_directive(trimmed).is_some() {
+                if let Some(first) = schema_spans {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::DuplicateDirective,
+                            "Only the first `@mermaidman` line sets the schema version",
+                        )
+                        .with_span(spans.text)
+                        .with_related(first.text, "schema version set here")
+                        .with_removal("Remove duplicate schema line", spans.line),
+                    );
+                } else {
+                    schema_spans = Some(spans);
+                }
+            } else if let Some(config) = parse_init_directive(trimmed) {
+                match config {
+                    Ok(config) => init.push((config, spans.text)),
+                    Err(error) => diagnostics.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::InvalidConfig,
+                            format!("Init directive is ignored: {}", error),
+                        )
+                        .with_span(spans.text),
+                    ),
+                }
+            } else if let Some(directive) = parse_node_directive_with(trimmed, version) {
+                let id = directive.id.clone();
+                if let Some((_, earlier)) = node_directives.insert(id.clone(), (directive, spans)) {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::DuplicateDirective,
+                            format!("Directive for `{}` is overridden by a later one", id),
+                        )
+                        .with_span(earlier.text)
+                        .with_related(spans.text, "overriding directive")
+                        .with_removal("Remove overridden directive", earlier.line),
+                    );
+                }
+            } else if let Some(directive) = parse_edge_directive_with(trimmed, version) {
+                edge_directives.push((directive, spans));
+            } else if let Some(directive) = parse_ai_directive_with(trimmed, version) {
+                ai_directives.push((directive, spans));
+            } else if let Some(directive) = parse_participant_directive(trimmed) {
+                let id = directive.id.clone();
+                if let Some((_, earlier)) = participant_directives.insert(id.clone(), (directive, spans)) {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::DuplicateDirective,
+                            format!("Directive for participant `{}` is overridden by a later one", id),
+                        )
+                        .with_span(earlier.text)
+                        .with_related(spans.text, "overriding directive")
+                        .with_removal("Remove overridden directive", earlier.line),
+                    );
+                }
+            } else if let Some(error) = directive_error(trimmed) {
+                diagnostics.push(
+                    Diagnostic::error(DiagnosticCode::InvalidDirective, error)
+                        .with_span(spans.text)
+                        .with_removal("Remove invalid directive", spans.line),
+                );
             }
             // Don't add directive lines to topology
         } else {
-            topology_lines.push(*line);
+            topology_lines.push(line);
         }
     }
 
+    if let Some(spans) = schema_spans.filter(|_| version > SCHEMA_VERSION) {
+        diagnostics.push(
+            Diagnostic::warning(
+                DiagnosticCode::UnsupportedVersion,
+                format!(
+                    "Directives use schema version {} but this version of Mermaidman understands up to {}; newer fields may be lost on save",
+                    version, SCHEMA_VERSION
+                ),
+            )
+            .with_span(spans.text),
+        );
+    }
+
     let topology = topology_lines.join("\n");
 
-    // Parse the mermaid topology
-    let (topo_nodes, topo_edges) = parse_mermaid_topology(&topology)?;
+    // Init lines override the frontmatter, later ones earlier ones
+    let config_span = frontmatter.as_ref().map(|(_, span)| *span).or(init.first().map(|(_, span)| *span));
+    let frontmatter = frontmatter.and_then(|(parsed, span)| match parsed {
+        Ok(parsed) => Some((parsed, span)),
+        Err(error) => {
+            diagnostics.push(
+                Diagnostic::warning(
+                    DiagnosticCode::InvalidConfig,
+                    format!("Frontmatter is ignored: {}", error),
+                )
+                .with_span(span),
+            );
+            None
+        }
+    });
+    let (config, rejected) = document_config(
+        frontmatter.as_ref().map(|(parsed, _)| parsed),
+        init.iter().map(|(config, _)| config.clone()),
+    );
+    for key in rejected {
+        let mut diagnostic = Diagnostic::warning(
+            DiagnosticCode::InvalidConfig,
+            format!("Config value for `{}` has the wrong type and is ignored", key),
+        );
+        if let Some(span) = config_span {
+            diagnostic = diagnostic.with_span(span);
+        }
+        diagnostics.push(diagnostic);
+    }
+
+    // Parse the whole document (directive lines are comments to the topology
+    // parser) so spans are relative to the input
+    let (parsed_topology, sequence) = if is_sequence_diagram(input) {
+        let parsed = parse_sequence(input);
+        diagnostics.extend(parsed.diagnostics);
+        let topology = Topology {
+            header: DiagramHeader {
+                keyword: DiagramKeyword::SequenceDiagram,
+                direction: None,
+            },
+            header_span: parsed.header_span,
+            ..Default::default()
+        };
+        (topology, Some((parsed.diagram, parsed.participants)))
+    } else {
+        (parse_mermaid_topology(input)?, None)
+    };
 
     // Merge topology with directives
     let mut nodes = Vec::new();
+    let mut source_map = SourceMap::default();
     let mut mermaid_id_to_uid: IndexMap<String, UID> = IndexMap::new();
 
-    for (mermaid_id, label) in topo_nodes {
-        let directive = node_directives.get(&mermaid_id);
+    for topo_node in parsed_topology.nodes {
+        let mermaid_id = topo_node.id;
+        let directive = node_directives.get(&mermaid_id).map(|(d, _)| d);
         
         let uid = directive
             .and_then(|d| d.uid.clone())
             .map(|s| UID::from_str(&s))
-            .unwrap_or_else(UID::new);
+            .unwrap_or_default();
 
         mermaid_id_to_uid.insert(mermaid_id.clone(), uid.clone());
+        source_map.nodes.push(NodeSource {
+            uid: uid.clone(),
+            spans: topo_node.spans,
+            directive: node_directives.get(&mermaid_id).map(|(_, spans)| spans.text),
+        });
 
         let mut node = Node::with_uid(uid, &mermaid_id);
-        node.label = label;
+        node.label = topo_node.label;
+        node.shape = topo_node.shape.unwrap_or_default();
+        node.classes = topo_node.classes;
+        node.style = topo_node.style;
+        node.link = topo_node.link;
 
         if let Some(d) = directive {
-            if let Some(x) = d.x {
-                node.x = Some(x as f64);
-            }
-            if let Some(y) = d.y {
-                node.y = Some(y as f64);
-            }
+            node.x = d.x;
+            node.y = d.y;
             if let Some(ref kind) = d.kind {
                 node.kind = parse_node_kind(kind);
             }
+            // `style` statements win over CSS kept in the directive
+            if let Some(ref style) = d.style {
+                node.style.get_or_insert_with(Default::default).fill_from(style);
+            }
+            node.code = d.code.clone();
+            node.media = d.media.clone();
+            node.diagram = d.diagram.clone();
+            node.markdown = d.markdown.clone();
             node.meta = d.meta.clone();
         }
 
@@ -87,56 +327,273 @@ pub fn parse_document(input: &str) -> Result<ParseResult> {
 
     // Process edges
     let mut edges = Vec::new();
-    let mut edge_directive_map: IndexMap<(String, String), &EdgeDirective> = IndexMap::new();
-    
-    for ed in &edge_directives {
-        if let (Some(ref src), Some(ref tgt)) = (&ed.source, &ed.target) {
-            edge_directive_map.insert((src.clone(), tgt.clone()), ed);
-        }
-    }
 
-    for (src_id, tgt_id, label) in topo_edges {
+    // Endpoints of topology edges, by Mermaid ID and by UID
+    let mut endpoints: Vec<(String, String, UID, UID)> = Vec::new();
+    for topo_edge in &parsed_topology.edges {
+        let (src_id, tgt_id) = (&topo_edge.source, &topo_edge.target);
         let source = mermaid_id_to_uid
-            .get(&src_id)
+            .get(src_id)
             .cloned()
             .unwrap_or_else(|| {
-                warnings.push(format!("Unknown source node: {}", src_id));
-                UID::from_str(&src_id)
+                diagnostics.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::UnknownNode,
+                        format!("Unknown source node: {}", src_id),
+                    )
+                    .with_span(topo_edge.span),
+                );
+                UID::from_str(src_id)
             });
         
         let target = mermaid_id_to_uid
-            .get(&tgt_id)
+            .get(tgt_id)
             .cloned()
             .unwrap_or_else(|| {
-                warnings.push(format!("Unknown target node: {}", tgt_id));
-                UID::from_str(&tgt_id)
+                diagnostics.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::UnknownNode,
+                        format!("Unknown target node: {}", tgt_id),
+                    )
+                    .with_span(topo_edge.span),
+                );
+                UID::from_str(tgt_id)
             });
 
-        let directive = edge_directive_map.get(&(src_id.clone(), tgt_id.clone()));
+        endpoints.push((src_id.clone(), tgt_id.clone(), source, target));
+    }
+
+    // Directives name endpoints by Mermaid ID or, as written by us, by UID
+    let joins = |d: &EdgeDirective, (src_id, tgt_id, source, target): &(String, String, UID, UID)| {
+        let (Some(src), Some(tgt)) = (&d.source, &d.target) else {
+            return false;
+        };
+        (src == src_id && tgt == tgt_id) || (src == &source.0 && tgt == &target.0)
+    };
+
+    // A directive belongs to the edge whose Mermaid edge ID matches its token,
+    // if it names no endpoints or the edge's; the rest go, in order, to the
+    // edges between the same endpoints
+    let unjoined = |d: &EdgeDirective| d.source.is_none() && d.target.is_none();
+    let mut claimed = vec![false; edge_directives.len()];
+    let mut assigned: Vec<Option<usize>> = parsed_topology
+        .edges
+        .iter()
+        .zip(&endpoints)
+        .map(|(topo_edge, ends)| {
+            let id = topo_edge.id.as_ref()?;
+            let index = edge_directives
+                .iter()
+                .enumerate()
+                .position(|(j, (d, _))| !claimed[j] && &d.id == id && (unjoined(d) || joins(d, ends)))?;
+            claimed[index] = true;
+            Some(index)
+        })
+        .collect();
+    for (slot, ends) in assigned.iter_mut().zip(&endpoints) {
+        if slot.is_some() {
+            continue;
+        }
+        let index = edge_directives
+            .iter()
+            .enumerate()
+            .position(|(j, (d, _))| !claimed[j] && joins(d, ends));
+        if let Some(index) = index {
+            claimed[index] = true;
+            *slot = Some(index);
+        }
+    }
+
+    // Directives without an `eid` are identified by their token
+    let mut eids: IndexMap<EID, Span> = IndexMap::new();
+    let topo_edges = parsed_topology.edges.into_iter().zip(&endpoints).zip(assigned);
+    for ((topo_edge, (_, _, source, target)), index) in topo_edges {
+        let entry = index.map(|j| &edge_directives[j]);
+        let directive = entry.map(|(d, _)| d);
         
-        let eid = directive
-            .and_then(|d| d.eid.clone())
-            .map(|s| EID::from_str(&s))
-            .unwrap_or_else(EID::new);
+        let mut eid = directive
+            .map(|d| EID::from_str(d.eid.as_ref().unwrap_or(&d.id)))
+            .unwrap_or_default();
+        if let Some((_, spans)) = entry {
+            if let Some(first) = eids.get(&eid) {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::DuplicateDirective,
+                        format!("EID `{}` is already used by another edge; this edge gets a new one", eid),
+                    )
+                    .with_span(spans.text)
+                    .with_related(*first, "EID first used here"),
+                );
+                eid = EID::default();
+            } else {
+                eids.insert(eid.clone(), spans.text);
+            }
+        }
+
+        source_map.edges.push(EdgeSource {
+            eid: eid.clone(),
+            span: topo_edge.span,
+            directive: entry.map(|(_, spans)| spans.text),
+        });
 
-        let mut edge = Edge::with_eid(eid, source, target);
-        edge.label = label.or_else(|| directive.and_then(|d| d.label.clone()));
+        let mut edge = Edge::with_eid(eid, source.clone(), target.clone());
+        edge.mermaid_id = topo_edge.id;
+        edge.label = topo_edge.label.or_else(|| directive.and_then(|d| d.label.clone()));
+        if topo_edge.style != EdgeStyle::default() {
+            edge.style = Some(topo_edge.style);
+        }
         
         if let Some(d) = directive {
+            // The link syntax and `linkStyle` win over the directive
+            if let Some(ref style) = d.style {
+                edge.style.get_or_insert_with(Default::default).fill_from(style);
+            }
             edge.meta = d.meta.clone();
         }
 
         edges.push(edge);
     }
 
+    for (id, (_, spans)) in &node_directives {
+        if !mermaid_id_to_uid.contains_key(id) {
+            diagnostics.push(orphan_directive(format!("No node `{}` in the topology", id), spans));
+        }
+    }
+
+    for ((directive, spans), claimed) in edge_directives.iter().zip(claimed) {
+        if claimed {
+            continue;
+        }
+        let (Some(src), Some(tgt)) = (&directive.source, &directive.target) else {
+            let message = format!("No edge `{}` in the topology", directive.id);
+            diagnostics.push(orphan_directive(message, spans));
+            continue;
+        };
+        let message = if endpoints.iter().any(|ends| joins(directive, ends)) {
+            format!("Every edge `{} --> {}` already has a directive", src, tgt)
+        } else {
+            format!("No edge `{} --> {}` in the topology", src, tgt)
+        };
+        diagnostics.push(orphan_directive(message, spans));
+    }
+
+    // Participants take their UID and layout from their directive
+    let sequence = sequence.map(|(mut diagram, sources)| {
+        for (participant, mut source) in diagram.participants.iter_mut().zip(sources) {
+            if let Some((directive, spans)) = participant_directives.shift_remove(&participant.id) {
+                if let Some(uid) = directive.uid {
+                    participant.uid = UID::from_str(&uid);
+                }
+                participant.x = directive.x;
+                participant.y = directive.y;
+                participant.meta = directive.meta;
+                source.directive = Some(spans.text);
+            }
+            source.uid = participant.uid.clone();
+            source_map.participants.push(source);
+        }
+        diagram
+    });
+    for (id, (_, spans)) in &participant_directives {
+        diagnostics.push(orphan_directive(format!("No participant `{}` in the diagram", id), spans));
+    }
+
+    let mut provenance: IndexMap<UID, Vec<AiRecord>> = IndexMap::new();
+    for (directive, spans) in ai_directives {
+        let uid = UID::from_str(&directive.target_uid);
+        if !mermaid_id_to_uid.values().any(|u| u == &uid) {
+            let message = format!("No node with UID `{}` for AI provenance", uid);
+            diagnostics.push(orphan_directive(message, &spans));
+            continue;
+        }
+        source_map.provenance.push(AiSource {
+            uid: uid.clone(),
+            span: spans.text,
+        });
+        provenance.entry(uid).or_default().push(directive.into());
+    }
+
+    let mut raw_lines = Vec::new();
+    for raw in parsed_topology.raw_lines {
+        if !raw.line.is_comment() {
+            diagnostics.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnparsedStatement,
+                    format!("Unrecognised statement `{}` is kept as written", raw.line.text),
+                )
+                .with_span(raw.span),
+            );
+        }
+        source_map.raw_lines.push(raw.span);
+        raw_lines.push(raw.line);
+    }
+
+    diagnostics.sort_by_key(|d| d.span.map_or(usize::MAX, |span| span.start));
+
+    source_map.header = parsed_topology.header_span;
+    source_map.acc_title = parsed_topology.acc_title_span;
+    source_map.acc_descr = parsed_topology.acc_descr_span;
+    source_map.schema = schema_spans.map(|spans| spans.text);
+    source_map.frontmatter = body_start.map(|len| index.span(0, len));
+    source_map.init = init.iter().map(|(_, span)| *span).collect();
+    source_map.styles = parsed_topology.style_spans;
+
+    let mut groups = Vec::new();
+    for sg in parsed_topology.subgraphs {
+        source_map.groups.push(GroupSource {
+            id: sg.id.clone(),
+            span: sg.span,
+            direction: sg.direction_span,
+            end: sg.end_span,
+        });
+        groups.push(Group {
+            members: sg
+                .members
+                .iter()
+                .filter_map(|id| mermaid_id_to_uid.get(id).cloned())
+                .collect(),
+            region_starts: sg
+                .regions
+                .iter()
+                .filter_map(|&i| mermaid_id_to_uid.get(sg.members.get(i)?).cloned())
+                .collect(),
+            id: sg.id,
+            title: sg.title,
+            direction: sg.direction,
+            children: sg.subgraphs,
+            parent: sg.parent,
+        });
+    }
+
     Ok(ParseResult {
         topology,
+        schema_version,
+        header: parsed_topology.header,
+        config,
+        accessibility: parsed_topology.accessibility,
         nodes,
+        sequence,
+        state_diagram: parsed_topology.state_diagram,
+        class_diagram: parsed_topology.class_diagram,
+        er_diagram: parsed_topology.er_diagram,
         edges,
-        warnings,
+        groups,
+        class_defs: parsed_topology.class_defs,
+        default_link_style: parsed_topology.default_link_style,
+        raw_lines,
+        provenance,
+        source_map,
+        diagnostics,
     })
 }
 
+/// Warning for a directive that matches nothing in the topology.
+fn orphan_directive(message: String, spans: &DirectiveSpans) -> Diagnostic {
+    Diagnostic::warning(DiagnosticCode::OrphanDirective, message)
+        .with_span(spans.text)
+        .with_removal("Remove orphan directive", spans.line)
+}
+
 fn parse_node_kind(s: &str) -> crate::types::NodeKind {
     match s.to_lowercase().as_str() {
         "card" => crate::types::NodeKind::Card,
@@ -170,5 +627,306 @@ A[Start] --> B[End]
         let node_a = result.nodes.iter().find(|n| n.mermaid_id == "A").unwrap();
         assert_eq!(node_a.uid.0, "n_001");
         assert_eq!(node_a.x, Some(100.0));
+        assert_eq!(result.header.to_string(), "graph TD");
+    }
+
+    #[test]
+    fn test_parse_document_groups() {
+        let input = r#"graph TD
+subgraph lane [Lane]
+    A[Start]
+end
+A --> B
+
+%% @node: A {"uid":"n_001","x":100,"y":50}
+"#;
+
+        let result = parse_document(input).unwrap();
+        assert_eq!(result.groups.len(), 1);
+        assert_eq!(result.groups[0].id, "lane");
+        assert_eq!(result.groups[0].members, vec![UID::from_str("n_001")]);
+    }
+
+    #[test]
+    fn test_parse_document_config() {
+        let input = "---\ntitle: Flow\nconfig:\n  theme: dark\n---\n%%{init: {\"theme\": \"forest\", \"flowchart\": {\"nodeSpacing\": \"wide\"}}}%%\ngraph TD\nA --> B\n%%{init: nope}%%\n";
+
+        let result = parse_document(input).unwrap();
+        assert_eq!(result.config.title.as_deref(), Some("Flow"));
+        assert_eq!(result.config.theme.as_deref(), Some("forest"));
+        assert_eq!(result.header.to_string(), "graph TD");
+        assert_eq!(result.nodes.len(), 2);
+        assert!(!result.topology.contains("title"));
+
+        let map = &result.source_map;
+        assert_eq!(map.frontmatter.map(|s| &input[s.start..s.end]), Some("---\ntitle: Flow\nconfig:\n  theme: dark\n---"));
+        assert_eq!(map.init.len(), 1);
+        assert_eq!(map.init[0].start_line, 6);
+
+        // The invalid init line stays a comment
+        assert_eq!(result.raw_lines.len(), 1);
+        let codes: Vec<_> = result.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, [DiagnosticCode::InvalidConfig, DiagnosticCode::InvalidConfig]);
+        assert!(result.diagnostics[0].message.contains("flowchart.nodeSpacing"));
+    }
+
+    #[test]
+    fn test_parse_document_source_map() {
+        let input = r#"graph TD
+A[Start] --> B
+  B --> C[End]
+
+%% @node: A {"uid":"n_001"}
+%% @edge: e1 {"eid":"e_001","source":"A","target":"B"}
+"#;
+
+        let result = parse_document(input).unwrap();
+        let map = &result.source_map;
+
+        let a = map.node(&UID::from_str("n_001")).unwrap();
+        assert_eq!(a.spans.len(), 1);
+        assert_eq!(&input[a.spans[0].start..a.spans[0].end], "A[Start]");
+        assert_eq!((a.spans[0].start_line, a.spans[0].start_column), (2, 1));
+        let directive = a.directive.unwrap();
+        assert_eq!(directive.start_line, 5);
+        assert!(input[directive.start..directive.end].starts_with("%% @node: A"));
+
+        // B is referenced twice
+        let b = map.node(&result.nodes[1].uid).unwrap();
+        let b_columns: Vec<_> = b.spans.iter().map(|s| (s.start_line, s.start_column)).collect();
+        assert_eq!(b_columns, vec![(2, 14), (3, 3)]);
+        assert_eq!(b.directive, None);
+
+        let first = map.edge(&result.edges[0].eid).unwrap();
+        assert_eq!(&input[first.span.start..first.span.end], "A[Start] --> B");
+        assert_eq!(first.directive.unwrap().start_line, 6);
+
+        let second = map.edge(&result.edges[1].eid).unwrap();
+        assert_eq!(&input[second.span.start..second.span.end], "B --> C[End]");
+        assert_eq!(second.directive, None);
+    }
+
+    #[test]
+    fn test_parse_document_diagnostics() {
+        let input = "graph TD\nA --> B\n%% @node: A {\"uid\":\"n_old\"}\n%% @node: A {\"uid\":\"n_001\"}\n%% @node: Gone {\"uid\":\"n_002\"}\n%% @edge: e1 {\"source\":\"A\",\"target\":\"Gone\"}\n%% @node: B {\"uid\":}\n%% plain comment\n";
+
+        let result = parse_document(input).unwrap();
+        let codes: Vec<_> = result.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                DiagnosticCode::DuplicateDirective,
+                DiagnosticCode::OrphanDirective,
+                DiagnosticCode::OrphanDirective,
+                DiagnosticCode::InvalidDirective,
+            ]
+        );
+
+        // The later directive wins; the fix removes the earlier line
+        assert_eq!(result.nodes[0].uid, UID::from_str("n_001"));
+        let duplicate = &result.diagnostics[0];
+        assert_eq!(duplicate.span.unwrap().start_line, 3);
+        assert_eq!(duplicate.related[0].span.start_line, 4);
+
+        let invalid = &result.diagnostics[3];
+        assert_eq!(invalid.severity, crate::diagnostic::Severity::Error);
+        assert!(invalid.message.starts_with("invalid JSON"));
+
+        // Applying the orphan fix deletes the whole line
+        let fix = result.diagnostics[1].fix.as_ref().unwrap();
+        let edit = &fix.edits[0];
+        let fixed = format!("{}{}", &input[..edit.span.start], &input[edit.span.end..]);
+        assert!(!fixed.contains("Gone {"));
+        assert!(fixed.contains("\n%% @edge: e1"));
+    }
+
+    #[test]
+    fn test_parse_multi_line_directive() {
+        let input = r#"graph TD
+A[Subflow]
+
+%% @node: A {
+%%   "uid":"n_1",
+%%   "x":120,
+%%   "y":80,
+%%   "kind":"diagram",
+%%   "diagram":{
+%%     "title":"Subflow",
+%%     "mermaidman":"graph TD\nX-->Y\n%% @node: X {\"x\":50,\"y\":40}\n"
+%%   }
+%% }
+"#;
+
+        let result = parse_document(input).unwrap();
+        let node = &result.nodes[0];
+        assert_eq!(node.uid, UID::from_str("n_1"));
+        assert_eq!((node.x, node.y), (Some(120.0), Some(80.0)));
+        assert_eq!(node.kind, crate::types::NodeKind::Diagram);
+        assert_eq!(node.diagram.as_ref().unwrap().title.as_deref(), Some("Subflow"));
+        assert!(result.raw_lines.is_empty());
+        assert!(result.diagnostics.is_empty());
+
+        let span = result.source_map.nodes[0].directive.unwrap();
+        assert_eq!((span.start_line, span.end_line), (4, 13));
+        assert!(input[span.start..span.end].ends_with("%% }"));
+    }
+
+    #[test]
+    fn test_parse_schema_version() {
+        let input = "graph TD\nA\n\n%% @node: A {\"uid\":\"n_1\",\"meta\":{\"uid\":\"n_1\",\"tags\":[\"x\"]}}\n";
+        let result = parse_document(input).unwrap();
+        assert_eq!(result.schema_version, None);
+        assert_eq!(result.nodes[0].meta, Some(serde_json::json!({"tags": ["x"]})));
+
+        // Current documents are not migrated
+        let header = format!("\n%% @mermaidman: {{\"version\":{}}}\n%% @node", SCHEMA_VERSION);
+        let current = input.replace("\n%% @node", &header);
+        let result = parse_document(&current).unwrap();
+        assert_eq!(result.schema_version, Some(SCHEMA_VERSION));
+        assert_eq!(result.nodes[0].meta.as_ref().unwrap()["uid"], "n_1");
+        assert_eq!(result.source_map.schema.unwrap().start_line, 4);
+        assert!(result.raw_lines.is_empty() && result.diagnostics.is_empty());
+
+        let newer = input.replace("\n%% @node", "\n%% @mermaidman: {\"version\":99}\n%% @node");
+        let result = parse_document(&newer).unwrap();
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, DiagnosticCode::UnsupportedVersion);
+        assert_eq!(result.diagnostics[0].span.unwrap().start_line, 4);
+    }
+
+    #[test]
+    fn test_parse_schema_version_2() {
+        let input = r#"graph TD
+A --> B
+
+%% @mermaidman: {"version":2}
+%% @node: A {"uid":"n_a","meta":{"uid":"n_a"}}
+%% @node: B {"uid":"n_b"}
+%% @edge: e_token {"eid":"e_store","source":"n_a","target":"n_b"}
+"#;
+        let result = parse_document(input).unwrap();
+        assert_eq!(result.schema_version, Some(2));
+        assert!(result.diagnostics.is_empty());
+
+        // Node bodies were already unnested; only the edge token moves into `eid`
+        assert_eq!(result.nodes[0].meta, Some(serde_json::json!({"uid": "n_a"})));
+        assert_eq!(result.edges[0].eid.0, "e_token");
+    }
+
+    #[test]
+    fn test_edge_directive_without_endpoints_matches_its_token() {
+        let input = "graph TD\nA e1@--> B\nB --> C\n\n%% @edge: e1 {\"eid\":\"e_one\",\"label\":\"first\"}\n%% @edge: e9 {\"label\":\"gone\"}\n";
+        let result = parse_document(input).unwrap();
+        assert_eq!(result.edges[0].eid.0, "e_one");
+        assert_eq!(result.edges[0].label.as_deref(), Some("first"));
+        assert!(result.source_map.edges[0].directive.is_some());
+
+        // The directive nothing claims is reported
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, DiagnosticCode::OrphanDirective);
+        assert_eq!(result.diagnostics[0].message, "No edge `e9` in the topology");
+        assert_eq!(result.diagnostics[0].span.unwrap().start_line, 6);
+
+        let store = crate::store::GraphStore::from_parse_result(result);
+        let saved = crate::write::update_document(input, &store);
+        assert_eq!(saved.matches("%% @edge: e1 ").count(), 1, "{}", saved);
+    }
+
+    #[test]
+    fn test_unversioned_edges_keep_their_eid() {
+        let input = r#"graph TD
+A --> B
+
+%% @node: A {"uid":"n_a"}
+%% @node: B {"uid":"n_b"}
+%% @edge: e1 {"eid":"e_keep","source":"n_a","target":"n_b","label":null}
+"#;
+        let result = parse_document(input).unwrap();
+        assert_eq!(result.edges[0].eid.0, "e_keep");
+        assert_eq!(result.edges[0].meta, None);
+
+        let saved = crate::write::generate_mermaidman(&crate::store::GraphStore::from_parse_result(result));
+        assert!(saved.contains(r#"%% @edge: e1 {"eid":"e_keep","source":"n_a","target":"n_b"}"#), "{}", saved);
+    }
+
+    #[test]
+    fn test_parse_parallel_edges() {
+        let input = r#"graph TD
+A -->|retry| B
+A -->|done| B
+A e7@--> B
+
+%% @edge: e7 {"source":"A","target":"B","meta":{"id":7}}
+%% @edge: e1 {"source":"A","target":"B","meta":{"n":1}}
+%% @edge: e2 {"source":"A","target":"B","meta":{"n":2}}
+%% @edge: e3 {"source":"A","target":"B"}
+"#;
+
+        let result = parse_document(input).unwrap();
+        let meta: Vec<_> = result.edges.iter().map(|e| e.meta.clone().unwrap()).collect();
+        assert_eq!(meta, vec![serde_json::json!({"n": 1}), serde_json::json!({"n": 2}), serde_json::json!({"id": 7})]);
+        assert_eq!(result.edges[1].label, Some("done".to_string()));
+        assert_eq!(result.edges[2].mermaid_id, Some("e7".to_string()));
+        assert_eq!(result.edges[1].eid.0, "e2");
+
+        let directive_lines: Vec<_> = result
+            .source_map
+            .edges
+            .iter()
+            .map(|e| e.directive.unwrap().start_line)
+            .collect();
+        assert_eq!(directive_lines, vec![7, 8, 6]);
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].message, "Every edge `A --> B` already has a directive");
+        assert_eq!(result.diagnostics[0].span.unwrap().start_line, 9);
+    }
+
+    #[test]
+    fn test_parse_ai_provenance() {
+        let input = r#"graph TD
+A --> B
+
+%% @node: A {"uid":"n_1"}
+%% @ai: n_1 {"action":"summarize","provider":"gemini","status":"accepted"}
+%% @ai: n_1 {"action":"expand"}
+%% @ai: n_9 {"action":"expand"}
+"#;
+
+        let result = parse_document(input).unwrap();
+        let log = &result.provenance[&UID::from_str("n_1")];
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].status, crate::types::AiReview::Accepted);
+        assert_eq!(log[1].action, "expand");
+        assert_eq!(result.source_map.provenance[1].span.start_line, 6);
+        assert!(result.raw_lines.is_empty());
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, DiagnosticCode::OrphanDirective);
+        assert_eq!(result.diagnostics[0].span.unwrap().start_line, 7);
+    }
+
+    #[test]
+    fn test_parse_document_raw_lines() {
+        let input = "graph TD\nA --> B\n%% keep me\nA -> B\n%% @node: A {\"uid\":\"n_001\"}\n";
+
+        let result = parse_document(input).unwrap();
+        let texts: Vec<_> = result.raw_lines.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(texts, vec!["%% keep me", "A -> B"]);
+        assert_eq!(result.source_map.raw_lines[1].start_line, 4);
+
+        // Only the statement is reported; comments are fine
+        assert_eq!(result.diagnostics.len(), 1);
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(diagnostic.code, DiagnosticCode::UnparsedStatement);
+        assert_eq!(diagnostic.span, Some(result.source_map.raw_lines[1]));
+
+        // An unclosed `accDescr {` does not swallow the directives
+        let input = "graph TD\nA --> B\naccDescr {\n%% @node: A {\"uid\":\"n_001\"}\n";
+        let result = parse_document(input).unwrap();
+        assert_eq!(result.nodes[0].uid.0, "n_001");
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, DiagnosticCode::UnparsedStatement);
+        assert_eq!(result.diagnostics[0].span.unwrap().start_line, 3);
     }
 }
@@ -0,0 +1,374 @@
+//! Sequence diagram parsing (`sequenceDiagram`).
+
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::parse::config::frontmatter_len;
+use crate::parse::span::{LineIndex, ParticipantSource, Span};
+use crate::parse::topology::is_directive;
+use crate::types::{
+    BlockKind, BlockSection, DiagramHeader, DiagramKeyword, Message, MessageArrow, NotePlacement,
+    Participant, ParticipantBox, ParticipantKind, SequenceBlock, SequenceDiagram, SequenceNote,
+    SequenceStatement,
+};
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// A parsed sequence diagram with source positions.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTopology {
+    pub diagram: SequenceDiagram,
+    /// The `sequenceDiagram` line.
+    pub header_span: Option<Span>,
+    /// Source positions of `diagram.participants`, in the same order.
+    pub participants: Vec<ParticipantSource>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Whether the document's header (after any frontmatter and comments) is
+/// `sequenceDiagram`.
+pub fn is_sequence_diagram(input: &str) -> bool {
+    let body = &input[frontmatter_len(input).unwrap_or(0)..];
+    body.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("%%"))
+        .and_then(DiagramHeader::parse)
+        .is_some_and(|header| header.keyword == DiagramKeyword::SequenceDiagram)
+}
+
+/// Parse a sequence diagram.
+///
+/// Directive lines and the frontmatter are skipped, as for flowcharts, so
+/// spans are document-relative. Statements that cannot be parsed, and `%%`
+/// comments, are kept as [`SequenceStatement::Raw`] in place.
+pub fn parse_sequence(input: &str) -> SequenceTopology {
+    let mut parser = SequenceParser {
+        index: LineIndex::new(input),
+        topology: SequenceTopology::default(),
+        top: Vec::new(),
+        open: Vec::new(),
+        open_box: None,
+    };
+    let body_start = frontmatter_len(input).unwrap_or(0);
+
+    for line in input.split('\n') {
+        let trimmed = line.trim();
+        let start = line.as_ptr() as usize - input.as_ptr() as usize;
+        if trimmed.is_empty() || start < body_start {
+            continue;
+        }
+        let span = parser.index.span_of(trimmed);
+
+        if trimmed.starts_with("%%") {
+            if !is_directive(trimmed) {
+                parser.push(raw(trimmed));
+            }
+        } else if parser.topology.header_span.is_none() && DiagramHeader::parse(trimmed).is_some() {
+            parser.topology.header_span = Some(span);
+        } else {
+            parser.statement(trimmed, span);
+        }
+    }
+
+    if let Some((participant_box, span)) = parser.open_box.take() {
+        parser.topology.diagnostics.push(
+            Diagnostic::warning(DiagnosticCode::UnparsedStatement, "`box` block is never closed")
+                .with_span(span),
+        );
+        parser.topology.diagram.boxes.push(participant_box);
+    }
+
+    while let Some((block, span)) = parser.open.pop() {
+        parser.topology.diagnostics.push(
+            Diagnostic::warning(
+                DiagnosticCode::UnparsedStatement,
+                format!("`{}` block is never closed", block.kind.as_str()),
+            )
+            .with_span(span),
+        );
+        parser.push(SequenceStatement::Block(block));
+    }
+
+    parser.topology.diagram.statements = parser.top;
+    parser.topology
+}
+
+struct SequenceParser<'a> {
+    index: LineIndex<'a>,
+    topology: SequenceTopology,
+    /// Top-level statements.
+    top: Vec<SequenceStatement>,
+    /// Open blocks, innermost last, with their opening lines.
+    open: Vec<(SequenceBlock, Span)>,
+    /// The open `box`, with its opening line.
+    open_box: Option<(ParticipantBox, Span)>,
+}
+
+impl SequenceParser<'_> {
+    /// Add a statement to the innermost open section.
+    fn push(&mut self, statement: SequenceStatement) {
+        match self.open.last_mut() {
+            Some((block, _)) => block
+                .sections
+                .last_mut()
+                .expect("blocks have a section")
+                .statements
+                .push(statement),
+            None => self.top.push(statement),
+        }
+    }
+
+    /// Record a participant at its first mention.
+    fn mention(&mut self, id: &str, span: Span) {
+        if self.topology.diagram.participant(id).is_some() {
+            return;
+        }
+        let participant = Participant::new(id);
+        self.topology.participants.push(ParticipantSource {
+            uid: participant.uid.clone(),
+            id: id.to_string(),
+            span,
+            directive: None,
+        });
+        self.topology.diagram.participants.push(participant);
+    }
+
+    fn statement(&mut self, line: &str, span: Span) {
+        let (keyword, rest) = match line.split_once(char::is_whitespace) {
+            Some((keyword, rest)) => (keyword, rest.trim()),
+            None => (line, ""),
+        };
+
+        match keyword {
+            "participant" | "actor" if !rest.is_empty() => {
+                let (id, alias) = match rest.split_once(" as ") {
+                    Some((id, alias)) => (id.trim(), Some(alias.trim().to_string())),
+                    None => (rest, None),
+                };
+                self.mention(id, span);
+                let index = self.topology.diagram.participants.iter().position(|p| p.id == id);
+                if let Some(index) = index {
+                    let participant = &mut self.topology.diagram.participants[index];
+                    participant.kind = match keyword {
+                        "actor" => ParticipantKind::Actor,
+                        _ => ParticipantKind::Participant,
+                    };
+                    participant.alias = alias;
+                    participant.declared = true;
+                    self.topology.participants[index].span = span;
+                }
+                if let Some((participant_box, _)) = self.open_box.as_mut() {
+                    participant_box.participants.push(id.to_string());
+                }
+                return;
+            }
+            "box" if self.open_box.is_none() && self.open.is_empty() => {
+                let participant_box = ParticipantBox {
+                    label: rest.to_string(),
+                    participants: Vec::new(),
+                };
+                self.open_box = Some((participant_box, span));
+                return;
+            }
+            "activate" | "deactivate" if !rest.is_empty() => {
+                self.mention(rest, span);
+                self.push(SequenceStatement::Activation {
+                    participant: rest.to_string(),
+                    active: keyword == "activate",
+                });
+                return;
+            }
+            "end" if rest.is_empty() => {
+                if let Some((block, _)) = self.open.pop() {
+                    self.push(SequenceStatement::Block(block));
+                    return;
+                }
+                if let Some((participant_box, _)) = self.open_box.take() {
+                    self.topology.diagram.boxes.push(participant_box);
+                    return;
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(kind) = BlockKind::parse(keyword) {
+            let block = SequenceBlock {
+                kind,
+                sections: vec![BlockSection {
+                    label: rest.to_string(),
+                    statements: Vec::new(),
+                }],
+            };
+            self.open.push((block, span));
+            return;
+        }
+
+        if let Some((block, _)) = self.open.last_mut() {
+            if block.kind.section_keyword() == Some(keyword) {
+                block.sections.push(BlockSection {
+                    label: rest.to_string(),
+                    statements: Vec::new(),
+                });
+                return;
+            }
+        }
+
+        if let Some(note) = parse_note(line) {
+            for id in &note.participants {
+                self.mention(id, span);
+            }
+            self.push(SequenceStatement::Note(note));
+            return;
+        }
+
+        if let Some(message) = parse_message(line) {
+            self.mention(&message.from, span);
+            self.mention(&message.to, span);
+            self.push(SequenceStatement::Message(message));
+            return;
+        }
+
+        self.topology.diagnostics.push(
+            Diagnostic::warning(
+                DiagnosticCode::UnparsedStatement,
+                format!("Unrecognised statement `{}` is kept as written", line),
+            )
+            .with_span(span),
+        );
+        self.push(raw(line));
+    }
+}
+
+fn raw(text: &str) -> SequenceStatement {
+    SequenceStatement::Raw {
+        text: text.to_string(),
+    }
+}
+
+/// Parse `Note left of A: text`, `Note right of A: text` or `Note over A,B: text`.
+fn parse_note(line: &str) -> Option<SequenceNote> {
+    static NOTE: OnceLock<Regex> = OnceLock::new();
+    let re = NOTE.get_or_init(|| {
+        Regex::new(r"(?i)^note\s+(left of|right of|over)\s+([^:]+?)\s*:\s*(.*)$").expect("valid note pattern")
+    });
+    let caps = re.captures(line)?;
+
+    let placement = match caps.get(1)?.as_str().to_lowercase().as_str() {
+        "left of" => NotePlacement::LeftOf,
+        "right of" => NotePlacement::RightOf,
+        _ => NotePlacement::Over,
+    };
+    let participants: Vec<String> = caps
+        .get(2)?
+        .as_str()
+        .split(',')
+        .map(|id| id.trim().to_string())
+        .collect();
+    if participants.iter().any(|id| id.is_empty() || id.contains(char::is_whitespace)) {
+        return None;
+    }
+
+    Some(SequenceNote {
+        placement,
+        participants,
+        text: caps.get(3)?.as_str().trim().to_string(),
+    })
+}
+
+/// Parse `A->>B: text`, with an optional `+`/`-` before the target.
+fn parse_message(line: &str) -> Option<Message> {
+    static MESSAGE: OnceLock<Regex> = OnceLock::new();
+    let re = MESSAGE.get_or_init(|| {
+        let arrows = MessageArrow::ALL
+            .iter()
+            .map(|arrow| regex::escape(arrow.as_str()))
+            .collect::<Vec<_>>()
+            .join("|");
+        Regex::new(&format!(
+            r"^([^\s:+]+?)\s*({})\s*([+-])?\s*([^\s:+-][^\s:]*)\s*(?::(.*))?$",
+            arrows
+        ))
+        .expect("valid message pattern")
+    });
+    let caps = re.captures(line)?;
+
+    let arrow = caps.get(2)?.as_str();
+    Some(Message {
+        from: caps.get(1)?.as_str().to_string(),
+        to: caps.get(4)?.as_str().to_string(),
+        arrow: *MessageArrow::ALL.iter().find(|a| a.as_str() == arrow)?,
+        text: caps.get(5).map_or("", |m| m.as_str()).trim().to_string(),
+        activation: caps.get(3).map(|m| m.as_str() == "+"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sequence() {
+        let input = "sequenceDiagram
+    actor U as User
+    participant API
+    U->>+API: POST /orders
+    %% validation
+    loop Every item
+        API-->>DB: reserve
+    end
+    alt in stock
+        API--)U: 201
+    else out of stock
+        API-xU: 409
+    end
+    Note over API,DB: one transaction
+    deactivate API
+    API wobble DB
+%% @participant: API {\"uid\":\"n_api\",\"x\":200}";
+
+        assert!(is_sequence_diagram(input));
+        let parsed = parse_sequence(input);
+        let diagram = &parsed.diagram;
+
+        let ids: Vec<_> = diagram.participants.iter().map(|p| (p.id.as_str(), p.declared)).collect();
+        assert_eq!(ids, [("U", true), ("API", true), ("DB", false)]);
+        assert_eq!(diagram.participants[0].kind, ParticipantKind::Actor);
+        assert_eq!(diagram.participants[0].alias.as_deref(), Some("User"));
+        assert_eq!(parsed.participants[2].span.start_line, 7);
+
+        let SequenceStatement::Message(first) = &diagram.statements[0] else {
+            panic!("expected a message");
+        };
+        assert_eq!((first.from.as_str(), first.to.as_str()), ("U", "API"));
+        assert_eq!((first.arrow, first.activation), (MessageArrow::SolidArrow, Some(true)));
+        assert_eq!(first.text, "POST /orders");
+
+        let SequenceStatement::Block(alt) = &diagram.statements[3] else {
+            panic!("expected a block");
+        };
+        assert_eq!(alt.kind, BlockKind::Alt);
+        let labels: Vec<_> = alt.sections.iter().map(|s| s.label.as_str()).collect();
+        assert_eq!(labels, ["in stock", "out of stock"]);
+        assert_eq!(
+            alt.sections[1].statements,
+            [SequenceStatement::Message(Message {
+                from: "API".to_string(),
+                to: "U".to_string(),
+                arrow: MessageArrow::SolidCross,
+                text: "409".to_string(),
+                activation: None,
+            })]
+        );
+
+        assert!(matches!(&diagram.statements[1], SequenceStatement::Raw { text } if text == "%% validation"));
+        assert_eq!(diagram.statements.len(), 7);
+        assert_eq!(parsed.diagnostics.len(), 1);
+        assert_eq!(parsed.diagnostics[0].span.unwrap().start_line, 16);
+    }
+
+    #[test]
+    fn test_unclosed_block_is_reported() {
+        let parsed = parse_sequence("sequenceDiagram\nopt retry\nA->B: again");
+        assert!(matches!(&parsed.diagram.statements[..], [SequenceStatement::Block(_)]));
+        assert!(parsed.diagnostics[0].message.contains("never closed"));
+        assert!(!is_sequence_diagram("graph TD\nA --> B"));
+    }
+}
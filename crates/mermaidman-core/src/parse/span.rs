@@ -0,0 +1,208 @@
+//! Source positions for parsed statements and directives.
+
+use crate::types::{EID, UID};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A region of the source text.
+///
+/// `start`/`end` are byte offsets (end exclusive). Lines and columns are
+/// 1-based; columns count characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.start_line, self.start_column)
+    }
+}
+
+/// Byte offset of `inner` within `outer`; `inner` must be a slice of `outer`.
+pub(crate) fn offset_in(outer: &str, inner: &str) -> usize {
+    inner.as_ptr() as usize - outer.as_ptr() as usize
+}
+
+/// Line start offsets, for turning byte offsets into [`Span`]s.
+pub(crate) struct LineIndex<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self { text, line_starts }
+    }
+
+    /// Span for the byte range `start..end`.
+    pub(crate) fn span(&self, start: usize, end: usize) -> Span {
+        let (start_line, start_column) = self.position(start);
+        let (end_line, end_column) = self.position(end);
+
+        Span {
+            start,
+            end,
+            start_line,
+            start_column,
+            end_line,
+            end_column,
+        }
+    }
+
+    /// Span covering `slice`, which must be a slice of the indexed text.
+    pub(crate) fn span_of(&self, slice: &str) -> Span {
+        let start = offset_in(self.text, slice);
+        self.span(start, start + slice.len())
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.text[self.line_starts[line]..offset].chars().count() + 1;
+        (line + 1, column)
+    }
+}
+
+/// Where a node appears in the source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeSource {
+    pub uid: UID,
+    /// Every declaration or reference of the node, in source order.
+    pub spans: Vec<Span>,
+    /// The `%% @node` directive line, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directive: Option<Span>,
+}
+
+/// Where an edge appears in the source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeSource {
+    pub eid: EID,
+    /// The statement that declares the edge.
+    pub span: Span,
+    /// The `%% @edge` directive line, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directive: Option<Span>,
+}
+
+/// Where a `%% @ai` directive appears in the source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AiSource {
+    /// The node the directive records an action on.
+    pub uid: UID,
+    pub span: Span,
+}
+
+/// Where a sequence diagram participant appears in the source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParticipantSource {
+    pub uid: UID,
+    pub id: String,
+    /// The declaring `participant`/`actor` line, or else the first statement
+    /// that mentions it.
+    pub span: Span,
+    /// The `%% @participant` directive line, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directive: Option<Span>,
+}
+
+/// Where a subgraph block appears in the source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupSource {
+    pub id: String,
+    /// The `subgraph` line.
+    pub span: Span,
+    /// The `direction` statement, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Span>,
+    /// The closing `end`, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<Span>,
+}
+
+/// Source positions for a parsed document, in the same order as its nodes,
+/// edges, groups and raw lines.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceMap {
+    /// The header line, if the document has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<Span>,
+    /// The `accTitle` statement, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acc_title: Option<Span>,
+    /// The `accDescr` statement or block, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acc_descr: Option<Span>,
+    /// The `%% @mermaidman` schema line, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Span>,
+    /// The frontmatter block, fences included.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontmatter: Option<Span>,
+    /// `%%{init}%%` lines that parsed, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub init: Vec<Span>,
+    pub nodes: Vec<NodeSource>,
+    pub edges: Vec<EdgeSource>,
+    #[serde(default)]
+    pub groups: Vec<GroupSource>,
+    /// `classDef`, `class`, `style`, `click` and `linkStyle` statements.
+    #[serde(default)]
+    pub styles: Vec<Span>,
+    #[serde(default)]
+    pub raw_lines: Vec<Span>,
+    /// `%% @ai` directives, in the order of each node's provenance log.
+    #[serde(default)]
+    pub provenance: Vec<AiSource>,
+    /// Sequence diagram participants, in display order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub participants: Vec<ParticipantSource>,
+}
+
+impl SourceMap {
+    /// Source positions for a node.
+    pub fn node(&self, uid: &UID) -> Option<&NodeSource> {
+        self.nodes.iter().find(|n| &n.uid == uid)
+    }
+
+    /// Source positions for an edge.
+    pub fn edge(&self, eid: &EID) -> Option<&EdgeSource> {
+        self.edges.iter().find(|e| &e.eid == eid)
+    }
+
+    /// Source positions for a subgraph block.
+    pub fn group(&self, id: &str) -> Option<&GroupSource> {
+        self.groups.iter().find(|g| g.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index_spans() {
+        let text = "graph TD\nA --> B\n  é[Ünï] --> C";
+        let index = LineIndex::new(text);
+
+        let span = index.span_of(&text[9..16]);
+        assert_eq!((span.start, span.end), (9, 16));
+        assert_eq!((span.start_line, span.start_column), (2, 1));
+        assert_eq!((span.end_line, span.end_column), (2, 8));
+
+        let start = text.find('[').unwrap();
+        let span = index.span(start, text.len());
+        assert_eq!((span.start_line, span.start_column), (3, 4));
+        assert_eq!((span.end_line, span.end_column), (3, 15));
+        assert_eq!(span.to_string(), "3:4");
+    }
+}
@@ -0,0 +1,343 @@
+//! State diagram statements (`stateDiagram-v2`), read into the flowchart
+//! topology model.
+//!
+//! States are nodes, transitions are edges and composite states are
+//! subgraphs, so directives, the store and reconciliation treat a state
+//! diagram like a flowchart. [`StateKind`] tells the node kinds apart.
+
+use crate::parse::span::{LineIndex, Span};
+use crate::parse::topology::{TopoEdge, TopoNode, TopoSubgraph, Topology};
+use crate::types::{ArrowKind, Direction, EdgeStyle, LineKind, NotePlacement, StateDiagram, StateKind};
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Scope name for `[*]` states outside any composite state.
+const ROOT_SCOPE: &str = "root";
+
+/// Reads state diagram statements one at a time.
+pub(crate) struct StateStatements<'a> {
+    index: LineIndex<'a>,
+    /// A `note ... end note` block being read: its state, placement, first
+    /// line and text lines so far.
+    note: Option<(String, NotePlacement, &'a str, Vec<&'a str>)>,
+    diagram: StateDiagram,
+}
+
+impl<'a> StateStatements<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        Self {
+            index: LineIndex::new(input),
+            note: None,
+            diagram: StateDiagram::default(),
+        }
+    }
+
+    /// Apply a state diagram statement; `false` if it is not one, for the
+    /// shared styling statements.
+    ///
+    /// `open` holds indices into `topology.subgraphs` of the open composite states.
+    pub(crate) fn statement(&mut self, topology: &mut Topology, statement: &'a str, open: &mut Vec<usize>) -> bool {
+        let span = self.index.span_of(statement);
+
+        if let Some((state, placement, first, mut lines)) = self.note.take() {
+            if statement != "end note" {
+                lines.push(statement);
+                self.note = Some((state, placement, first, lines));
+                return true;
+            }
+            let start = self.index.span_of(first).start;
+            let span = self.index.span(start, span.end);
+            add_note(topology, &state, placement, lines.join("\n"), span, open);
+            return true;
+        }
+
+        if statement == "}" {
+            if let Some(current) = open.pop() {
+                topology.subgraphs[current].end_span = Some(span);
+                return true;
+            }
+        }
+
+        // The next state mentioned opens a concurrent region
+        if statement == "--" {
+            if let Some(&current) = open.last() {
+                let subgraph = &mut topology.subgraphs[current];
+                subgraph.regions.push(subgraph.members.len());
+                return true;
+            }
+        }
+
+        if let Some(direction) = statement.strip_prefix("direction ").and_then(Direction::parse) {
+            match open.last() {
+                Some(&current) => {
+                    topology.subgraphs[current].direction = Some(direction);
+                    topology.subgraphs[current].direction_span = Some(span);
+                }
+                None => topology.header.direction = Some(direction),
+            }
+            return true;
+        }
+
+        static COMPOSITE: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&COMPOSITE, r#"^state\s+(?:"([^"]*)"\s+as\s+)?([\w.]+)\s*\{$"#).captures(statement) {
+            let id = caps[2].to_string();
+            let mut subgraph = TopoSubgraph {
+                id: id.clone(),
+                title: caps.get(1).map(|m| m.as_str().to_string()),
+                span,
+                ..Default::default()
+            };
+            if let Some(&parent) = open.last() {
+                subgraph.parent = Some(topology.subgraphs[parent].id.clone());
+                topology.subgraphs[parent].subgraphs.push(id);
+            }
+            topology.subgraphs.push(subgraph);
+            open.push(topology.subgraphs.len() - 1);
+            return true;
+        }
+
+        static ALIAS: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&ALIAS, r#"^state\s+"([^"]*)"\s+as\s+([\w.]+)$"#).captures(statement) {
+            let node = state_node(&caps[2], Some(caps[1].to_string()), StateKind::State, span);
+            topology.visit_node(node, open);
+            return true;
+        }
+
+        static DECLARATION: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&DECLARATION, r"^state\s+([\w.]+)(?:\s*<<(choice|fork|join)>>)?$").captures(statement) {
+            let kind = match caps.get(2).map(|m| m.as_str()) {
+                Some("choice") => StateKind::Choice,
+                Some("fork") => StateKind::Fork,
+                Some("join") => StateKind::Join,
+                _ => StateKind::State,
+            };
+            topology.visit_node(state_node(&caps[1], None, kind, span), open);
+            return true;
+        }
+
+        static NOTE: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&NOTE, r"^note\s+(left|right)\s+of\s+([\w.]+)\s*(?::\s*(.*))?$").captures(statement) {
+            let placement = match &caps[1] {
+                "left" => NotePlacement::LeftOf,
+                _ => NotePlacement::RightOf,
+            };
+            match caps.get(3) {
+                Some(text) => add_note(topology, &caps[2], placement, text.as_str().trim().to_string(), span, open),
+                None => self.note = Some((caps[2].to_string(), placement, statement, Vec::new())),
+            }
+            return true;
+        }
+
+        // `[*]` or an ID, each with an optional `:::class`
+        static TRANSITION: OnceLock<Regex> = OnceLock::new();
+        let transition = regex(
+            &TRANSITION,
+            r"^(\[\*\]|[\w.]+)(?::::([\w\-]+))?\s*-->\s*(\[\*\]|[\w.]+)(?::::([\w\-]+))?\s*(?::\s*(.*))?$",
+        );
+        if let Some(caps) = transition.captures(statement) {
+            let scope = open.last().map_or(ROOT_SCOPE, |&current| &topology.subgraphs[current].id).to_string();
+            let mut source = endpoint_node(&caps[1], &scope, StateKind::Start, span);
+            let mut target = endpoint_node(&caps[3], &scope, StateKind::End, span);
+            source.classes.extend(caps.get(2).map(|m| m.as_str().to_string()));
+            target.classes.extend(caps.get(4).map(|m| m.as_str().to_string()));
+
+            topology.edges.push(TopoEdge {
+                source: source.id.clone(),
+                target: target.id.clone(),
+                label: caps.get(5).map(|m| m.as_str().trim().to_string()).filter(|l| !l.is_empty()),
+                span,
+                ..Default::default()
+            });
+            topology.visit_node(source, open);
+            topology.visit_node(target, open);
+            return true;
+        }
+
+        static DESCRIPTION: OnceLock<Regex> = OnceLock::new();
+        if let Some(caps) = regex(&DESCRIPTION, r"^([\w.]+)(?::::([\w\-]+))?\s*(?::\s*(.*))?$").captures(statement) {
+            if matches!(&caps[1], "classDef" | "class" | "style") {
+                return false;
+            }
+            // A description does not change what kind of state it is, nor
+            // its name
+            let id = caps[1].to_string();
+            let node = TopoNode {
+                id: id.clone(),
+                classes: caps.get(2).map(|m| m.as_str().to_string()).into_iter().collect(),
+                spans: vec![span],
+                ..Default::default()
+            };
+            topology.visit_node(node, open);
+            if let Some(text) = caps.get(3).map(|m| m.as_str().trim()).filter(|t| !t.is_empty()) {
+                self.diagram.descriptions.entry(id).or_default().push(text.to_string());
+            }
+            return true;
+        }
+
+        false
+    }
+
+    /// Hand the descriptions to the topology, keeping an unclosed note
+    /// block's lines as written.
+    pub(crate) fn finish(self, topology: &mut Topology, open: &[usize]) {
+        topology.state_diagram = Some(self.diagram);
+        if let Some((_, _, first, lines)) = self.note {
+            for line in std::iter::once(first).chain(lines) {
+                topology.keep_raw(line, self.index.span_of(line), open);
+            }
+        }
+    }
+}
+
+/// The regex in `cell`, compiled from `pattern` on first use.
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("valid state diagram pattern"))
+}
+
+fn state_node(id: &str, label: Option<String>, kind: StateKind, span: Span) -> TopoNode {
+    TopoNode {
+        id: id.to_string(),
+        label,
+        shape: Some(kind.shape()),
+        spans: vec![span],
+        ..Default::default()
+    }
+}
+
+/// A transition endpoint; `[*]` is the scope's start or end state.
+fn endpoint_node(id: &str, scope: &str, pseudo: StateKind, span: Span) -> TopoNode {
+    if id != "[*]" {
+        return TopoNode {
+            id: id.to_string(),
+            spans: vec![span],
+            ..Default::default()
+        };
+    }
+    let suffix = if pseudo == StateKind::Start { "start" } else { "end" };
+    state_node(&format!("{}_{}", scope, suffix), None, pseudo, span)
+}
+
+/// Add a note node and the dotted edge tying it to its state.
+fn add_note(topology: &mut Topology, state: &str, placement: NotePlacement, text: String, span: Span, open: &[usize]) {
+    let side = if placement == NotePlacement::LeftOf { "left" } else { "right" };
+    let base = format!("{}_note_{}", state, side);
+    let mut id = base.clone();
+    let mut n = 1;
+    while topology.nodes.iter().any(|node| node.id == id) {
+        n += 1;
+        id = format!("{}_{}", base, n);
+    }
+
+    topology.visit_node(state_node(&id, Some(text), StateKind::Note(placement), span), open);
+    topology.visit_node(
+        TopoNode {
+            id: state.to_string(),
+            spans: vec![span],
+            ..Default::default()
+        },
+        open,
+    );
+    topology.edges.push(TopoEdge {
+        source: id,
+        target: state.to_string(),
+        style: note_link(),
+        span,
+        ..Default::default()
+    });
+}
+
+/// The link between a note and its state.
+pub(crate) fn note_link() -> EdgeStyle {
+    EdgeStyle {
+        line: Some(LineKind::Dotted),
+        arrow: Some(ArrowKind::None),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::{parse_document, parse_mermaid_topology};
+    use crate::types::{DiagramKeyword, Direction, NotePlacement, StateKind};
+
+    #[test]
+    fn test_parse_state_diagram() {
+        let input = "stateDiagram-v2
+    direction LR
+    [*] --> Idle
+    state \"Waiting for input\" as Idle
+    Idle --> Running : start
+    state Running {
+        direction TB
+        [*] --> Loading
+        Loading --> Ready
+        Ready --> [*]
+    }
+    state check <<choice>>
+    Running --> check
+    check --> Done: ok
+    check --> Idle
+    note right of Done : all good
+    note left of Idle
+        waits here
+        for a while
+    end note
+    Done:::final --> [*]
+    classDef final fill:#9f9
+    hide empty description";
+
+        let topology = parse_mermaid_topology(input).unwrap();
+        assert_eq!(topology.header.keyword, DiagramKeyword::StateDiagramV2);
+        assert_eq!(topology.header.direction, Some(Direction::LeftRight));
+
+        let kinds: Vec<_> = topology
+            .nodes
+            .iter()
+            .map(|n| (n.id.as_str(), n.shape.as_ref().map(StateKind::of)))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("root_start", Some(StateKind::Start)),
+                ("Idle", Some(StateKind::State)),
+                ("Running", None),
+                ("Running_start", Some(StateKind::Start)),
+                ("Loading", None),
+                ("Ready", None),
+                ("Running_end", Some(StateKind::End)),
+                ("check", Some(StateKind::Choice)),
+                ("Done", None),
+                ("Done_note_right", Some(StateKind::Note(NotePlacement::RightOf))),
+                ("Idle_note_left", Some(StateKind::Note(NotePlacement::LeftOf))),
+                ("root_end", Some(StateKind::End)),
+            ]
+        );
+        assert_eq!(topology.nodes[1].label.as_deref(), Some("Waiting for input"));
+        assert_eq!(topology.nodes[10].label.as_deref(), Some("waits here\nfor a while"));
+        assert_eq!(topology.nodes[8].classes, ["final"]);
+
+        let running = &topology.subgraphs[0];
+        assert_eq!(running.id, "Running");
+        assert_eq!(running.direction, Some(Direction::TopBottom));
+        assert_eq!(running.members, ["Running_start", "Loading", "Ready", "Running_end"]);
+        assert!(running.end_span.is_some());
+
+        let labels: Vec<_> = topology.edges.iter().map(|e| e.label.as_deref()).collect();
+        assert_eq!(labels[1], Some("start"));
+        assert_eq!(labels[6], Some("ok"));
+        assert_eq!(topology.edges.len(), 11);
+        assert_eq!(topology.class_defs[0].name, "final");
+        assert_eq!(topology.raw_lines[0].line.text, "hide empty description");
+    }
+
+    #[test]
+    fn test_state_directives_merge_like_flowchart() {
+        let input = "stateDiagram-v2\n[*] --> A\nA --> B\n\n%% @node: root_start {\"uid\":\"n_start\",\"x\":10}\n%% @node: A {\"uid\":\"n_a\",\"x\":100}";
+        let parsed = parse_document(input).unwrap();
+
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(parsed.nodes[0].uid.0, "n_start");
+        assert_eq!(parsed.nodes[1].x, Some(100.0));
+        assert_eq!(parsed.edges[0].source.0, "n_start");
+    }
+}
@@ -1,87 +1,925 @@
 //! Mermaid topology parsing (nodes and edges from flowchart syntax).
 
-use crate::{Error, Result};
+use crate::types::{
+    Accessibility, ArrowKind, ClassDef, ClassDiagram, DiagramHeader, DiagramKeyword, Direction, EdgeStyle,
+    ErDiagram, LineKind, NodeLink, NodeShape, NodeStyle, RawLine, StateDiagram,
+};
+use crate::parse::directives::{
+    parse_ai_directive, parse_edge_directive, parse_node_directive, parse_participant_directive,
+    parse_schema_directive, DirectiveBlock,
+};
+use crate::parse::config::{frontmatter_len, parse_init_directive};
+use crate::parse::label::{decode_entities, decode_label, quoted_len};
+use crate::parse::class::ClassStatements;
+use crate::parse::er::ErStatements;
+use crate::parse::state::StateStatements;
+use crate::parse::span::{offset_in, LineIndex, Span};
+use crate::Result;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until},
+    bytes::complete::{tag, take_while1},
     character::complete::{alphanumeric1, char, space0},
-    combinator::{opt, recognize},
-    sequence::{delimited, pair},
+    combinator::{all_consuming, consumed, opt, recognize},
+    multi::separated_list1,
+    sequence::{delimited, pair, preceded, terminated},
     IResult,
 };
 
-/// Parse mermaid topology, returning (nodes, edges).
-/// Nodes: Vec<(mermaid_id, Option<label>)>
-/// Edges: Vec<(source_id, target_id, Option<label>)>
-pub fn parse_mermaid_topology(
-    input: &str,
-) -> Result<(Vec<(String, Option<String>)>, Vec<(String, String, Option<String>)>)> {
-    let mut nodes = Vec::new();
-    let mut edges = Vec::new();
-    let mut seen_nodes = std::collections::HashSet::new();
-
-    for line in input.lines() {
-        let trimmed = line.trim();
-        
-        // Skip empty lines, comments, and graph declarations
-        if trimmed.is_empty() 
-            || trimmed.starts_with("%%") 
-            || trimmed.starts_with("graph ")
-            || trimmed.starts_with("flowchart ")
-            || trimmed.starts_with("subgraph ")
-            || trimmed == "end"
-        {
+/// A node as it appears in the topology.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TopoNode {
+    pub id: String,
+    pub label: Option<String>,
+    /// Shape, if the occurrence declared one (bare references have none).
+    pub shape: Option<NodeShape>,
+    /// Classes from `class` statements and `:::` shorthand.
+    pub classes: Vec<String>,
+    /// Inline CSS from `style` statements.
+    pub style: Option<NodeStyle>,
+    /// Interaction from a `click` statement.
+    pub link: Option<NodeLink>,
+    /// Every declaration or reference in the source, in order.
+    pub spans: Vec<Span>,
+}
+
+/// An edge as it appears in the topology.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TopoEdge {
+    pub source: String,
+    pub target: String,
+    /// Mermaid edge ID, from `A e1@--> B`.
+    pub id: Option<String>,
+    pub label: Option<String>,
+    /// Link syntax (stroke, arrowheads, length).
+    pub style: EdgeStyle,
+    /// The statement that declares the edge.
+    pub span: Span,
+}
+
+/// A `subgraph ... end` block.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TopoSubgraph {
+    pub id: String,
+    pub title: Option<String>,
+    pub direction: Option<Direction>,
+    /// Node IDs that belong directly to this subgraph.
+    pub members: Vec<String>,
+    /// IDs of subgraphs nested directly inside this one.
+    pub subgraphs: Vec<String>,
+    /// ID of the enclosing subgraph, if nested.
+    pub parent: Option<String>,
+    /// Indices into `members` where a `--` line starts a new concurrent
+    /// region of a composite state.
+    pub regions: Vec<usize>,
+    /// The `subgraph` line.
+    pub span: Span,
+    /// The `direction` statement, if any.
+    pub direction_span: Option<Span>,
+    /// The closing `end`; `None` if the block is never closed.
+    pub end_span: Option<Span>,
+}
+
+/// A statement the parser could not handle, or a plain `%%` comment.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TopoRawLine {
+    pub line: RawLine,
+    pub span: Span,
+}
+
+/// Parsed mermaid topology.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Topology {
+    /// Header line; defaults to `graph TD` when the input has none.
+    pub header: DiagramHeader,
+    /// Where the header line is, if the input has one.
+    pub header_span: Option<Span>,
+    /// `accTitle` and `accDescr`.
+    pub accessibility: Accessibility,
+    /// The `accTitle` statement, if any.
+    pub acc_title_span: Option<Span>,
+    /// The `accDescr` statement or block, if any.
+    pub acc_descr_span: Option<Span>,
+    pub nodes: Vec<TopoNode>,
+    pub edges: Vec<TopoEdge>,
+    /// Subgraphs in declaration order (parents before children).
+    pub subgraphs: Vec<TopoSubgraph>,
+    /// `classDef` statements; a repeated name replaces the earlier definition.
+    pub class_defs: Vec<ClassDef>,
+    /// CSS from `linkStyle default`, applied to every link.
+    pub default_link_style: Option<EdgeStyle>,
+    /// `classDef`, `class`, `style`, `click` and `linkStyle` statements, in source order.
+    pub style_spans: Vec<Span>,
+    /// Unrecognised statements and comments, in source order.
+    pub raw_lines: Vec<TopoRawLine>,
+    /// The descriptions of a state diagram's states.
+    pub state_diagram: Option<StateDiagram>,
+    /// The class model of a `classDiagram`, whose classes are `nodes`.
+    pub class_diagram: Option<ClassDiagram>,
+    /// The entity model of an `erDiagram`, whose entities are `nodes`.
+    pub er_diagram: Option<ErDiagram>,
+}
+
+impl Topology {
+    /// Record a node occurrence, assigning it to the innermost open subgraph
+    /// unless it already belongs to one.
+    ///
+    /// A later occurrence with a shape redefines the label and shape, as in Mermaid.
+    pub(crate) fn visit_node(&mut self, node: TopoNode, open: &[usize]) {
+        let id = node.id.clone();
+
+        match self.nodes.iter_mut().find(|n| n.id == id) {
+            Some(existing) => {
+                if node.shape.is_some() {
+                    existing.label = node.label;
+                    existing.shape = node.shape;
+                }
+                add_classes(&mut existing.classes, node.classes);
+                existing.spans.extend(node.spans);
+            }
+            None => self.nodes.push(node),
+        }
+
+        if let Some(&current) = open.last() {
+            let assigned = self.subgraphs.iter().any(|s| s.members.contains(&id));
+            if !assigned {
+                self.subgraphs[current].members.push(id);
+            }
+        }
+    }
+
+    /// Keep a statement verbatim inside the innermost open subgraph.
+    pub(crate) fn keep_raw(&mut self, text: &str, span: Span, open: &[usize]) {
+        self.keep_raw_in(text, span, open, None);
+    }
+
+    /// Keep a statement verbatim inside the body of the class or entity `owner`.
+    pub(crate) fn keep_raw_in(&mut self, text: &str, span: Span, open: &[usize], owner: Option<&str>) {
+        let group = open.last().map(|&current| self.subgraphs[current].id.clone());
+        self.raw_lines.push(TopoRawLine {
+            line: RawLine {
+                text: text.to_string(),
+                group,
+                owner: owner.map(str::to_string),
+            },
+            span,
+        });
+    }
+
+    /// Apply `classDef names css`.
+    fn define_classes(&mut self, names: &str, css: &str) {
+        let style = NodeStyle::from_css(css);
+
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let def = ClassDef {
+                name: name.to_string(),
+                style: style.clone(),
+            };
+            match self.class_defs.iter_mut().find(|d| d.name == name) {
+                Some(existing) => *existing = def,
+                None => self.class_defs.push(def),
+            }
+        }
+    }
+
+    /// Apply `class ids name`; unknown node IDs are ignored.
+    fn assign_class(&mut self, ids: &str, name: &str) {
+        for id in ids.split(',').map(str::trim) {
+            if let Some(node) = self.nodes.iter_mut().find(|n| n.id == id) {
+                add_classes(&mut node.classes, vec![name.to_string()]);
+            }
+        }
+    }
+
+    /// Apply `linkStyle targets css`, where targets is `default` or a comma list
+    /// of edge indices in declaration order. Returns `false`, applying
+    /// nothing, if an index is not a number or has no edge.
+    fn apply_link_style(&mut self, targets: &str, css: &str) -> bool {
+        let merge = |existing: &EdgeStyle| {
+            EdgeStyle::from_css(&format!("{},{}", existing.to_css(), css))
+        };
+
+        if targets == "default" {
+            let existing = self.default_link_style.clone().unwrap_or_default();
+            self.default_link_style = Some(merge(&existing));
+            return true;
+        }
+
+        let indices: Vec<Option<usize>> = targets
+            .split(',')
+            .map(|i| i.trim().parse::<usize>().ok().filter(|&i| i < self.edges.len()))
+            .collect();
+        if indices.contains(&None) {
+            return false;
+        }
+        for index in indices.into_iter().flatten() {
+            let edge = &mut self.edges[index];
+            let merged = merge(&edge.style);
+            edge.style.set_css(&merged);
+        }
+        true
+    }
+
+    /// Apply `style id css`, merging with earlier `style` statements for the node.
+    fn apply_style(&mut self, id: &str, css: &str) {
+        if !self.nodes.iter().any(|n| n.id == id) {
+            self.nodes.push(TopoNode {
+                id: id.to_string(),
+                ..Default::default()
+            });
+        }
+
+        let node = self.nodes.iter_mut().find(|n| n.id == id).expect("node inserted above");
+        let merged = match &node.style {
+            Some(existing) => format!("{},{}", existing.to_css(), css),
+            None => css.to_string(),
+        };
+        node.style = Some(NodeStyle::from_css(&merged));
+    }
+}
+
+/// Append classes not already present.
+fn add_classes(classes: &mut Vec<String>, new: Vec<String>) {
+    for class in new {
+        if !classes.contains(&class) {
+            classes.push(class);
+        }
+    }
+}
+
+/// Split a styling statement (`classDef`, `class`, `style`) into its two arguments.
+fn parse_style_statement<'a>(line: &'a str, keyword: &str) -> Option<(&'a str, &'a str)> {
+    let rest = line.strip_prefix(keyword)?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let rest = rest.trim().trim_end_matches(';');
+    let (target, value) = rest.split_once(char::is_whitespace)?;
+    Some((target, value.trim()))
+}
+
+/// Statement readers for diagrams with their own model.
+enum ModelStatements<'a> {
+    Class(ClassStatements<'a>),
+    Er(ErStatements<'a>),
+}
+
+impl ModelStatements<'_> {
+    /// The class or entity whose body is being read.
+    fn owner(&self) -> Option<&str> {
+        match self {
+            ModelStatements::Class(class) => class.owner(),
+            ModelStatements::Er(er) => er.owner(),
+        }
+    }
+}
+
+/// Parse mermaid topology into nodes, edges and subgraphs.
+///
+/// State diagrams are read into the same model; see [`StateKind`](crate::types::StateKind).
+/// Class and ER diagrams are read into `class_diagram` or `er_diagram`, with
+/// a node per class or entity and an edge per relationship.
+/// `%% @node`/`%% @edge` directives, including multi-line blocks, `%%{init}%%`
+/// lines and the frontmatter are skipped, so a whole document can be passed
+/// to get document-relative spans.
+/// Statements that cannot be parsed, and other `%%` lines, are kept in
+/// `raw_lines` instead of being dropped.
+pub fn parse_mermaid_topology(input: &str) -> Result<Topology> {
+    let mut topology = Topology::default();
+    let index = LineIndex::new(input);
+    // Indices into `topology.subgraphs` for the currently open blocks.
+    let mut open: Vec<usize> = Vec::new();
+
+    let mut seen_header = false;
+    // `linkStyle` indices refer to the final edge order, so apply them last;
+    // each keeps its text, span and subgraph in case it has to stay raw
+    let mut link_styles: Vec<(&str, &str, TopoRawLine)> = Vec::new();
+    // `click` may precede the node's declaration
+    let mut clicks: Vec<(&str, NodeLink)> = Vec::new();
+    // An open multi-line directive and its lines so far
+    let mut pending: Option<(DirectiveBlock, Vec<&str>)> = None;
+    let body_start = frontmatter_len(input).unwrap_or(0);
+    // Set once the header opens a state diagram
+    let mut state: Option<StateStatements> = None;
+    // Set once the header opens a class or ER diagram, which have no
+    // statements in common with flowcharts
+    let mut model: Option<ModelStatements> = None;
+
+    for statement in split_statements(input) {
+        let trimmed = statement.trim();
+
+        if trimmed.is_empty() || offset_in(input, statement) < body_start {
+            continue;
+        }
+
+        if trimmed.starts_with("%%") {
+            if let Some((block, lines)) = pending.as_mut() {
+                lines.push(trimmed);
+                if block.push(trimmed) {
+                    let directive = is_directive(block.text());
+                    let lines = std::mem::take(lines);
+                    pending = None;
+                    if !directive {
+                        let owner = model.as_ref().and_then(ModelStatements::owner);
+                        for line in lines {
+                            topology.keep_raw_in(line, index.span_of(line), &open, owner);
+                        }
+                    }
+                }
+                continue;
+            }
+            if let Some(block) = DirectiveBlock::start(trimmed) {
+                pending = Some((block, vec![trimmed]));
+                continue;
+            }
+
+            if !is_directive(trimmed) {
+                let owner = model.as_ref().and_then(ModelStatements::owner);
+                topology.keep_raw_in(trimmed, index.span_of(trimmed), &open, owner);
+            }
+            continue;
+        }
+
+        // A block cut short by a statement is just comments
+        if let Some((_, lines)) = pending.take() {
+            let owner = model.as_ref().and_then(ModelStatements::owner);
+            for line in lines {
+                topology.keep_raw_in(line, index.span_of(line), &open, owner);
+            }
+        }
+
+        if !seen_header {
+            if let Some(header) = DiagramHeader::parse(trimmed) {
+                topology.header = header;
+                topology.header_span = Some(index.span_of(trimmed));
+                seen_header = true;
+                match header.keyword {
+                    keyword if keyword.is_state() => state = Some(StateStatements::new(input)),
+                    DiagramKeyword::ClassDiagram => model = Some(ModelStatements::Class(ClassStatements::new(input))),
+                    DiagramKeyword::ErDiagram => model = Some(ModelStatements::Er(ErStatements::new(input))),
+                    _ => {}
+                }
+                continue;
+            }
+        }
+
+        if let Some(title) = parse_acc_statement(trimmed, "accTitle") {
+            topology.accessibility.title = Some(title);
+            topology.acc_title_span = Some(index.span_of(trimmed));
+            continue;
+        }
+
+        if let Some(description) = parse_acc_statement(trimmed, "accDescr") {
+            topology.accessibility.description = Some(description);
+            topology.acc_descr_span = Some(index.span_of(trimmed));
+            continue;
+        }
+
+        // An `accDescr {` block that is never closed
+        if acc_statement_len(trimmed).is_some() {
+            topology.keep_raw(trimmed, index.span_of(trimmed), &open);
+            continue;
+        }
+
+        match model.as_mut() {
+            Some(ModelStatements::Class(class)) => {
+                class.statement(&mut topology, trimmed, &mut open);
+                continue;
+            }
+            Some(ModelStatements::Er(er)) => {
+                er.statement(&mut topology, trimmed);
+                continue;
+            }
+            None => {}
+        }
+
+        if let Some(state) = state.as_mut() {
+            if state.statement(&mut topology, trimmed, &mut open) {
+                continue;
+            }
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("subgraph") {
+            if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                let mut subgraph = parse_subgraph_header(rest.trim());
+                subgraph.span = index.span_of(trimmed);
+                if let Some(&parent) = open.last() {
+                    subgraph.parent = Some(topology.subgraphs[parent].id.clone());
+                    let id = subgraph.id.clone();
+                    topology.subgraphs[parent].subgraphs.push(id);
+                }
+                topology.subgraphs.push(subgraph);
+                open.push(topology.subgraphs.len() - 1);
+                continue;
+            }
+        }
+
+        if trimmed == "end" {
+            if let Some(current) = open.pop() {
+                topology.subgraphs[current].end_span = Some(index.span_of(trimmed));
+                continue;
+            }
+        }
+
+        if let Some(direction) = trimmed.strip_prefix("direction ") {
+            if let (Some(&current), Some(direction)) = (open.last(), Direction::parse(direction)) {
+                topology.subgraphs[current].direction = Some(direction);
+                topology.subgraphs[current].direction_span = Some(index.span_of(trimmed));
+                continue;
+            }
+        }
+
+        if let Some((names, css)) = parse_style_statement(trimmed, "classDef") {
+            topology.define_classes(names, css);
+            topology.style_spans.push(index.span_of(trimmed));
+            continue;
+        }
+
+        if let Some((ids, name)) = parse_style_statement(trimmed, "class") {
+            topology.assign_class(ids, name);
+            topology.style_spans.push(index.span_of(trimmed));
+            continue;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("click") {
+            if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                if let Some(click) = parse_click_statement(rest.trim()) {
+                    clicks.push(click);
+                    topology.style_spans.push(index.span_of(trimmed));
+                    continue;
+                }
+            }
+        }
+
+        if let Some((targets, css)) = parse_style_statement(trimmed, "linkStyle") {
+            let raw = TopoRawLine {
+                line: RawLine {
+                    text: trimmed.to_string(),
+                    group: open.last().map(|&current| topology.subgraphs[current].id.clone()),
+                    owner: None,
+                },
+                span: index.span_of(trimmed),
+            };
+            topology.style_spans.push(raw.span);
+            link_styles.push((targets, css, raw));
+            continue;
+        }
+
+        if let Some((id, css)) = parse_style_statement(trimmed, "style") {
+            topology.apply_style(id, css);
+            topology.style_spans.push(index.span_of(trimmed));
+            continue;
+        }
+
+        // A keyword whose arguments did not parse is not a node
+        if state.is_some() || starts_with_keyword(trimmed) {
+            topology.keep_raw(trimmed, index.span_of(trimmed), &open);
             continue;
         }
 
         // Try to parse as edge first
-        if let Ok((_, edge_data)) = parse_edge_line(trimmed) {
-            let (src_id, src_label, tgt_id, tgt_label, edge_label) = edge_data;
-            
-            // Add source node if not seen
-            if !seen_nodes.contains(&src_id) {
-                seen_nodes.insert(src_id.clone());
-                nodes.push((src_id.clone(), src_label));
-            }
-            
-            // Add target node if not seen
-            if !seen_nodes.contains(&tgt_id) {
-                seen_nodes.insert(tgt_id.clone());
-                nodes.push((tgt_id.clone(), tgt_label));
-            }
-            
-            edges.push((src_id, tgt_id, edge_label));
+        if let Ok((_, statement)) = all_consuming(parse_edge_line)(trimmed) {
+            topology.edges.extend(statement.expand(index.span_of(trimmed)));
+            for (source, mut node) in statement.groups.into_iter().flatten() {
+                node.spans.push(index.span_of(source));
+                topology.visit_node(node, &open);
+            }
             continue;
         }
 
         // Try to parse as node declaration
-        if let Ok((_, (id, label))) = parse_node_declaration(trimmed) {
-            if !seen_nodes.contains(&id) {
-                seen_nodes.insert(id.clone());
-                nodes.push((id, label));
+        if let Ok((_, (source, mut node))) = all_consuming(consumed(parse_node_declaration))(trimmed) {
+            node.spans.push(index.span_of(source));
+            topology.visit_node(node, &open);
+            continue;
+        }
+
+        topology.keep_raw(trimmed, index.span_of(trimmed), &open);
+    }
+
+    if let Some((_, lines)) = pending {
+        for line in lines {
+            topology.keep_raw(line, index.span_of(line), &open);
+        }
+    }
+    if let Some(state) = state {
+        state.finish(&mut topology, &open);
+    }
+    match model {
+        Some(ModelStatements::Class(class)) => class.finish(&mut topology),
+        Some(ModelStatements::Er(er)) => er.finish(&mut topology),
+        None => {}
+    }
+
+    // A `linkStyle` naming edges that do not exist is kept as written
+    for (targets, css, raw) in link_styles {
+        if !topology.apply_link_style(targets, css) {
+            topology.style_spans.retain(|span| *span != raw.span);
+            let at = topology.raw_lines.partition_point(|r| r.span.start < raw.span.start);
+            topology.raw_lines.insert(at, raw);
+        }
+    }
+
+    for (id, link) in clicks {
+        if let Some(node) = topology.nodes.iter_mut().find(|n| n.id == id) {
+            node.link = Some(link);
+        }
+    }
+
+    Ok(topology)
+}
+
+/// Whether a `%%` line is a `@node`, `@edge`, `@ai`, `@participant` or
+/// `@mermaidman` directive or a valid `%%{init}%%` line, which the document
+/// parser handles.
+pub(crate) fn is_directive(line: &str) -> bool {
+    parse_node_directive(line).is_some()
+        || parse_edge_directive(line).is_some()
+        || parse_ai_directive(line).is_some()
+        || parse_participant_directive(line).is_some()
+        || parse_schema_directive(line).is_some()
+        || parse_init_directive(line).is_some_and(|config| config.is_ok())
+}
+
+/// Statement keywords that cannot start a node or edge statement.
+const KEYWORDS: &[&str] = &[
+    "subgraph", "end", "direction", "classDef", "class", "click", "linkStyle", "style",
+];
+
+/// Whether the statement's first word is a keyword.
+fn starts_with_keyword(statement: &str) -> bool {
+    let word = statement
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()
+        .unwrap_or_default();
+    KEYWORDS.contains(&word)
+}
+
+/// Split topology text into statements at newlines and `;`.
+///
+/// Quoted strings may span lines and contain `;`, a `;` closing an entity code
+/// (`#quot;`) does not end the statement, and `%%` comment lines are kept whole.
+fn split_statements(input: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < input.len() {
+        let rest = &input[i..];
+        let at_statement_start = input[start..i].trim().is_empty();
+
+        if rest.starts_with("%%") && at_statement_start {
+            let end = rest.find('\n').map_or(input.len(), |n| i + n);
+            statements.push(&input[start..end]);
+            start = end + 1;
+            i = start;
+            continue;
+        }
+
+        if at_statement_start {
+            if let Some(len) = acc_statement_len(rest) {
+                statements.push(&input[start..i + len]);
+                start = i + len;
+                i = start;
+                continue;
+            }
+        }
+
+        if rest.starts_with('"') {
+            // An unterminated quote is treated as a plain character
+            if let Some(len) = quoted_len(rest) {
+                i += len;
+                continue;
             }
         }
+
+        let split = match rest.as_bytes()[0] {
+            b'\n' => true,
+            b';' => !ends_with_entity_name(&input[start..i]),
+            _ => false,
+        };
+        if split {
+            statements.push(&input[start..i]);
+            start = i + 1;
+        }
+
+        i += rest.chars().next().map_or(1, char::len_utf8);
     }
 
-    Ok((nodes, edges))
+    if start < input.len() {
+        statements.push(&input[start..]);
+    }
+
+    statements
 }
 
-/// Parse a node declaration: `A[Label]` or `A((Label))` or `A{Label}` etc.
-fn parse_node_declaration(input: &str) -> IResult<&str, (String, Option<String>)> {
+/// Length of the `accTitle:`/`accDescr:` statement (to the end of its line)
+/// or `accDescr { ... }` block (to its closing brace) that `rest` starts with.
+///
+/// The closing brace is looked for before the first directive line; a block
+/// left open ends there, or at the end of the input, without one.
+fn acc_statement_len(rest: &str) -> Option<usize> {
+    let keyword = ["accTitle", "accDescr"].into_iter().find(|k| rest.starts_with(k))?;
+    let body = rest[keyword.len()..].trim_start_matches([' ', '\t']);
+    let offset = rest.len() - body.len();
+
+    if body.starts_with(':') {
+        Some(rest.find('\n').unwrap_or(rest.len()))
+    } else if body.starts_with('{') {
+        let mut end = body.len();
+        let mut line_start = 0;
+        for line in body.split_inclusive('\n') {
+            if line_start > 0 && is_directive(line.trim()) {
+                end = line_start;
+                break;
+            }
+            line_start += line.len();
+        }
+        let block = &body[..end];
+        Some(offset + block.find('}').map_or(block.trim_end().len(), |pos| pos + 1))
+    } else {
+        None
+    }
+}
+
+/// Parse `keyword: text` or `keyword { lines }`; block lines are trimmed and
+/// blank lines around them dropped.
+fn parse_acc_statement(statement: &str, keyword: &str) -> Option<String> {
+    let body = statement.strip_prefix(keyword)?.trim_start();
+    if let Some(text) = body.strip_prefix(':') {
+        return Some(text.trim().to_string());
+    }
+
+    let block = body.strip_prefix('{')?.strip_suffix('}')?;
+    let lines: Vec<&str> = block.lines().map(str::trim).collect();
+    let first = lines.iter().position(|l| !l.is_empty()).unwrap_or(lines.len());
+    let last = lines.iter().rposition(|l| !l.is_empty()).map_or(first, |n| n + 1);
+    Some(lines[first..last].join("\n"))
+}
+
+/// Whether `text` ends with `#name`, so a following `;` closes an entity code.
+fn ends_with_entity_name(text: &str) -> bool {
+    text.rfind('#').is_some_and(|pos| {
+        let name = &text[pos + 1..];
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// Parse the remainder of a `click` line.
+///
+/// Accepts `id href "url" ["tooltip"] [target]`, `id "url" ["tooltip"] [target]`,
+/// `id callback ["tooltip"]` and `id call callback(args) ["tooltip"]`.
+fn parse_click_statement(rest: &str) -> Option<(&str, NodeLink)> {
+    let rest = rest.trim_end_matches(';');
+    let (rest, id) = parse_node_id(rest).ok()?;
+    let rest = rest.trim_start();
+    let mut link = NodeLink::default();
+
+    let args = if let Some(call) = rest.strip_prefix("call ") {
+        let call = call.trim_start();
+        let end = call.find(')')? + 1;
+        link.callback = Some(call[..end].to_string());
+        split_click_args(&call[end..])
+    } else {
+        let mut args = split_click_args(rest);
+        if args.first() == Some(&("href", false)) {
+            args.remove(0);
+        }
+        match args.first() {
+            Some(&(href, true)) => link.href = Some(decode_entities(href)),
+            Some(&(callback, false)) => link.callback = Some(callback.to_string()),
+            None => return None,
+        }
+        args.remove(0);
+        args
+    };
+
+    for (arg, quoted) in args {
+        if quoted && link.tooltip.is_none() {
+            link.tooltip = Some(decode_entities(arg));
+        } else if !quoted && link.href.is_some() {
+            link.target = Some(arg.to_string());
+        }
+    }
+
+    Some((id, link))
+}
+
+/// Split `click` arguments on whitespace, keeping quoted strings whole.
+///
+/// Each argument is returned with whether it was quoted.
+fn split_click_args(input: &str) -> Vec<(&str, bool)> {
+    let mut args = Vec::new();
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            args.push((&quoted[..end], true));
+            rest = quoted.get(end + 1..).unwrap_or("");
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            args.push((&rest[..end], false));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+
+    args
+}
+
+/// Parse the remainder of a `subgraph` line.
+///
+/// Accepts `id`, `id [Title]`, `id["Title"]`, `"Title"` and bare `Title with spaces`.
+fn parse_subgraph_header(rest: &str) -> TopoSubgraph {
+    let mut subgraph = TopoSubgraph::default();
+
+    if let Some(quoted) = rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')) {
+        subgraph.id = decode_entities(quoted);
+        return subgraph;
+    }
+
+    if let Ok((after, id)) = parse_node_id(rest) {
+        let after = after.trim();
+        if after.is_empty() {
+            subgraph.id = id.to_string();
+            return subgraph;
+        }
+        if let Some(title) = after.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            subgraph.id = id.to_string();
+            subgraph.title = Some(decode_label(title));
+            return subgraph;
+        }
+    }
+
+    subgraph.id = rest.to_string();
+    subgraph
+}
+
+/// Bracket delimiters for classic node shapes.
+///
+/// Longer openings come first so `((` wins over `(`; entries sharing an
+/// opening are disambiguated by whichever closing appears first.
+const SHAPE_DELIMITERS: &[(&str, &str, NodeShape)] = &[
+    ("(((", ")))", NodeShape::DoubleCircle),
+    ("((", "))", NodeShape::Circle),
+    ("([", "])", NodeShape::Stadium),
+    ("(", ")", NodeShape::Rounded),
+    ("[[", "]]", NodeShape::Subroutine),
+    ("[(", ")]", NodeShape::Cylinder),
+    ("[/", "/]", NodeShape::Parallelogram),
+    ("[/", "\\]", NodeShape::Trapezoid),
+    ("[\\", "\\]", NodeShape::ParallelogramAlt),
+    ("[\\", "/]", NodeShape::TrapezoidAlt),
+    ("[", "]", NodeShape::Rect),
+    ("{{", "}}", NodeShape::Hexagon),
+    ("{", "}", NodeShape::Rhombus),
+    (">", "]", NodeShape::Asymmetric),
+];
+
+/// Parse a node declaration: `A[Label]`, `A((Label))`, `A{Label}`, `A@{ shape: diam }` etc.,
+/// optionally followed by `:::className`.
+fn parse_node_declaration(input: &str) -> IResult<&str, TopoNode> {
+    let (input, mut node) = parse_node_shape(input)?;
+
+    let (input, class) = opt(preceded(
+        tag(":::"),
+        take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-'),
+    ))(input)?;
+    if let Some(class) = class {
+        node.classes.push(class.to_string());
+    }
+
+    Ok((input, node))
+}
+
+/// Parse a node ID with its optional shape.
+fn parse_node_shape(input: &str) -> IResult<&str, TopoNode> {
     let (input, id) = parse_node_id(input)?;
-    let (input, label) = opt(alt((
-        delimited(tag("["), take_until("]"), tag("]")),
-        delimited(tag("(("), take_until("))"), tag("))")),
-        delimited(tag("(["), take_until("])"), tag("])")),
-        delimited(tag("("), take_until(")"), tag(")")),
-        delimited(tag("{"), take_until("}"), tag("}")),
-        delimited(tag("{{"), take_until("}}"), tag("}}")),
-        delimited(tag(">"), take_until("]"), tag("]")),
-        delimited(tag("[/"), take_until("/]"), tag("/]")),
-        delimited(tag("[\\"), take_until("\\]"), tag("\\]")),
-    )))(input)?;
+    let mut node = TopoNode {
+        id: id.to_string(),
+        ..Default::default()
+    };
+
+    if let Some(body) = input.strip_prefix("@{") {
+        let Some(end) = shape_data_end(body) else {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::TakeUntil,
+            )));
+        };
+        let (shape, label) = parse_shape_data(&body[..end]);
+        node.shape = Some(shape.unwrap_or_default());
+        node.label = label;
+        return Ok((&body[end + 1..], node));
+    }
 
-    Ok((input, (id.to_string(), label.map(|s| s.to_string()))))
+    if let Some((rest, label, shape)) = parse_shape_delimiters(input) {
+        node.label = Some(decode_label(label));
+        node.shape = Some(shape);
+        return Ok((rest, node));
+    }
+
+    Ok((input, node))
+}
+
+/// Match the first applicable shape delimiter pair at the start of `input`.
+///
+/// Returns the rest, the raw label text (still quoted, if it was) and the shape.
+/// A quoted label may contain any of the closing delimiters.
+fn parse_shape_delimiters(input: &str) -> Option<(&str, &str, NodeShape)> {
+    let mut tried_open = "";
+
+    for (open, _, _) in SHAPE_DELIMITERS {
+        if *open == tried_open || !input.starts_with(open) {
+            continue;
+        }
+        tried_open = open;
+
+        let body = &input[open.len()..];
+        let candidates = SHAPE_DELIMITERS.iter().filter(|(o, _, _)| o == open);
+
+        let trimmed = body.trim_start();
+        if let Some(len) = quoted_len(trimmed) {
+            let end = body.len() - trimmed.len() + len;
+            let after = body[end..].trim_start();
+            let closing = candidates.clone().find(|(_, close, _)| after.starts_with(close));
+            if let Some((_, close, shape)) = closing {
+                return Some((&after[close.len()..], &body[..end], shape.clone()));
+            }
+        }
+
+        let best = candidates
+            .filter_map(|(_, close, shape)| body.find(close).map(|pos| (pos, *close, shape)))
+            .min_by_key(|(pos, _, _)| *pos);
+
+        if let Some((pos, close, shape)) = best {
+            return Some((&body[pos + close.len()..], &body[..pos], shape.clone()));
+        }
+    }
+
+    None
+}
+
+/// Parse the body of `@{ shape: name, label: "text" }`.
+fn parse_shape_data(body: &str) -> (Option<NodeShape>, Option<String>) {
+    let mut shape = None;
+    let mut label = None;
+
+    for (key, value) in split_shape_data(body) {
+        match key {
+            "shape" => shape = Some(NodeShape::from_name(value)),
+            "label" => label = Some(decode_entities(value)),
+            _ => {}
+        }
+    }
+
+    (shape, label)
+}
+
+/// Position of the `}` closing a `@{ ... }` body, skipping quoted values.
+fn shape_data_end(body: &str) -> Option<usize> {
+    let mut in_quotes = false;
+    body.char_indices().find_map(|(i, c)| {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '}' if !in_quotes => return Some(i),
+            _ => {}
+        }
+        None
+    })
+}
+
+/// Split `key: value` pairs on commas outside double quotes, unquoting values.
+fn split_shape_data(body: &str) -> Vec<(&str, &str)> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (i, c) in body.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                parts.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&body[start..]);
+
+    parts
+        .into_iter()
+        .filter_map(|part| {
+            let (key, value) = part.split_once(':')?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((key.trim(), value))
+        })
+        .collect()
 }
 
 /// Parse a node ID (alphanumeric + underscore).
@@ -92,54 +930,273 @@ fn parse_node_id(input: &str) -> IResult<&str, &str> {
     ))(input)
 }
 
-/// Parse an edge line: `A --> B` or `A[Label] --> B[Label]` etc.
-fn parse_edge_line(
-    input: &str,
-) -> IResult<&str, (String, Option<String>, String, Option<String>, Option<String>)> {
-    let (input, (src_id, src_label)) = parse_node_declaration(input)?;
-    let (input, _) = space0(input)?;
+/// An edge statement: `&`-groups of nodes joined by links.
+///
+/// `A & B --> C --> D` has groups `[[A, B], [C], [D]]` and two links.
+/// Each node keeps the source text it was parsed from.
+#[derive(Debug, Clone, PartialEq)]
+struct EdgeStatement<'a> {
+    groups: Vec<Vec<(&'a str, TopoNode)>>,
+    /// Link ID, style and label; `links[i]` joins `groups[i]` to `groups[i + 1]`.
+    links: Vec<(Option<&'a str>, EdgeStyle, Option<String>)>,
+}
+
+impl EdgeStatement<'_> {
+    /// Expand chains and fan-out into individual edges, all spanning the statement.
+    fn expand(&self, span: Span) -> Vec<TopoEdge> {
+        let mut edges = Vec::new();
 
-    // Parse arrow with optional label
-    let (input, edge_label) = parse_arrow_with_label(input)?;
-    let (input, _) = space0(input)?;
+        for (i, (id, style, label)) in self.links.iter().enumerate() {
+            for (_, src) in &self.groups[i] {
+                for (_, tgt) in &self.groups[i + 1] {
+                    edges.push(TopoEdge {
+                        source: src.id.clone(),
+                        target: tgt.id.clone(),
+                        id: id.map(str::to_string),
+                        label: label.clone(),
+                        style: style.clone(),
+                        span,
+                    });
+                }
+            }
+        }
 
-    let (input, (tgt_id, tgt_label)) = parse_node_declaration(input)?;
+        edges
+    }
+}
 
-    Ok((input, (src_id, src_label, tgt_id, tgt_label, edge_label)))
+/// Parse `A & B[Label] & C`.
+fn parse_node_group(input: &str) -> IResult<&str, Vec<(&str, TopoNode)>> {
+    separated_list1(
+        delimited(space0, char('&'), space0),
+        consumed(parse_node_declaration),
+    )(input)
 }
 
-/// Parse arrow types with optional labels.
-fn parse_arrow_with_label(input: &str) -> IResult<&str, Option<String>> {
+/// Parse an edge line: `A --> B`, `A --> B --> C`, `A & B --> C & D`, `A e1@--> B` etc.
+fn parse_edge_line(input: &str) -> IResult<&str, EdgeStatement<'_>> {
+    let (mut input, first) = parse_node_group(input)?;
+    let mut statement = EdgeStatement {
+        groups: vec![first],
+        links: Vec::new(),
+    };
+
+    loop {
+        // Parse arrow with optional edge ID and label
+        let Ok((rest, (id, (style, label)))) = preceded(
+            space0,
+            pair(opt(terminated(parse_node_id, char('@'))), parse_arrow_with_label),
+        )(input) else {
+            break;
+        };
+        let (rest, group) = preceded(space0, parse_node_group)(rest)?;
+
+        statement.links.push((id, style, label));
+        statement.groups.push(group);
+        input = rest;
+    }
+
+    if statement.links.is_empty() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Tag,
+        )));
+    }
+
+    Ok((input, statement))
+}
+
+/// Parse a link with an optional label: `-->`, `-.->|text|`, `<==>`, `--o` etc.
+fn parse_arrow_with_label(input: &str) -> IResult<&str, (EdgeStyle, Option<String>)> {
     // Arrow with label: --|text|-->
     if let Ok((rest, _)) = tag::<&str, &str, nom::error::Error<&str>>("--")(input) {
-        if let Ok((rest, label)) = delimited(
-            char::<&str, nom::error::Error<&str>>('|'),
-            take_until("|"),
-            char('|'),
-        )(rest)
-        {
+        if let Ok((rest, label)) = parse_pipe_label(rest) {
             if let Ok((rest, _)) = tag::<&str, &str, nom::error::Error<&str>>("-->")(rest) {
-                return Ok((rest, Some(label.to_string())));
+                return Ok((rest, (EdgeStyle::default(), Some(label))));
+            }
+        }
+    }
+
+    // Inline text: -- text -->, -. text .->, == text ==>
+    if let Some(link) = parse_text_link(input) {
+        return Ok(link);
+    }
+
+    let (rest, style) = parse_link(input)?;
+
+    // Pipe label after the link: -->|text|
+    let (rest, label) = opt(preceded(space0, parse_pipe_label))(rest)?;
+
+    Ok((rest, (style, label)))
+}
+
+/// Parse a pipe label, `|text|` or `|"quoted | text"|`, decoding it.
+fn parse_pipe_label(input: &str) -> IResult<&str, String> {
+    let (body, _) = char('|')(input)?;
+
+    let trimmed = body.trim_start();
+    let label_end = quoted_len(trimmed)
+        .map(|len| body.len() - trimmed.len() + len)
+        .filter(|&end| body[end..].trim_start().starts_with('|'))
+        .or_else(|| body.find('|'));
+    let Some(label_end) = label_end else {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            body,
+            nom::error::ErrorKind::TakeUntil,
+        )));
+    };
+
+    let close = label_end + body[label_end..].find('|').unwrap_or(0);
+    Ok((&body[close + 1..], decode_label(&body[..label_end])))
+}
+
+/// Parse a link whose label sits inside it: `-- text -->`, `<-. text .->`, `== text ===`.
+fn parse_text_link(input: &str) -> Option<(&str, (EdgeStyle, Option<String>))> {
+    let (start, after_head) = match input.as_bytes() {
+        [b'<', b'-' | b'=', ..] => (Some(ArrowKind::Default), &input[1..]),
+        [b'o', b'-' | b'=', ..] => (Some(ArrowKind::Circle), &input[1..]),
+        [b'x', b'-' | b'=', ..] => (Some(ArrowKind::Cross), &input[1..]),
+        _ => (None, input),
+    };
+
+    let (line, text) = if let Some(text) = after_head.strip_prefix("--") {
+        (LineKind::Normal, text)
+    } else if let Some(text) = after_head.strip_prefix("-.") {
+        (LineKind::Dotted, text)
+    } else if let Some(text) = after_head.strip_prefix("==") {
+        (LineKind::Thick, text)
+    } else {
+        return None;
+    };
+
+    // The text must be set apart from the opening, otherwise this is a plain link
+    if !text.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let mut in_quotes = false;
+    for (i, c) in text.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        if in_quotes {
+            continue;
+        }
+
+        let closing = &text[i..];
+        let tail = match (line, c) {
+            (LineKind::Dotted, '.') => parse_dotted_tail(closing),
+            (LineKind::Normal, '-') | (LineKind::Thick, '=') => parse_link(closing)
+                .ok()
+                .filter(|(_, style)| style.line_kind() == line && style.arrow_start.is_none()),
+            _ => None,
+        };
+
+        if let Some((rest, mut style)) = tail {
+            let label = text[..i].trim();
+            if label.is_empty() {
+                return None;
             }
+            style.arrow_start = start;
+            return Some((rest, (style, Some(decode_label(label)))));
         }
     }
 
-    // Simple arrows (no label)
-    let arrows = [
-        "-->", "---", "-.-", "-.->", "==>", "==",
-        "-.->", "~~>", "--o", "--x", "<-->", "o--o",
-    ];
+    None
+}
+
+/// Parse the closing half of a dotted text link: `.->`, `.-`, `..->`.
+fn parse_dotted_tail(input: &str) -> Option<(&str, EdgeStyle)> {
+    let dots = input.bytes().take_while(|&b| b == b'.').count();
+    let rest = input[dots..].strip_prefix('-')?;
+
+    let (rest, end) = match rest.as_bytes().first() {
+        Some(b'>') => (&rest[1..], ArrowKind::Default),
+        Some(b'o') => (&rest[1..], ArrowKind::Circle),
+        Some(b'x') => (&rest[1..], ArrowKind::Cross),
+        _ => (rest, ArrowKind::None),
+    };
+
+    let style = EdgeStyle {
+        dashed: Some(true),
+        arrow: (end != ArrowKind::Default).then_some(end),
+        line: Some(LineKind::Dotted),
+        length: (dots != 1).then_some(dots as u8),
+        ..Default::default()
+    };
+
+    Some((rest, style))
+}
+
+/// Parse the link syntax itself into an [`EdgeStyle`].
+///
+/// Fields stay `None` where the link matches a plain `-->`.
+fn parse_link(input: &str) -> IResult<&str, EdgeStyle> {
+    let fail = || {
+        Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Tag,
+        )))
+    };
+    let bytes = input.as_bytes();
+    let count = |from: usize, c: u8| bytes[from..].iter().take_while(|&&b| b == c).count();
+
+    // Optional head at the source end
+    let mut pos = 0;
+    let start = match (bytes.first(), bytes.get(1)) {
+        (Some(b'<'), Some(b'-' | b'=')) => Some(ArrowKind::Default),
+        (Some(b'o'), Some(b'-' | b'=')) => Some(ArrowKind::Circle),
+        (Some(b'x'), Some(b'-' | b'=')) => Some(ArrowKind::Cross),
+        _ => None,
+    };
+    if start.is_some() {
+        pos = 1;
+    }
 
-    for arrow in arrows {
-        if let Ok((rest, _)) = tag::<&str, &str, nom::error::Error<&str>>(arrow)(input) {
-            return Ok((rest, None));
+    let (line, body) = match (bytes.get(pos), bytes.get(pos + 1)) {
+        (Some(b'~'), _) => (LineKind::Invisible, count(pos, b'~')),
+        (Some(b'='), _) => (LineKind::Thick, count(pos, b'=')),
+        (Some(b'-'), Some(b'.')) => {
+            let dots = count(pos + 1, b'.');
+            if bytes.get(pos + 1 + dots) != Some(&b'-') {
+                return fail();
+            }
+            (LineKind::Dotted, dots + 2)
         }
+        (Some(b'-'), _) => (LineKind::Normal, count(pos, b'-')),
+        _ => return fail(),
+    };
+    pos += body;
+
+    // Optional head at the target end
+    let end = match bytes.get(pos) {
+        Some(b'>') if line != LineKind::Invisible => ArrowKind::Default,
+        Some(b'o') if line != LineKind::Invisible => ArrowKind::Circle,
+        Some(b'x') if line != LineKind::Invisible => ArrowKind::Cross,
+        _ => ArrowKind::None,
+    };
+    if end != ArrowKind::None {
+        pos += 1;
     }
 
-    Err(nom::Err::Error(nom::error::Error::new(
-        input,
-        nom::error::ErrorKind::Tag,
-    )))
+    // `-->` / `==>` need two strokes, open `---` / `===` / `~~~` need three
+    let length = match (line, end) {
+        (LineKind::Dotted, _) => body - 2,
+        (_, ArrowKind::None) if body >= 3 => body - 2,
+        (LineKind::Normal | LineKind::Thick, _) if end != ArrowKind::None && body >= 2 => body - 1,
+        _ => return fail(),
+    };
+
+    let style = EdgeStyle {
+        dashed: (line == LineKind::Dotted).then_some(true),
+        arrow: (end != ArrowKind::Default).then_some(end),
+        arrow_start: start,
+        line: (line != LineKind::Normal).then_some(line),
+        length: (length != 1).then_some(length as u8),
+        ..Default::default()
+    };
+
+    Ok((&input[pos..], style))
 }
 
 #[cfg(test)]
@@ -150,16 +1207,19 @@ mod tests {
     fn test_parse_simple_topology() {
         let input = r#"graph TD
 A[Start] --> B[End]"#;
-        
-        let (nodes, edges) = parse_mermaid_topology(input).unwrap();
-        
+
+        let topology = parse_mermaid_topology(input).unwrap();
+        let (nodes, edges) = (&topology.nodes, &topology.edges);
+
         assert_eq!(nodes.len(), 2);
-        assert_eq!(nodes[0], ("A".to_string(), Some("Start".to_string())));
-        assert_eq!(nodes[1], ("B".to_string(), Some("End".to_string())));
-        
+        assert_eq!(nodes[0].id, "A");
+        assert_eq!(nodes[0].label, Some("Start".to_string()));
+        assert_eq!(nodes[1].id, "B");
+        assert_eq!(nodes[1].label, Some("End".to_string()));
+
         assert_eq!(edges.len(), 1);
-        assert_eq!(edges[0].0, "A");
-        assert_eq!(edges[0].1, "B");
+        assert_eq!(edges[0].source, "A");
+        assert_eq!(edges[0].target, "B");
     }
 
     #[test]
@@ -170,23 +1230,522 @@ B((Circle))
 C{Diamond}
 D([Stadium])
 E>Asymmetric]"#;
-        
-        let (nodes, _) = parse_mermaid_topology(input).unwrap();
-        
+
+        let nodes = parse_mermaid_topology(input).unwrap().nodes;
+
         assert_eq!(nodes.len(), 5);
-        assert_eq!(nodes[0].1, Some("Rectangle".to_string()));
-        assert_eq!(nodes[1].1, Some("Circle".to_string()));
-        assert_eq!(nodes[2].1, Some("Diamond".to_string()));
+        assert_eq!(nodes[0].label, Some("Rectangle".to_string()));
+        assert_eq!(nodes[1].label, Some("Circle".to_string()));
+        assert_eq!(nodes[2].label, Some("Diamond".to_string()));
+
+        assert_eq!(nodes[0].shape, Some(NodeShape::Rect));
+        assert_eq!(nodes[1].shape, Some(NodeShape::Circle));
+        assert_eq!(nodes[2].shape, Some(NodeShape::Rhombus));
+        assert_eq!(nodes[3].shape, Some(NodeShape::Stadium));
+        assert_eq!(nodes[4].shape, Some(NodeShape::Asymmetric));
+    }
+
+    #[test]
+    fn test_parse_all_bracket_shapes() {
+        let input = r#"graph LR
+A(Rounded) --> B[[Sub]] --> C[(Db)] --> D(((Double)))
+E{{Hex}} --> F[/Lean right/] --> G[\Lean left\]
+H[/Trap\] --> I[\Trap alt/]"#;
+
+        let nodes = parse_mermaid_topology(input).unwrap().nodes;
+        let shapes: Vec<_> = nodes.iter().map(|n| n.shape.clone().unwrap()).collect();
+
+        assert_eq!(
+            shapes,
+            vec![
+                NodeShape::Rounded,
+                NodeShape::Subroutine,
+                NodeShape::Cylinder,
+                NodeShape::DoubleCircle,
+                NodeShape::Hexagon,
+                NodeShape::Parallelogram,
+                NodeShape::ParallelogramAlt,
+                NodeShape::Trapezoid,
+                NodeShape::TrapezoidAlt,
+            ]
+        );
+        assert_eq!(nodes[7].label, Some("Trap".to_string()));
+    }
+
+    #[test]
+    fn test_parse_shape_data_syntax() {
+        let input = r#"graph TD
+A@{ shape: diam, label: "Ready, set?" } --> B@{ shape: doc }
+B --> C
+C[Later definition]
+D@{ shape: rect, label: "a}b" } --> E"#;
+
+        let topology = parse_mermaid_topology(input).unwrap();
+        let nodes = topology.nodes;
+
+        assert_eq!(nodes[0].shape, Some(NodeShape::Rhombus));
+        assert_eq!(nodes[0].label, Some("Ready, set?".to_string()));
+        assert_eq!(nodes[1].shape, Some(NodeShape::Custom("doc".to_string())));
+        assert_eq!(nodes[1].label, None);
+        assert_eq!(nodes[2].shape, Some(NodeShape::Rect));
+        assert_eq!(nodes[2].label, Some("Later definition".to_string()));
+
+        // A brace inside a quoted value does not close the body
+        assert_eq!(nodes[3].shape, Some(NodeShape::Rect));
+        assert_eq!(nodes[3].label, Some("a}b".to_string()));
+        assert_eq!(topology.edges.len(), 3);
+        assert!(topology.raw_lines.is_empty());
     }
 
     #[test]
     fn test_parse_edge_with_label() {
         let input = r#"graph TD
 A --|yes|--> B"#;
-        
-        let (_, edges) = parse_mermaid_topology(input).unwrap();
-        
+
+        let edges = parse_mermaid_topology(input).unwrap().edges;
+
         assert_eq!(edges.len(), 1);
-        assert_eq!(edges[0].2, Some("yes".to_string()));
+        assert_eq!(edges[0].label, Some("yes".to_string()));
+    }
+
+    #[test]
+    fn test_parse_arrow_kinds() {
+        let input = r#"graph LR
+A -.-> B
+A ==> C
+A --- D
+A ~~~ E
+A --o F
+A --x G
+A <--> H
+A o--o I
+A ---> J
+A -..- K
+A ===> L
+A -->|go| M"#;
+
+        let edges = parse_mermaid_topology(input).unwrap().edges;
+        let style = |i: usize| edges[i].style.clone();
+
+        assert_eq!(style(0).line, Some(LineKind::Dotted));
+        assert_eq!(style(0).dashed, Some(true));
+        assert_eq!(style(0).arrow, None);
+        assert_eq!(style(1).line, Some(LineKind::Thick));
+        assert_eq!(style(2).arrow, Some(ArrowKind::None));
+        assert_eq!(style(2).line, None);
+        assert_eq!(style(3).line, Some(LineKind::Invisible));
+        assert_eq!(style(3).arrow, Some(ArrowKind::None));
+        assert_eq!(style(4).arrow, Some(ArrowKind::Circle));
+        assert_eq!(style(5).arrow, Some(ArrowKind::Cross));
+        assert_eq!(style(6).arrow_start, Some(ArrowKind::Default));
+        assert_eq!(style(6).arrow, None);
+        assert_eq!(style(7).arrow_start, Some(ArrowKind::Circle));
+        assert_eq!(style(7).arrow, Some(ArrowKind::Circle));
+        assert_eq!(style(8).length, Some(2));
+        assert_eq!(style(9).length, Some(2));
+        assert_eq!(style(9).arrow, Some(ArrowKind::None));
+        assert_eq!(style(10).length, Some(2));
+        assert_eq!(style(10).line, Some(LineKind::Thick));
+        assert_eq!(style(11), EdgeStyle::default());
+        assert_eq!(edges[11].label, Some("go".to_string()));
+
+        let targets: Vec<_> = edges.iter().map(|e| e.target.as_str()).collect();
+        assert_eq!(targets, vec!["B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M"]);
+    }
+
+    #[test]
+    fn test_parse_edge_label_forms() {
+        let input = r#"graph TD
+A -->|yes| B
+A -- no --> C
+A -. maybe .-> D
+A == strong ==> E
+A -- plain text --- F
+A-- re-try -->G
+A <-- both ways --> H
+A -->|"quoted"| I
+A ---|open| J"#;
+
+        let edges = parse_mermaid_topology(input).unwrap().edges;
+        let labels: Vec<_> = edges.iter().map(|e| e.label.as_deref().unwrap()).collect();
+        let targets: Vec<_> = edges.iter().map(|e| e.target.as_str()).collect();
+
+        assert_eq!(
+            labels,
+            vec!["yes", "no", "maybe", "strong", "plain text", "re-try", "both ways", "quoted", "open"]
+        );
+        assert_eq!(targets, vec!["B", "C", "D", "E", "F", "G", "H", "I", "J"]);
+
+        assert_eq!(edges[1].style, EdgeStyle::default());
+        assert_eq!(edges[2].style.line, Some(LineKind::Dotted));
+        assert_eq!(edges[2].style.arrow, None);
+        assert_eq!(edges[3].style.line, Some(LineKind::Thick));
+        assert_eq!(edges[4].style.arrow, Some(ArrowKind::None));
+        assert_eq!(edges[6].style.arrow_start, Some(ArrowKind::Default));
+        assert_eq!(edges[8].style.arrow, Some(ArrowKind::None));
+    }
+
+    #[test]
+    fn test_parse_text_label_in_chain() {
+        let input = r#"graph LR
+A -- first --> B -. second .-> C"#;
+
+        let edges = parse_mermaid_topology(input).unwrap().edges;
+
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0].label, Some("first".to_string()));
+        assert_eq!(edges[1].label, Some("second".to_string()));
+        assert_eq!(edges[1].source, "B");
+    }
+
+    #[test]
+    fn test_parse_edge_ids() {
+        let input = r#"graph LR
+A e1@--> B
+A e2@-.->|retry| B
+B o--o C"#;
+
+        let edges = parse_mermaid_topology(input).unwrap().edges;
+
+        assert_eq!(edges.len(), 3);
+        assert_eq!(edges[0].id, Some("e1".to_string()));
+        assert_eq!(edges[1].id, Some("e2".to_string()));
+        assert_eq!(edges[1].label, Some("retry".to_string()));
+        assert_eq!(edges[1].style.line, Some(LineKind::Dotted));
+        assert_eq!(edges[2].id, None);
+        assert_eq!(edges[2].style.arrow_start, Some(ArrowKind::Circle));
+    }
+
+    #[test]
+    fn test_parse_chained_edges() {
+        let input = r#"graph TD
+A[Start] --> B --> C[End]"#;
+
+        let topology = parse_mermaid_topology(input).unwrap();
+
+        assert_eq!(topology.nodes.len(), 3);
+        assert_eq!(topology.nodes[2].label, Some("End".to_string()));
+
+        let pairs: Vec<_> = topology
+            .edges
+            .iter()
+            .map(|e| (e.source.as_str(), e.target.as_str()))
+            .collect();
+        assert_eq!(pairs, vec![("A", "B"), ("B", "C")]);
+    }
+
+    #[test]
+    fn test_parse_fan_out_edges() {
+        let input = r#"graph TD
+A & B[Second] --> C & D
+D --|next|--> E & F --> G"#;
+
+        let topology = parse_mermaid_topology(input).unwrap();
+
+        let ids: Vec<_> = topology.nodes.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, vec!["A", "B", "C", "D", "E", "F", "G"]);
+        assert_eq!(topology.nodes[1].label, Some("Second".to_string()));
+
+        let pairs: Vec<_> = topology
+            .edges
+            .iter()
+            .map(|e| (e.source.as_str(), e.target.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("A", "C"),
+                ("A", "D"),
+                ("B", "C"),
+                ("B", "D"),
+                ("D", "E"),
+                ("D", "F"),
+                ("E", "G"),
+                ("F", "G"),
+            ]
+        );
+        assert_eq!(topology.edges[4].label, Some("next".to_string()));
+        assert_eq!(topology.edges[6].label, None);
+    }
+
+    #[test]
+    fn test_parse_subgraphs() {
+        let input = r#"flowchart TB
+C[Outside] --> A1
+subgraph lane_a [Lane A]
+    direction LR
+    A1[First] --> A2
+    subgraph inner["Inner Lane"]
+        I1
+    end
+end
+subgraph Lane B
+    B1 --> A2
+end"#;
+
+        let topology = parse_mermaid_topology(input).unwrap();
+        let subgraphs = &topology.subgraphs;
+
+        assert_eq!(topology.header.to_string(), "flowchart TB");
+
+        assert_eq!(subgraphs.len(), 3);
+
+        assert_eq!(subgraphs[0].id, "lane_a");
+        assert_eq!(subgraphs[0].title, Some("Lane A".to_string()));
+        assert_eq!(subgraphs[0].direction, Some(Direction::LeftRight));
+        assert_eq!(subgraphs[0].members, vec!["A1", "A2"]);
+        assert_eq!(subgraphs[0].subgraphs, vec!["inner"]);
+
+        assert_eq!(subgraphs[1].id, "inner");
+        assert_eq!(subgraphs[1].title, Some("Inner Lane".to_string()));
+        assert_eq!(subgraphs[1].parent, Some("lane_a".to_string()));
+        assert_eq!(subgraphs[1].members, vec!["I1"]);
+
+        // A2 stays in the first subgraph that referenced it
+        assert_eq!(subgraphs[2].id, "Lane B");
+        assert_eq!(subgraphs[2].title, None);
+        assert_eq!(subgraphs[2].members, vec!["B1"]);
+
+        assert_eq!(topology.nodes.len(), 5);
+        assert_eq!(topology.edges.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_class_and_style_statements() {
+        let input = r#"graph TD
+A[Start]:::hot --> B
+B:::cold & C --> D
+classDef hot fill:#f96,stroke:#333,stroke-width:4px;
+classDef cold,calm fill:#9cf
+classDef hot fill:#f00
+class B,C,Missing calm
+style D fill:#eee,stroke-dasharray: 5 5
+style D color:#111
+style E stroke:#f00"#;
+
+        let topology = parse_mermaid_topology(input).unwrap();
+
+        let defs: Vec<_> = topology.class_defs.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(defs, vec!["hot", "cold", "calm"]);
+        assert_eq!(topology.class_defs[0].style.fill, Some("#f00".to_string()));
+        assert_eq!(topology.class_defs[0].style.stroke, None);
+
+        let node = |id: &str| topology.nodes.iter().find(|n| n.id == id).unwrap();
+        assert_eq!(node("A").label, Some("Start".to_string()));
+        assert_eq!(node("A").classes, vec!["hot"]);
+        assert_eq!(node("B").classes, vec!["cold", "calm"]);
+        assert_eq!(node("C").classes, vec!["calm"]);
+        assert_eq!(
+            node("D").style.as_ref().unwrap().to_css(),
+            "fill:#eee,color:#111,stroke-dasharray:5 5"
+        );
+        assert_eq!(node("E").style.as_ref().unwrap().stroke, Some("#f00".to_string()));
+
+        // Styling statements never become nodes themselves
+        let ids: Vec<_> = topology.nodes.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, vec!["A", "B", "C", "D", "E"]);
+        assert_eq!(topology.edges.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_link_styles() {
+        let input = r#"graph TD
+linkStyle default stroke:#999
+A --> B & C
+B -.-> D
+linkStyle 0,2 stroke:#f00,stroke-width:2px;
+linkStyle 2 color:blue
+linkStyle 9 stroke:#000
+linkStyle 1,x stroke:#0f0"#;
+
+        let topology = parse_mermaid_topology(input).unwrap();
+
+        assert_eq!(
+            topology.default_link_style.as_ref().unwrap().to_css(),
+            "stroke:#999"
+        );
+        assert_eq!(topology.edges[0].style.to_css(), "stroke:#f00,stroke-width:2px");
+        assert_eq!(topology.edges[1].style, EdgeStyle::default());
+        assert_eq!(
+            topology.edges[2].style.to_css(),
+            "stroke:#f00,stroke-width:2px,color:blue"
+        );
+        // The link syntax survives alongside the CSS
+        assert_eq!(topology.edges[2].style.line, Some(LineKind::Dotted));
+        assert_eq!(topology.nodes.len(), 4);
+
+        // Statements naming missing edges are kept as written
+        let raw: Vec<_> = topology.raw_lines.iter().map(|r| r.line.text.as_str()).collect();
+        assert_eq!(raw, ["linkStyle 9 stroke:#000", "linkStyle 1,x stroke:#0f0"]);
+        assert_eq!(topology.style_spans.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_click_statements() {
+        let input = r#"graph TD
+click A href "https://example.com" "Open site" _blank
+A --> B --> C --> D --> E
+click B "other.mmd#Start"
+click C showDetails "Details"
+click D call notify(D, "hi") "Notify"
+click E href "docs.mmd" _self
+click Missing cb
+click"#;
+
+        let topology = parse_mermaid_topology(input).unwrap();
+        let link = |id: &str| {
+            topology
+                .nodes
+                .iter()
+                .find(|n| n.id == id)
+                .and_then(|n| n.link.clone())
+                .unwrap()
+        };
+
+        let a = link("A");
+        assert_eq!(a.href, Some("https://example.com".to_string()));
+        assert_eq!(a.tooltip, Some("Open site".to_string()));
+        assert_eq!(a.target, Some("_blank".to_string()));
+
+        assert_eq!(link("B").href, Some("other.mmd#Start".to_string()));
+        assert_eq!(link("B").tooltip, None);
+
+        let c = link("C");
+        assert_eq!(c.callback, Some("showDetails".to_string()));
+        assert_eq!(c.tooltip, Some("Details".to_string()));
+
+        let d = link("D");
+        assert_eq!(d.callback, Some(r#"notify(D, "hi")"#.to_string()));
+        assert_eq!(d.tooltip, Some("Notify".to_string()));
+
+        assert_eq!(link("E").target, Some("_self".to_string()));
+
+        // `click` is never mistaken for a node
+        let ids: Vec<_> = topology.nodes.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, vec!["A", "B", "C", "D", "E"]);
+    }
+
+    #[test]
+    fn test_parse_quoted_and_markdown_labels() {
+        let input = "graph TD
+A[\"Hello [world]\"] --> B(\"line1<br/>line2\")
+C[\"`**bold** text
+second line`\"] -->|\"a | b\"| D{{\"x }} y\"}}
+E[Say #quot;hi#quot; ok] -- \"a --> b\" --> F
+G[\"semi; colon\"]; G --> H; H --> A
+";
+
+        let topology = parse_mermaid_topology(input).unwrap();
+        let label = |id: &str| {
+            topology
+                .nodes
+                .iter()
+                .find(|n| n.id == id)
+                .and_then(|n| n.label.clone())
+                .unwrap_or_default()
+        };
+
+        assert_eq!(label("A"), "Hello [world]");
+        assert_eq!(label("B"), "line1<br/>line2");
+        assert_eq!(label("C"), "`**bold** text\nsecond line`");
+        assert_eq!(label("D"), "x }} y");
+        assert_eq!(label("E"), "Say \"hi\" ok");
+        assert_eq!(label("G"), "semi; colon");
+
+        let edges: Vec<_> = topology
+            .edges
+            .iter()
+            .map(|e| (e.source.as_str(), e.target.as_str(), e.label.as_deref()))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("A", "B", None),
+                ("C", "D", Some("a | b")),
+                ("E", "F", Some("a --> b")),
+                ("G", "H", None),
+                ("H", "A", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_accessibility() {
+        let input = "flowchart LR
+accTitle: Orders; returns
+accDescr {
+    How an order moves
+    A --> B is not an edge here
+}
+A --> B";
+
+        let topology = parse_mermaid_topology(input).unwrap();
+        assert_eq!(topology.accessibility.title.as_deref(), Some("Orders; returns"));
+        assert_eq!(
+            topology.accessibility.description.as_deref(),
+            Some("How an order moves\nA --> B is not an edge here")
+        );
+        assert_eq!(topology.edges.len(), 1);
+        assert!(topology.raw_lines.is_empty());
+
+        let span = topology.acc_descr_span.unwrap();
+        assert_eq!((span.start_line, span.end_line), (3, 6));
+
+        // A block left open stops at the directives and is kept as written
+        let input = "graph TD\nA --> B\naccDescr {\n    Unfinished\n\n%% @node: A {\"uid\":\"n_001\",\"meta\":{}}\n";
+        let topology = parse_mermaid_topology(input).unwrap();
+        assert_eq!(topology.accessibility.description, None);
+        assert_eq!(topology.raw_lines.len(), 1);
+        assert_eq!(topology.raw_lines[0].line.text, "accDescr {\n    Unfinished");
+        assert_eq!((topology.raw_lines[0].span.start_line, topology.raw_lines[0].span.end_line), (3, 4));
+
+        let topology = parse_mermaid_topology("graph TD\naccDescr: One line\naccTitleNode --> B").unwrap();
+        assert_eq!(topology.accessibility.description.as_deref(), Some("One line"));
+        assert_eq!(topology.nodes[0].id, "accTitleNode");
+    }
+
+    #[test]
+    fn test_parse_keeps_unrecognised_statements() {
+        let input = "graph TD
+%% plain comment
+%% @node: A {\"uid\":\"n_001\"}
+A --> B
+A -> B
+subgraph lane
+    C[Inside] --> D
+    C ==>> D
+end
+title Overview
+node-1[Dash]
+click
+end";
+
+        let topology = parse_mermaid_topology(input).unwrap();
+        let raw: Vec<_> = topology
+            .raw_lines
+            .iter()
+            .map(|r| (r.line.text.as_str(), r.line.group.as_deref()))
+            .collect();
+        assert_eq!(
+            raw,
+            vec![
+                ("%% plain comment", None),
+                ("A -> B", None),
+                ("C ==>> D", Some("lane")),
+                ("title Overview", None),
+                ("node-1[Dash]", None),
+                ("click", None),
+                ("end", None),
+            ]
+        );
+
+        // Partially parsed statements add nothing to the graph
+        let ids: Vec<_> = topology.nodes.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, vec!["A", "B", "C", "D"]);
+        assert_eq!(topology.edges.len(), 2);
+
+        let span = topology.raw_lines[1].span;
+        assert_eq!((span.start_line, span.start_column), (5, 1));
+        assert_eq!(&input[span.start..span.end], "A -> B");
     }
 }
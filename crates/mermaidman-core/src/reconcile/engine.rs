@@ -1,22 +1,25 @@
 //! Reconcile engine: sync graph topology with directives.
 
-use crate::parse::{parse_document, ParseResult};
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::parse::parse_document;
 use crate::store::GraphStore;
-use crate::types::{Edge, Node, EID, UID};
-use crate::write::generate_mermaidman;
-use crate::{Error, Result};
+use crate::types::{Edge, EdgeStyle, Group, Node, NodeStyle, EID, UID};
+use crate::write::update_document;
+use crate::Result;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
 /// Reconciliation result.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReconcileResult {
-    /// The reconciled Mermaidman text.
+    /// The reconciled Mermaidman text: the input with its directives
+    /// brought up to date, formatted as the user wrote it.
     pub text: String,
     /// Updated graph store.
     pub store: GraphStore,
-    /// Warnings during reconciliation.
-    pub warnings: Vec<String>,
+    /// Problems found while parsing and reconciling.
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
     /// Orphaned node UIDs (directives without topology).
     pub orphaned_nodes: Vec<UID>,
     /// Orphaned edge EIDs.
@@ -33,11 +36,36 @@ pub fn reconcile(
 ) -> Result<ReconcileResult> {
     // Parse the new topology
     let parsed = parse_document(topology_text)?;
-    let mut warnings = parsed.warnings.clone();
+    let mut diagnostics = parsed.diagnostics.clone();
     
     // Build new store, reusing UIDs where possible
     let mut new_store = GraphStore::new();
+    new_store.header = parsed.header;
+    new_store.config = parsed.config.clone();
+    new_store.accessibility = parsed.accessibility.clone();
+
+    // Participants without a directive keep the UID and layout they had
+    new_store.sequence = parsed.sequence.clone().map(|mut sequence| {
+        let sources = &parsed.source_map.participants;
+        let previous = existing_store.sequence.as_ref();
+        for (participant, source) in sequence.participants.iter_mut().zip(sources) {
+            let old = previous.and_then(|s| s.participant(&participant.id));
+            if let Some(old) = old.filter(|_| source.directive.is_none()) {
+                participant.uid = old.uid.clone();
+                participant.x = old.x;
+                participant.y = old.y;
+                participant.meta = old.meta.clone();
+            }
+        }
+        sequence
+    });
+    new_store.state_diagram = parsed.state_diagram.clone();
+    new_store.class_diagram = parsed.class_diagram.clone();
+    new_store.er_diagram = parsed.er_diagram.clone();
+    new_store.default_link_style = parsed.default_link_style.clone();
+    new_store.raw_lines = parsed.raw_lines.clone();
     let mut mermaid_to_uid: IndexMap<String, UID> = IndexMap::new();
+    let mut parsed_to_uid: IndexMap<UID, UID> = IndexMap::new();
 
     // Process nodes: match by mermaid_id to preserve UIDs
     for parsed_node in &parsed.nodes {
@@ -52,9 +80,15 @@ pub fn reconcile(
             let mut n = existing.clone();
             n.mermaid_id = parsed_node.mermaid_id.clone();
             n.label = parsed_node.label.clone().or(n.label);
+            if parsed_node.label.is_some() {
+                n.shape = parsed_node.shape.clone();
+            }
             n
         } else {
-            Node::with_uid(uid.clone(), &parsed_node.mermaid_id)
+            let mut n = Node::with_uid(uid.clone(), &parsed_node.mermaid_id);
+            n.label = parsed_node.label.clone();
+            n.shape = parsed_node.shape.clone();
+            n
         };
         
         // Update from parsed data
@@ -67,33 +101,111 @@ pub fn reconcile(
         if parsed_node.kind != crate::types::NodeKind::Card {
             node.kind = parsed_node.kind;
         }
+        if parsed_node.code.is_some() {
+            node.code = parsed_node.code.clone();
+        }
+        if parsed_node.media.is_some() {
+            node.media = parsed_node.media.clone();
+        }
+        if parsed_node.diagram.is_some() {
+            node.diagram = parsed_node.diagram.clone();
+        }
+        if parsed_node.markdown.is_some() {
+            node.markdown = parsed_node.markdown.clone();
+        }
         if parsed_node.meta.is_some() {
             node.meta = parsed_node.meta.clone();
         }
         
+        // Classes, CSS and click links come from the topology; canvas-only style keys
+        // are kept unless the directive sets them
+        node.classes = parsed_node.classes.clone();
+        node.link = parsed_node.link.clone();
+        let parsed_style = parsed_node.style.clone().unwrap_or_default();
+        let mut style = node.style.take().unwrap_or_default();
+        style.set_css(&parsed_style);
+        if parsed_style.border.is_some() {
+            style.border = parsed_style.border;
+        }
+        if style != NodeStyle::default() {
+            node.style = Some(style);
+        }
+        
         mermaid_to_uid.insert(node.mermaid_id.clone(), uid.clone());
+        parsed_to_uid.insert(parsed_node.uid.clone(), uid.clone());
         new_store.upsert_node(node);
     }
 
-    // Process edges: match by source+target UIDs
-    for parsed_edge in &parsed.edges {
-        let source = mermaid_to_uid
-            .get(parsed_edge.source.as_ref())
-            .cloned()
-            .unwrap_or_else(|| parsed_edge.source.clone());
-        
-        let target = mermaid_to_uid
-            .get(parsed_edge.target.as_ref())
-            .cloned()
-            .unwrap_or_else(|| parsed_edge.target.clone());
+    // Groups come straight from the topology; remap members to reconciled UIDs
+    for parsed_group in &parsed.groups {
+        let remap = |uids: &[UID]| {
+            uids.iter()
+                .map(|uid| parsed_to_uid.get(uid).cloned().unwrap_or_else(|| uid.clone()))
+                .collect()
+        };
 
-        // Try to find existing edge with same source/target
-        let existing_eid = existing_store
+        new_store.upsert_group(Group {
+            members: remap(&parsed_group.members),
+            region_starts: remap(&parsed_group.region_starts),
+            ..parsed_group.clone()
+        });
+    }
+
+    for class_def in &parsed.class_defs {
+        new_store.upsert_class_def(class_def.clone());
+    }
+
+    // Process edges: match by Mermaid edge ID, then EID, then in order among
+    // edges with the same source+target UIDs
+    let endpoints: Vec<(UID, UID)> = parsed
+        .edges
+        .iter()
+        .map(|e| {
+            let remap = |uid: &UID| {
+                parsed_to_uid
+                    .get(uid)
+                    .or_else(|| mermaid_to_uid.get(uid.as_ref()))
+                    .cloned()
+                    .unwrap_or_else(|| uid.clone())
+            };
+            (remap(&e.source), remap(&e.target))
+        })
+        .collect();
+    let mut claimed: std::collections::HashSet<&EID> = std::collections::HashSet::new();
+    let mut matched: Vec<Option<EID>> = parsed
+        .edges
+        .iter()
+        .map(|parsed_edge| {
+            let existing = existing_store
+                .active_edges()
+                .find(|e| e.mermaid_id.is_some() && e.mermaid_id == parsed_edge.mermaid_id)
+                .or_else(|| existing_store.get_edge(&parsed_edge.eid).filter(|e| !e.deleted))
+                .filter(|e| claimed.insert(&e.eid))?;
+            Some(existing.eid.clone())
+        })
+        .collect();
+    for (slot, (source, target)) in matched.iter_mut().zip(&endpoints) {
+        if slot.is_some() {
+            continue;
+        }
+        let existing = existing_store
             .active_edges()
-            .find(|e| e.source == source && e.target == target)
-            .map(|e| e.eid.clone());
-        
-        let eid = existing_eid.unwrap_or_else(|| parsed_edge.eid.clone());
+            .find(|e| !claimed.contains(&e.eid) && &e.source == source && &e.target == target);
+        if let Some(existing) = existing {
+            claimed.insert(&existing.eid);
+            *slot = Some(existing.eid.clone());
+        }
+    }
+
+    let parsed_edges = parsed.edges.iter().zip(endpoints).zip(matched);
+    for ((parsed_edge, (source, target)), existing_eid) in parsed_edges {
+        let eid = match existing_eid {
+            Some(eid) => eid,
+            // The parsed EID may already belong to another (or a deleted) edge
+            None if existing_store.edges.contains_key(&parsed_edge.eid)
+                || new_store.edges.contains_key(&parsed_edge.eid) => EID::default(),
+            None => parsed_edge.eid.clone(),
+        };
         
         let mut edge = if let Some(existing) = existing_store.get_edge(&eid) {
             let mut e = existing.clone();
@@ -104,6 +216,7 @@ pub fn reconcile(
         } else {
             Edge::with_eid(eid, source, target)
         };
+        edge.mermaid_id = parsed_edge.mermaid_id.clone();
         
         if parsed_edge.label.is_some() {
             edge.label = parsed_edge.label.clone();
@@ -112,9 +225,29 @@ pub fn reconcile(
             edge.meta = parsed_edge.meta.clone();
         }
         
+        // The link syntax and `linkStyle` in the topology are authoritative
+        let parsed_style = parsed_edge.style.clone().unwrap_or_default();
+        let mut style = edge.style.take().unwrap_or_default();
+        style.set_link(&parsed_style);
+        style.set_css(&parsed_style);
+        if style != EdgeStyle::default() {
+            edge.style = Some(style);
+        }
+        
         new_store.upsert_edge(edge);
     }
 
+    // AI provenance is kept for surviving nodes; `%% @ai` lines in the text replace a node's log
+    for (uid, log) in &existing_store.provenance {
+        if new_store.get_node(uid).is_some() {
+            new_store.provenance.insert(uid.clone(), log.clone());
+        }
+    }
+    for (uid, log) in &parsed.provenance {
+        let uid = parsed_to_uid.get(uid).cloned().unwrap_or_else(|| uid.clone());
+        new_store.provenance.insert(uid, log.clone());
+    }
+
     // Find orphaned nodes (in old store but not in new topology)
     let new_node_uids: std::collections::HashSet<_> = 
         new_store.nodes.keys().collect();
@@ -125,10 +258,11 @@ pub fn reconcile(
         .map(|n| n.uid.clone())
         .collect();
     
-    if !orphaned_nodes.is_empty() {
-        warnings.push(format!(
-            "Orphaned nodes: {:?}",
-            orphaned_nodes.iter().map(|u| &u.0).collect::<Vec<_>>()
+    for uid in &orphaned_nodes {
+        let mermaid_id = existing_store.alias.get_mermaid_id(uid).map_or("?", |id| id.as_str());
+        diagnostics.push(Diagnostic::warning(
+            DiagnosticCode::OrphanedNode,
+            format!("Node `{}` ({}) is no longer in the topology", mermaid_id, uid),
         ));
     }
 
@@ -142,13 +276,20 @@ pub fn reconcile(
         .map(|e| e.eid.clone())
         .collect();
 
-    // Generate reconciled text
-    let text = generate_mermaidman(&new_store, "TD");
+    for eid in &orphaned_edges {
+        diagnostics.push(Diagnostic::warning(
+            DiagnosticCode::OrphanedEdge,
+            format!("Edge {} is no longer in the topology", eid),
+        ));
+    }
+
+    // Apply the reconciled store to the user's text, keeping its formatting
+    let text = update_document(topology_text, &new_store);
 
     Ok(ReconcileResult {
         text,
         store: new_store,
-        warnings,
+        diagnostics,
         orphaned_nodes,
         orphaned_edges,
     })
@@ -200,6 +341,23 @@ A[Begin] --> B[Finish]
         assert_eq!(node_a.y, Some(50.0));
     }
 
+    #[test]
+    fn test_reconcile_keeps_the_users_formatting() {
+        let initial = "graph TD\nA --> B\n\n%% @node: A {\"uid\":\"n_001\",\"x\":100,\"y\":50}\n";
+        let parsed = parse_document(initial).unwrap();
+        let store = GraphStore::from_parsed(parsed.nodes, parsed.edges);
+
+        let edited = "graph TD\n  %% the usual path\n  A --> B\n\n  B --> C\n\n%% @node: A {\"uid\":\"n_001\",\"x\":100,\"y\":50}\n";
+        let result = reconcile(edited, &store).unwrap();
+        assert!(result.text.starts_with("graph TD\n  %% the usual path\n  A --> B\n\n  B --> C\n\n"), "{}", result.text);
+        assert!(result.text.contains("%% @node: A {\"uid\":\"n_001\",\"x\":100,\"y\":50}\n"), "{}", result.text);
+
+        let reparsed = GraphStore::from_parse_result(parse_document(&result.text).unwrap());
+        for node in result.store.active_nodes() {
+            assert_eq!(reparsed.alias.get_uid(&node.mermaid_id), Some(&node.uid));
+        }
+    }
+
     #[test]
     fn test_reconcile_detects_orphans() {
         let initial = r#"graph TD
@@ -223,5 +381,119 @@ A[Start] --> B[Middle]
         
         // C should be orphaned
         assert!(result.orphaned_nodes.iter().any(|u| u.0 == "n_003"));
+
+        let orphan = result
+            .diagnostics
+            .iter()
+            .find(|d| d.code == DiagnosticCode::OrphanedNode)
+            .unwrap();
+        assert_eq!(orphan.message, "Node `C` (n_003) is no longer in the topology");
+        assert!(result.diagnostics.iter().any(|d| d.code == DiagnosticCode::OrphanedEdge));
+    }
+
+    #[test]
+    fn test_reconcile_keeps_groups() {
+        let initial = r#"graph TD
+A[Start] --> B[End]
+
+%% @node: A {"uid":"n_001","x":100,"y":50}
+"#;
+
+        let parsed = parse_document(initial).unwrap();
+        let store = GraphStore::from_parse_result(parsed);
+
+        let modified = r#"graph TD
+subgraph lane
+    A[Start]
+end
+A --> B[End]
+"#;
+
+        let result = reconcile(modified, &store).unwrap();
+
+        let group = result.store.get_group("lane").unwrap();
+        assert_eq!(group.members, vec![UID::from_str("n_001")]);
+        assert!(result.text.contains("subgraph lane"));
+    }
+
+    #[test]
+    fn test_reconcile_keeps_direction() {
+        let store = GraphStore::new();
+
+        let result = reconcile("flowchart RL\nA --> B\n", &store).unwrap();
+
+        assert!(result.text.starts_with("flowchart RL\n"));
+    }
+
+    #[test]
+    fn test_reconcile_keeps_border_and_updates_css() {
+        let parsed = parse_document("graph TD\nA --> B\nstyle A fill:#f00\n").unwrap();
+        let mut store = GraphStore::from_parse_result(parsed);
+        let uid = store.alias.get_uid("A").cloned().unwrap();
+        store.get_node_mut(&uid).unwrap().style.as_mut().unwrap().border = Some("2px".to_string());
+
+        let modified = "graph TD\nA:::hot --> B\nclassDef hot stroke:#333\n";
+        let result = reconcile(modified, &store).unwrap();
+
+        let node = result.store.get_node(&uid).unwrap();
+        let style = node.style.as_ref().unwrap();
+        assert_eq!(style.border, Some("2px".to_string()));
+        assert_eq!(style.fill, None);
+        assert_eq!(node.classes, vec!["hot"]);
+        assert!(result.store.class_defs.contains_key("hot"));
+        assert!(result.text.starts_with(modified));
+    }
+
+    #[test]
+    fn test_reconcile_keeps_provenance() {
+        let initial = r#"graph TD
+A[Summary] --> B
+
+%% @node: A {"uid":"n_001"}
+%% @node: B {"uid":"n_002"}
+%% @ai: n_001 {"action":"summarize","provider":"gemini"}
+"#;
+        let store = GraphStore::from_parse_result(parse_document(initial).unwrap());
+        assert_eq!(store.provenance_of(&UID::from_str("n_001"))[0].action, "summarize");
+
+        // Topology-only edits keep the log
+        let result = reconcile("graph TD\nA[Summary] --> C\n", &store).unwrap();
+        assert_eq!(result.store.provenance_of(&UID::from_str("n_001")).len(), 1);
+
+        // `%% @ai` lines in the text replace it
+        let edited = initial.replace("summarize", "expand");
+        let result = reconcile(&edited, &store).unwrap();
+        let log = result.store.provenance_of(&UID::from_str("n_001"));
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].action, "expand");
+    }
+
+    #[test]
+    fn test_reconcile_keeps_parallel_edges() {
+        let initial = r#"graph TD
+A -->|retry| B
+A -->|done| B
+
+%% @node: A {"uid":"n_001"}
+%% @node: B {"uid":"n_002"}
+%% @edge: e1 {"source":"n_001","target":"n_002","meta":{"n":1}}
+%% @edge: e2 {"source":"n_001","target":"n_002","meta":{"n":2}}
+"#;
+        let store = GraphStore::from_parse_result(parse_document(initial).unwrap());
+
+        // Relabelling in the text keeps each edge's identity and meta
+        let result = reconcile("graph TD\nA -->|again| B\nA -->|finished| B\n", &store).unwrap();
+
+        let edges: Vec<_> = result.store.active_edges().collect();
+        assert_eq!(edges.len(), 2);
+        assert_eq!((edges[0].eid.0.as_str(), edges[0].label.as_deref()), ("e1", Some("again")));
+        assert_eq!((edges[1].eid.0.as_str(), edges[1].label.as_deref()), ("e2", Some("finished")));
+        assert_eq!(edges[1].meta, Some(serde_json::json!({"n": 2})));
+        assert!(result.orphaned_edges.is_empty());
+
+        let reparsed = parse_document(&result.text).unwrap();
+        assert_eq!(reparsed.edges.len(), 2);
+        assert_eq!(reparsed.edges[1].meta, Some(serde_json::json!({"n": 2})));
+        assert!(reparsed.diagnostics.is_empty());
     }
 }
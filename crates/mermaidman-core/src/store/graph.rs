@@ -1,6 +1,10 @@
 //! In-memory graph store with UID-first indexing.
 
-use crate::types::{Edge, Node, EID, UID};
+use crate::parse::ParseResult;
+use crate::types::{
+    Accessibility, AiRecord, AiReview, ClassDef, ClassDiagram, DiagramHeader, DocumentConfig, Edge, EdgeStyle,
+    ErDiagram, Group, Node, RawLine, SequenceDiagram, StateDiagram, EID, UID,
+};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
@@ -32,15 +36,15 @@ impl AliasMap {
 
     /// Remove alias by UID.
     pub fn remove_by_uid(&mut self, uid: &UID) {
-        if let Some(mermaid_id) = self.uid_to_mermaid_id.remove(uid) {
-            self.mermaid_id_to_uid.remove(&mermaid_id);
+        if let Some(mermaid_id) = self.uid_to_mermaid_id.shift_remove(uid) {
+            self.mermaid_id_to_uid.shift_remove(&mermaid_id);
         }
     }
 
     /// Update Mermaid ID for a UID.
     pub fn rename(&mut self, uid: &UID, new_mermaid_id: &str) {
         if let Some(old_mermaid_id) = self.uid_to_mermaid_id.get(uid).cloned() {
-            self.mermaid_id_to_uid.remove(&old_mermaid_id);
+            self.mermaid_id_to_uid.shift_remove(&old_mermaid_id);
         }
         self.mermaid_id_to_uid
             .insert(new_mermaid_id.to_string(), uid.clone());
@@ -52,9 +56,47 @@ impl AliasMap {
 /// The in-memory graph store.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GraphStore {
+    /// Header keyword and direction used when writing.
+    #[serde(default)]
+    pub header: DiagramHeader,
+    /// Title and Mermaid config, written as frontmatter or `%%{init}%%` lines.
+    #[serde(default, skip_serializing_if = "DocumentConfig::is_empty")]
+    pub config: DocumentConfig,
+    /// `accTitle` and `accDescr`, written after the header.
+    #[serde(default, skip_serializing_if = "Accessibility::is_empty")]
+    pub accessibility: Accessibility,
+    /// The diagram of a `sequenceDiagram` document; nodes and edges are then empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<SequenceDiagram>,
+    /// The state descriptions of a `stateDiagram` document; the states are
+    /// nodes and their names are node labels.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_diagram: Option<StateDiagram>,
+    /// The class model of a `classDiagram` document. Classes are laid out
+    /// as the nodes of the same name; the model holds their members.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class_diagram: Option<ClassDiagram>,
+    /// The entity model of an `erDiagram` document, laid out like classes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub er_diagram: Option<ErDiagram>,
     pub nodes: IndexMap<UID, Node>,
     pub edges: IndexMap<EID, Edge>,
     pub alias: AliasMap,
+    /// Subgraph groups keyed by their Mermaid ID, in declaration order.
+    #[serde(default)]
+    pub groups: IndexMap<String, Group>,
+    /// `classDef` definitions keyed by class name, in declaration order.
+    #[serde(default)]
+    pub class_defs: IndexMap<String, ClassDef>,
+    /// CSS applied to every link (`linkStyle default`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_link_style: Option<EdgeStyle>,
+    /// Statements the parser did not understand, written back verbatim.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub raw_lines: Vec<RawLine>,
+    /// AI provenance log keyed by node UID, oldest record first.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub provenance: IndexMap<UID, Vec<AiRecord>>,
     pub version: u32,
 }
 
@@ -62,9 +104,21 @@ impl GraphStore {
     /// Create a new empty store.
     pub fn new() -> Self {
         Self {
+            header: DiagramHeader::default(),
+            config: DocumentConfig::default(),
+            accessibility: Accessibility::default(),
+            sequence: None,
+            state_diagram: None,
+            class_diagram: None,
+            er_diagram: None,
             nodes: IndexMap::new(),
             edges: IndexMap::new(),
             alias: AliasMap::default(),
+            groups: IndexMap::new(),
+            class_defs: IndexMap::new(),
+            default_link_style: None,
+            raw_lines: Vec::new(),
+            provenance: IndexMap::new(),
             version: 1,
         }
     }
@@ -85,6 +139,31 @@ impl GraphStore {
         store
     }
 
+    /// Create from a full parse result, including groups and class definitions.
+    pub fn from_parse_result(result: ParseResult) -> Self {
+        let mut store = Self::from_parsed(result.nodes, result.edges);
+        store.header = result.header;
+        store.config = result.config;
+        store.accessibility = result.accessibility;
+        store.sequence = result.sequence;
+        store.state_diagram = result.state_diagram;
+        store.class_diagram = result.class_diagram;
+        store.er_diagram = result.er_diagram;
+        store.default_link_style = result.default_link_style;
+        store.raw_lines = result.raw_lines;
+        store.provenance = result.provenance;
+
+        for group in result.groups {
+            store.upsert_group(group);
+        }
+
+        for class_def in result.class_defs {
+            store.upsert_class_def(class_def);
+        }
+
+        store
+    }
+
     /// Get a node by UID.
     pub fn get_node(&self, uid: &UID) -> Option<&Node> {
         self.nodes.get(uid)
@@ -121,6 +200,31 @@ impl GraphStore {
         self.edges.insert(edge.eid.clone(), edge);
     }
 
+    /// Insert or update a group.
+    pub fn upsert_group(&mut self, group: Group) {
+        self.groups.insert(group.id.clone(), group);
+    }
+
+    /// Get a group by its Mermaid ID.
+    pub fn get_group(&self, id: &str) -> Option<&Group> {
+        self.groups.get(id)
+    }
+
+    /// Get the group a node belongs to directly.
+    pub fn group_of(&self, uid: &UID) -> Option<&Group> {
+        self.groups.values().find(|g| g.members.contains(uid))
+    }
+
+    /// Insert or update a class definition.
+    pub fn upsert_class_def(&mut self, class_def: ClassDef) {
+        self.class_defs.insert(class_def.name.clone(), class_def);
+    }
+
+    /// Get top-level groups (not nested in another group).
+    pub fn root_groups(&self) -> impl Iterator<Item = &Group> {
+        self.groups.values().filter(|g| g.parent.is_none())
+    }
+
     /// Move a node to new coordinates.
     pub fn move_node(&mut self, uid: &UID, x: f64, y: f64) {
         if let Some(node) = self.nodes.get_mut(uid) {
@@ -130,6 +234,18 @@ impl GraphStore {
         }
     }
 
+    /// Move a sequence diagram participant; `false` if there is no such participant.
+    pub fn move_participant(&mut self, id: &str, x: f64, y: f64) -> bool {
+        match self.sequence.as_mut().and_then(|s| s.participant_mut(id)) {
+            Some(participant) => {
+                participant.x = Some(x);
+                participant.y = Some(y);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Rename a node's Mermaid ID.
     pub fn rename_node(&mut self, uid: &UID, new_mermaid_id: &str) {
         self.alias.rename(uid, new_mermaid_id);
@@ -156,6 +272,45 @@ impl GraphStore {
         }
     }
 
+    /// Append an AI action to a node's provenance log.
+    pub fn record_ai(&mut self, uid: &UID, record: AiRecord) {
+        self.provenance.entry(uid.clone()).or_default().push(record);
+    }
+
+    /// A node's AI provenance log, oldest first.
+    pub fn provenance_of(&self, uid: &UID) -> &[AiRecord] {
+        self.provenance.get(uid).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Accept or reject the `index`-th AI action on a node; `false` if there is none.
+    pub fn set_ai_review(&mut self, uid: &UID, index: usize, status: AiReview) -> bool {
+        match self.provenance.get_mut(uid).and_then(|log| log.get_mut(index)) {
+            Some(record) => {
+                record.status = status;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The AI action that wrote a node's current text, if it is still unedited.
+    ///
+    /// That is the latest non-rejected record whose `after_hash` matches
+    /// [`Node::text_hash`].
+    pub fn machine_written(&self, uid: &UID) -> Option<&AiRecord> {
+        let hash = self.get_node(uid)?.text_hash();
+        self.provenance_of(uid)
+            .iter()
+            .rev()
+            .find(|r| r.status != AiReview::Rejected && r.after_hash.as_ref() == Some(&hash))
+    }
+
+    /// Active nodes whose text was written by AI, with the action that wrote it.
+    pub fn machine_written_nodes(&self) -> impl Iterator<Item = (&Node, &AiRecord)> {
+        self.active_nodes()
+            .filter_map(|node| Some((node, self.machine_written(&node.uid)?)))
+    }
+
     /// Get all non-deleted nodes.
     pub fn active_nodes(&self) -> impl Iterator<Item = &Node> {
         self.nodes.values().filter(|n| !n.deleted)
@@ -240,4 +395,52 @@ mod tests {
         assert!(store.get_node(&uid).unwrap().deleted);
         assert_eq!(store.active_nodes().count(), 0);
     }
+
+    #[test]
+    fn test_groups() {
+        let mut store = GraphStore::new();
+        let node = Node::new("A");
+        let uid = node.uid.clone();
+        store.upsert_node(node);
+
+        let mut outer = Group::new("outer");
+        outer.children.push("inner".to_string());
+        let mut inner = Group::new("inner");
+        inner.parent = Some("outer".to_string());
+        inner.members.push(uid.clone());
+        store.upsert_group(outer);
+        store.upsert_group(inner);
+
+        assert_eq!(store.group_of(&uid).unwrap().id, "inner");
+        assert_eq!(store.root_groups().count(), 1);
+    }
+
+    #[test]
+    fn test_machine_written() {
+        let mut store = GraphStore::new();
+        let mut node = Node::new("A");
+        node.label = Some("Draft".to_string());
+        let uid = node.uid.clone();
+        let before = node.text_hash();
+        store.upsert_node(node);
+
+        store.get_node_mut(&uid).unwrap().label = Some("Summary by model".to_string());
+        let mut record = AiRecord::new("summarize");
+        record.before_hash = Some(before);
+        record.after_hash = Some(store.get_node(&uid).unwrap().text_hash());
+        store.record_ai(&uid, record);
+
+        assert_eq!(store.machine_written(&uid).unwrap().action, "summarize");
+        assert_eq!(store.machine_written_nodes().count(), 1);
+
+        assert!(store.set_ai_review(&uid, 0, AiReview::Rejected));
+        assert!(store.machine_written(&uid).is_none());
+        assert!(store.set_ai_review(&uid, 0, AiReview::Accepted));
+        assert!(!store.set_ai_review(&uid, 1, AiReview::Accepted));
+
+        // Edited by hand
+        store.get_node_mut(&uid).unwrap().label = Some("Summary, revised".to_string());
+        assert!(store.machine_written(&uid).is_none());
+        assert_eq!(store.provenance_of(&uid).len(), 1);
+    }
 }
@@ -1,6 +1,8 @@
 //! Core type definitions for Mermaidman.
 
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fmt;
 
 /// Unique node identifier (stable across renames).
@@ -14,6 +16,7 @@ impl UID {
     }
 
     /// Create from existing string.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         Self(s.to_string())
     }
@@ -48,6 +51,7 @@ impl EID {
     }
 
     /// Create from existing string.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         Self(s.to_string())
     }
@@ -111,6 +115,231 @@ impl fmt::Display for DocId {
     }
 }
 
+/// Layout direction of a flowchart or subgraph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub enum Direction {
+    #[serde(rename = "TB")]
+    TopBottom,
+    #[default]
+    #[serde(rename = "TD")]
+    TopDown,
+    #[serde(rename = "BT")]
+    BottomTop,
+    #[serde(rename = "RL")]
+    RightLeft,
+    #[serde(rename = "LR")]
+    LeftRight,
+}
+
+impl Direction {
+    /// Parse a Mermaid direction keyword (`TB`, `TD`, `BT`, `RL`, `LR`).
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "TB" => Some(Direction::TopBottom),
+            "TD" => Some(Direction::TopDown),
+            "BT" => Some(Direction::BottomTop),
+            "RL" => Some(Direction::RightLeft),
+            "LR" => Some(Direction::LeftRight),
+            _ => None,
+        }
+    }
+
+    /// The Mermaid keyword for this direction.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::TopBottom => "TB",
+            Direction::TopDown => "TD",
+            Direction::BottomTop => "BT",
+            Direction::RightLeft => "RL",
+            Direction::LeftRight => "LR",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Keyword that opens a diagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagramKeyword {
+    #[default]
+    Graph,
+    Flowchart,
+    SequenceDiagram,
+    StateDiagram,
+    StateDiagramV2,
+    ClassDiagram,
+    ErDiagram,
+}
+
+impl DiagramKeyword {
+    /// The Mermaid keyword text.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagramKeyword::Graph => "graph",
+            DiagramKeyword::Flowchart => "flowchart",
+            DiagramKeyword::SequenceDiagram => "sequenceDiagram",
+            DiagramKeyword::StateDiagram => "stateDiagram",
+            DiagramKeyword::StateDiagramV2 => "stateDiagram-v2",
+            DiagramKeyword::ClassDiagram => "classDiagram",
+            DiagramKeyword::ErDiagram => "erDiagram",
+        }
+    }
+
+    /// Whether this opens a flowchart, whose statements are edited in place.
+    pub fn is_flowchart(&self) -> bool {
+        matches!(self, DiagramKeyword::Graph | DiagramKeyword::Flowchart)
+    }
+
+    /// Whether this opens a state diagram.
+    pub fn is_state(&self) -> bool {
+        matches!(self, DiagramKeyword::StateDiagram | DiagramKeyword::StateDiagramV2)
+    }
+}
+
+/// Diagram header line, e.g. `graph TD` or `flowchart LR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagramHeader {
+    pub keyword: DiagramKeyword,
+    /// `None` when the header omits a direction (Mermaid then lays out top-down).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+}
+
+impl Default for DiagramHeader {
+    fn default() -> Self {
+        Self {
+            keyword: DiagramKeyword::Graph,
+            direction: Some(Direction::TopDown),
+        }
+    }
+}
+
+impl DiagramHeader {
+    /// Parse a header line; returns `None` if the line is not a header.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim().trim_end_matches(';');
+        let mut parts = line.split_whitespace();
+
+        let keyword = match parts.next()? {
+            "graph" => DiagramKeyword::Graph,
+            "flowchart" => DiagramKeyword::Flowchart,
+            // Only flowcharts have a direction on the header
+            "sequenceDiagram" | "stateDiagram" | "stateDiagram-v2" | "classDiagram" | "erDiagram"
+                if parts.clone().next().is_none() =>
+            {
+                let keyword = match line {
+                    "sequenceDiagram" => DiagramKeyword::SequenceDiagram,
+                    "stateDiagram" => DiagramKeyword::StateDiagram,
+                    "stateDiagram-v2" => DiagramKeyword::StateDiagramV2,
+                    "classDiagram" => DiagramKeyword::ClassDiagram,
+                    _ => DiagramKeyword::ErDiagram,
+                };
+                return Some(Self { keyword, direction: None });
+            }
+            _ => return None,
+        };
+        let direction = match parts.next() {
+            Some(token) => Some(Direction::parse(token)?),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self { keyword, direction })
+    }
+}
+
+/// Other diagrams than flowcharts set their direction with a `direction`
+/// statement in their body, so only the keyword is written for them.
+impl fmt::Display for DiagramHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.direction {
+            Some(direction) if self.keyword.is_flowchart() => write!(f, "{} {}", self.keyword.as_str(), direction),
+            _ => f.write_str(self.keyword.as_str()),
+        }
+    }
+}
+
+/// Mermaid configuration from YAML frontmatter and `%%{init: ...}%%` directives.
+///
+/// Keys follow Mermaid's config schema. Keys without a typed field are kept
+/// in `extra`; typed keys whose value does not fit are dropped with a warning.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentConfig {
+    /// The frontmatter `title`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// `default`, `base`, `dark`, `forest` or `neutral`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    /// Overrides for theme colours and fonts, e.g. `primaryColor`.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub theme_variables: IndexMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flowchart: Option<FlowchartConfig>,
+    #[serde(flatten)]
+    pub extra: IndexMap<String, serde_json::Value>,
+}
+
+impl DocumentConfig {
+    /// Whether nothing is configured.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Flowchart layout options (`config.flowchart`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowchartConfig {
+    /// Edge interpolation: `basis`, `linear`, `step`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub curve: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html_labels: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_spacing: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank_spacing: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagram_padding: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapping_width: Option<u32>,
+    /// `dagre-wrapper` or `elk`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_renderer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_max_width: Option<bool>,
+    #[serde(flatten)]
+    pub extra: IndexMap<String, serde_json::Value>,
+}
+
+/// Accessible title and description (`accTitle`, `accDescr`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Accessibility {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Several lines are written as an `accDescr { ... }` block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl Accessibility {
+    /// Whether neither is set.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.description.is_none()
+    }
+}
+
 /// Node kind enumeration.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
@@ -125,28 +354,233 @@ pub enum NodeKind {
     Oembed,
 }
 
+/// Node shape as drawn by Mermaid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeShape {
+    /// `A[text]`
+    #[default]
+    Rect,
+    /// `A(text)`
+    Rounded,
+    /// `A([text])`
+    Stadium,
+    /// `A[[text]]`
+    Subroutine,
+    /// `A[(text)]`
+    Cylinder,
+    /// `A((text))`
+    Circle,
+    /// `A(((text)))`
+    DoubleCircle,
+    /// `A>text]`
+    Asymmetric,
+    /// `A{text}`
+    Rhombus,
+    /// `A{{text}}`
+    Hexagon,
+    /// `A[/text/]`
+    Parallelogram,
+    /// `A[\text\]`
+    ParallelogramAlt,
+    /// `A[/text\]`
+    Trapezoid,
+    /// `A[\text/]`
+    TrapezoidAlt,
+    /// Any other `A@{ shape: name }` shape, by Mermaid name.
+    Custom(String),
+}
+
+impl NodeShape {
+    /// Opening and closing delimiters for the classic bracket syntax.
+    pub fn delimiters(&self) -> Option<(&'static str, &'static str)> {
+        let delims = match self {
+            NodeShape::Rect => ("[", "]"),
+            NodeShape::Rounded => ("(", ")"),
+            NodeShape::Stadium => ("([", "])"),
+            NodeShape::Subroutine => ("[[", "]]"),
+            NodeShape::Cylinder => ("[(", ")]"),
+            NodeShape::Circle => ("((", "))"),
+            NodeShape::DoubleCircle => ("(((", ")))"),
+            NodeShape::Asymmetric => (">", "]"),
+            NodeShape::Rhombus => ("{", "}"),
+            NodeShape::Hexagon => ("{{", "}}"),
+            NodeShape::Parallelogram => ("[/", "/]"),
+            NodeShape::ParallelogramAlt => ("[\\", "\\]"),
+            NodeShape::Trapezoid => ("[/", "\\]"),
+            NodeShape::TrapezoidAlt => ("[\\", "/]"),
+            NodeShape::Custom(_) => return None,
+        };
+        Some(delims)
+    }
+
+    /// Resolve a shape name from the `A@{ shape: name }` syntax.
+    pub fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "rect" | "rectangle" | "proc" | "process" => NodeShape::Rect,
+            "rounded" | "event" => NodeShape::Rounded,
+            "stadium" | "pill" | "terminal" => NodeShape::Stadium,
+            "subproc" | "subprocess" | "subroutine" | "fr-rect" | "framed-rectangle" => {
+                NodeShape::Subroutine
+            }
+            "cyl" | "cylinder" | "database" | "db" => NodeShape::Cylinder,
+            "circle" | "circ" => NodeShape::Circle,
+            "dbl-circ" | "double-circle" => NodeShape::DoubleCircle,
+            "odd" | "asymmetric" => NodeShape::Asymmetric,
+            "diam" | "diamond" | "decision" | "question" => NodeShape::Rhombus,
+            "hex" | "hexagon" | "prepare" => NodeShape::Hexagon,
+            "lean-r" | "lean-right" | "in-out" => NodeShape::Parallelogram,
+            "lean-l" | "lean-left" | "out-in" => NodeShape::ParallelogramAlt,
+            "trap-b" | "trapezoid-bottom" | "priority" => NodeShape::Trapezoid,
+            "trap-t" | "trapezoid-top" | "manual" => NodeShape::TrapezoidAlt,
+            other => NodeShape::Custom(other.to_string()),
+        }
+    }
+}
+
 /// Arrow style for edges.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum ArrowKind {
+    /// Regular arrowhead (`>` / `<`).
     #[default]
     Default,
     None,
+    /// Circle head (`o`).
+    Circle,
+    /// Cross head (`x`).
+    Cross,
+}
+
+/// Line stroke for edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LineKind {
+    /// `-->`
+    #[default]
+    Normal,
+    /// `-.->`
+    Dotted,
+    /// `==>`
+    Thick,
+    /// `~~~`
+    Invisible,
 }
 
 /// Edge style properties.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+///
+/// `line`, `arrow_start`, `arrow`, `length` and `dashed` mirror the Mermaid
+/// link syntax; unset fields mean a plain `-->`. `stroke`, `stroke_width`,
+/// `color` and `extra` are the CSS of a `linkStyle` statement.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct EdgeStyle {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stroke: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke_width: Option<String>,
+    /// Label text colour.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Other CSS properties, in declaration order.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub extra: IndexMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub dashed: Option<bool>,
+    /// Arrowhead at the target end.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub arrow: Option<ArrowKind>,
+    /// Arrowhead at the source end.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrow_start: Option<ArrowKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<LineKind>,
+    /// Link length: `-->` is 1, `--->` is 2, `---->` is 3.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<u8>,
+}
+
+impl EdgeStyle {
+    /// Effective line kind, honouring the canvas `dashed` flag.
+    pub fn line_kind(&self) -> LineKind {
+        match (self.line, self.dashed) {
+            (Some(line), _) => line,
+            (None, Some(true)) => LineKind::Dotted,
+            (None, _) => LineKind::Normal,
+        }
+    }
+
+    /// Copy the link-syntax fields from another style, keeping the rest.
+    pub fn set_link(&mut self, link: &EdgeStyle) {
+        self.dashed = link.dashed;
+        self.arrow = link.arrow;
+        self.arrow_start = link.arrow_start;
+        self.line = link.line;
+        self.length = link.length;
+    }
+
+    /// Fill unset fields from another style, such as one read from a directive.
+    pub fn fill_from(&mut self, other: &EdgeStyle) {
+        self.stroke = self.stroke.take().or_else(|| other.stroke.clone());
+        self.stroke_width = self.stroke_width.take().or_else(|| other.stroke_width.clone());
+        self.color = self.color.take().or_else(|| other.color.clone());
+        for (key, value) in &other.extra {
+            self.extra.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        self.dashed = self.dashed.or(other.dashed);
+        self.arrow = self.arrow.or(other.arrow);
+        self.arrow_start = self.arrow_start.or(other.arrow_start);
+        self.line = self.line.or(other.line);
+        self.length = self.length.or(other.length);
+    }
+
+    /// Parse a `linkStyle` CSS list: `stroke:#f00,stroke-width:4px`.
+    pub fn from_css(css: &str) -> Self {
+        let mut style = Self::default();
+
+        for (key, value) in split_css(css) {
+            match key.as_str() {
+                "stroke" => style.stroke = Some(value),
+                "stroke-width" => style.stroke_width = Some(value),
+                "color" => style.color = Some(value),
+                _ => {
+                    style.extra.insert(key, value);
+                }
+            }
+        }
+
+        style
+    }
+
+    /// Format the `linkStyle` CSS properties as a CSS list.
+    pub fn to_css(&self) -> String {
+        let typed = [
+            ("stroke", &self.stroke),
+            ("stroke-width", &self.stroke_width),
+            ("color", &self.color),
+        ];
+
+        typed
+            .iter()
+            .filter_map(|(key, value)| value.as_ref().map(|v| format!("{}:{}", key, v)))
+            .chain(self.extra.iter().map(|(k, v)| format!("{}:{}", k, v)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Copy the `linkStyle` CSS properties from another style, keeping the rest.
+    pub fn set_css(&mut self, css: &EdgeStyle) {
+        self.stroke = css.stroke.clone();
+        self.stroke_width = css.stroke_width.clone();
+        self.color = css.color.clone();
+        self.extra = css.extra.clone();
+    }
 }
 
 /// Node style properties.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+///
+/// Everything except `border` maps onto Mermaid CSS (`style A fill:#f9f`);
+/// properties without a typed field are kept in `extra`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct NodeStyle {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stroke: Option<String>,
@@ -154,6 +588,143 @@ pub struct NodeStyle {
     pub fill: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub border: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke_width: Option<String>,
+    /// Text colour.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Other CSS properties, in declaration order.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub extra: IndexMap<String, String>,
+}
+
+impl NodeStyle {
+    /// Parse a Mermaid CSS list: `fill:#f9f,stroke:#333,stroke-width:4px`.
+    ///
+    /// Commas escaped as `\,` stay inside the value.
+    pub fn from_css(css: &str) -> Self {
+        let mut style = Self::default();
+
+        for (key, value) in split_css(css) {
+            match key.as_str() {
+                "fill" => style.fill = Some(value),
+                "stroke" => style.stroke = Some(value),
+                "stroke-width" => style.stroke_width = Some(value),
+                "color" => style.color = Some(value),
+                _ => {
+                    style.extra.insert(key, value);
+                }
+            }
+        }
+
+        style
+    }
+
+    /// Format the Mermaid-visible properties as a CSS list (`border` is canvas-only).
+    pub fn to_css(&self) -> String {
+        let typed = [
+            ("fill", &self.fill),
+            ("stroke", &self.stroke),
+            ("stroke-width", &self.stroke_width),
+            ("color", &self.color),
+        ];
+
+        typed
+            .iter()
+            .filter_map(|(key, value)| value.as_ref().map(|v| format!("{}:{}", key, v)))
+            .chain(self.extra.iter().map(|(k, v)| format!("{}:{}", k, v)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Fill unset properties from another style, such as one read from a directive.
+    pub fn fill_from(&mut self, other: &NodeStyle) {
+        self.stroke = self.stroke.take().or_else(|| other.stroke.clone());
+        self.fill = self.fill.take().or_else(|| other.fill.clone());
+        self.border = self.border.take().or_else(|| other.border.clone());
+        self.stroke_width = self.stroke_width.take().or_else(|| other.stroke_width.clone());
+        self.color = self.color.take().or_else(|| other.color.clone());
+        for (key, value) in &other.extra {
+            self.extra.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Copy the Mermaid CSS properties from another style, keeping `border`.
+    pub fn set_css(&mut self, css: &NodeStyle) {
+        self.fill = css.fill.clone();
+        self.stroke = css.stroke.clone();
+        self.stroke_width = css.stroke_width.clone();
+        self.color = css.color.clone();
+        self.extra = css.extra.clone();
+    }
+}
+
+/// Split a Mermaid CSS list into trimmed `(key, value)` pairs.
+fn split_css(css: &str) -> Vec<(String, String)> {
+    let css = css.trim().trim_end_matches(';');
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let bytes = css.as_bytes();
+
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b',' && (i == 0 || bytes[i - 1] != b'\\') {
+            parts.push(&css[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&css[start..]);
+
+    parts
+        .into_iter()
+        .filter_map(|part| {
+            let (key, value) = part.split_once(':')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// A named Mermaid class (`classDef name fill:#f9f`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClassDef {
+    pub name: String,
+    pub style: NodeStyle,
+}
+
+/// Interaction from a Mermaid `click` statement.
+///
+/// Either `href` (with optional `target`) or `callback` is set. A callback
+/// written with `call` keeps its parentheses: `click A call run(1)` gives `run(1)`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeLink {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    /// Link target such as `_blank`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<String>,
+}
+
+impl NodeLink {
+    /// If `href` points at another Mermaidman file, its path and `#fragment`.
+    ///
+    /// URLs with a scheme other than `file://` are not diagram links.
+    pub fn diagram_target(&self) -> Option<(&str, Option<&str>)> {
+        let href = self.href.as_deref()?;
+        let href = href.strip_prefix("file://").unwrap_or(href);
+        if href.contains("://") {
+            return None;
+        }
+
+        let (path, fragment) = match href.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment).filter(|f| !f.is_empty())),
+            None => (href, None),
+        };
+
+        path.ends_with(".mmd").then_some((path, fragment))
+    }
 }
 
 /// Code block metadata.
@@ -204,8 +775,16 @@ pub struct Node {
     pub y: Option<f64>,
     #[serde(default)]
     pub kind: NodeKind,
+    #[serde(default)]
+    pub shape: NodeShape,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub style: Option<NodeStyle>,
+    /// Mermaid classes applied via `class` or `:::`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<String>,
+    /// Mermaid `click` interaction.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<NodeLink>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<CodeMeta>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -232,7 +811,10 @@ impl Node {
             x: None,
             y: None,
             kind: NodeKind::default(),
+            shape: NodeShape::default(),
             style: None,
+            classes: Vec::new(),
+            link: None,
             code: None,
             media: None,
             diagram: None,
@@ -252,7 +834,10 @@ impl Node {
             x: None,
             y: None,
             kind: NodeKind::default(),
+            shape: NodeShape::default(),
             style: None,
+            classes: Vec::new(),
+            link: None,
             code: None,
             media: None,
             diagram: None,
@@ -262,6 +847,67 @@ impl Node {
             updated_at: Some(now()),
         }
     }
+
+    /// Hash of the node's written text (label, markdown and code), used to
+    /// tell whether text an AI action produced is still in place.
+    pub fn text_hash(&self) -> BlobId {
+        let code = self.code.as_ref().and_then(|c| c.content.as_deref());
+        let mut hasher = Sha256::new();
+        for part in [self.label.as_deref(), self.markdown.as_deref(), code] {
+            hasher.update(part.unwrap_or_default());
+            hasher.update([0]);
+        }
+        BlobId::from_hash(&hasher.finalize())
+    }
+}
+
+/// Review state of an AI change to a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AiReview {
+    /// Applied but not yet reviewed.
+    #[default]
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+/// Provenance of one AI action on a node, kept in a `%% @ai` directive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AiRecord {
+    /// What was done: `summarize`, `expand`, `format`, ...
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub status: AiReview,
+    /// [`Node::text_hash`] before the action.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before_hash: Option<BlobId>,
+    /// [`Node::text_hash`] of the text the action produced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after_hash: Option<BlobId>,
+    /// Other directive keys, such as `model` or `prompt_hash`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+impl AiRecord {
+    /// Create a pending record for an action, stamped with the current time.
+    pub fn new(action: &str) -> Self {
+        Self {
+            action: action.to_string(),
+            provider: None,
+            timestamp: Some(now()),
+            status: AiReview::Pending,
+            before_hash: None,
+            after_hash: None,
+            meta: None,
+        }
+    }
 }
 
 /// An edge in the graph.
@@ -270,6 +916,9 @@ pub struct Edge {
     pub eid: EID,
     pub source: UID,
     pub target: UID,
+    /// The Mermaid edge ID (`A e1@--> B`), if the link declares one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mermaid_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub label: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -289,6 +938,7 @@ impl Edge {
             eid: EID::new(),
             source,
             target,
+            mermaid_id: None,
             label: None,
             style: None,
             meta: None,
@@ -303,6 +953,7 @@ impl Edge {
             eid,
             source,
             target,
+            mermaid_id: None,
             label: None,
             style: None,
             meta: None,
@@ -312,6 +963,781 @@ impl Edge {
     }
 }
 
+/// A group of nodes (Mermaid `subgraph`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Group {
+    /// The subgraph ID as written in Mermaid.
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    /// Nodes that belong directly to this group.
+    #[serde(default)]
+    pub members: Vec<UID>,
+    /// IDs of groups nested directly inside this one.
+    #[serde(default)]
+    pub children: Vec<String>,
+    /// ID of the enclosing group, if nested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// Members that open a new concurrent region of a composite state; each
+    /// is written after a `--` line.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub region_starts: Vec<UID>,
+}
+
+impl Group {
+    /// Create an empty group.
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A topology line the parser did not understand, kept so writing the
+/// document reproduces it verbatim. Plain `%%` comments are kept the same way.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RawLine {
+    /// The statement as written, without surrounding whitespace.
+    pub text: String,
+    /// ID of the enclosing subgraph, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// The class or entity whose `{ ... }` body holds the line, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+impl RawLine {
+    /// Whether the line is a `%%` comment rather than a statement.
+    pub fn is_comment(&self) -> bool {
+        self.text.starts_with("%%")
+    }
+}
+
+/// What a state diagram node stands for, told by its shape.
+///
+/// `[*]` start and end states are nodes named `<scope>_start` and
+/// `<scope>_end`, where the scope is the enclosing composite state or `root`.
+/// Notes are nodes named `<state>_note_left` or `<state>_note_right`, linked
+/// to their state by a dotted edge; their placement is kept in the shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StateKind {
+    State,
+    Start,
+    End,
+    /// `<<choice>>`
+    Choice,
+    /// `<<fork>>`
+    Fork,
+    /// `<<join>>`
+    Join,
+    /// `note left of` or `note right of`
+    Note(NotePlacement),
+}
+
+impl StateKind {
+    /// The kind a node's shape stands for.
+    pub fn of(shape: &NodeShape) -> Self {
+        match shape {
+            NodeShape::Rhombus => StateKind::Choice,
+            NodeShape::Custom(name) => match name.as_str() {
+                "start" => StateKind::Start,
+                "stop" => StateKind::End,
+                "fork" => StateKind::Fork,
+                "join" => StateKind::Join,
+                "note-left" => StateKind::Note(NotePlacement::LeftOf),
+                "note" | "note-right" => StateKind::Note(NotePlacement::RightOf),
+                _ => StateKind::State,
+            },
+            _ => StateKind::State,
+        }
+    }
+
+    /// The shape a node of this kind is drawn with.
+    pub fn shape(&self) -> NodeShape {
+        match self {
+            StateKind::State => NodeShape::Rounded,
+            StateKind::Start => NodeShape::Custom("start".to_string()),
+            StateKind::End => NodeShape::Custom("stop".to_string()),
+            StateKind::Choice => NodeShape::Rhombus,
+            StateKind::Fork => NodeShape::Custom("fork".to_string()),
+            StateKind::Join => NodeShape::Custom("join".to_string()),
+            StateKind::Note(NotePlacement::LeftOf) => NodeShape::Custom("note-left".to_string()),
+            StateKind::Note(_) => NodeShape::Custom("note-right".to_string()),
+        }
+    }
+}
+
+/// What a state diagram says beyond its nodes: the `ID : text` description
+/// lines shown under a state's name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateDiagram {
+    /// Description lines by state ID, in the order written.
+    pub descriptions: IndexMap<String, Vec<String>>,
+}
+
+impl StateDiagram {
+    /// The description lines of a state.
+    pub fn descriptions(&self, id: &str) -> &[String] {
+        self.descriptions.get(id).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// A sequence diagram: participants and the statements between them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SequenceDiagram {
+    /// Participants in display order: declared or first mentioned.
+    pub participants: Vec<Participant>,
+    pub statements: Vec<SequenceStatement>,
+    /// `box ... end` groups of participants.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub boxes: Vec<ParticipantBox>,
+}
+
+impl SequenceDiagram {
+    /// Get a participant by its Mermaid ID.
+    pub fn participant(&self, id: &str) -> Option<&Participant> {
+        self.participants.iter().find(|p| p.id == id)
+    }
+
+    /// Get a mutable participant by its Mermaid ID.
+    pub fn participant_mut(&mut self, id: &str) -> Option<&mut Participant> {
+        self.participants.iter_mut().find(|p| p.id == id)
+    }
+}
+
+/// Participants declared together inside `box Aqua Group ... end`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParticipantBox {
+    /// The text after `box`: an optional color, then the title.
+    pub label: String,
+    /// IDs of the participants declared in the box, in order.
+    pub participants: Vec<String>,
+}
+
+/// A sequence diagram participant, with layout from its `%% @participant` directive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Participant {
+    pub uid: UID,
+    /// Mermaid ID used in messages.
+    pub id: String,
+    /// Display name from `participant A as Alice`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default)]
+    pub kind: ParticipantKind,
+    /// Whether a `participant`/`actor` line declares it, rather than a first mention.
+    #[serde(default)]
+    pub declared: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+impl Participant {
+    /// Create a participant with a fresh UID.
+    pub fn new(id: &str) -> Self {
+        Self {
+            uid: UID::new(),
+            id: id.to_string(),
+            alias: None,
+            kind: ParticipantKind::default(),
+            declared: false,
+            x: None,
+            y: None,
+            meta: None,
+        }
+    }
+}
+
+/// How a participant is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticipantKind {
+    /// A box (`participant`).
+    #[default]
+    Participant,
+    /// A stick figure (`actor`).
+    Actor,
+}
+
+impl ParticipantKind {
+    /// The declaring keyword.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParticipantKind::Participant => "participant",
+            ParticipantKind::Actor => "actor",
+        }
+    }
+}
+
+/// One statement of a sequence diagram, in source order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SequenceStatement {
+    Message(Message),
+    /// `activate A` (`active: true`) or `deactivate A`.
+    Activation { participant: String, active: bool },
+    Note(SequenceNote),
+    Block(SequenceBlock),
+    /// A statement the parser did not understand, or a `%%` comment.
+    Raw { text: String },
+}
+
+/// A message arrow between two participants.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub from: String,
+    pub to: String,
+    pub arrow: MessageArrow,
+    #[serde(default)]
+    pub text: String,
+    /// `+` activates the target, `-` deactivates the source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activation: Option<bool>,
+}
+
+/// Message arrow syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageArrow {
+    /// `->`
+    Solid,
+    /// `-->`
+    Dotted,
+    /// `->>`
+    SolidArrow,
+    /// `-->>`
+    DottedArrow,
+    /// `-x`
+    SolidCross,
+    /// `--x`
+    DottedCross,
+    /// `-)`
+    SolidAsync,
+    /// `--)`
+    DottedAsync,
+    /// `<<->>`
+    BidirectionalSolid,
+    /// `<<-->>`
+    BidirectionalDotted,
+}
+
+impl MessageArrow {
+    /// Every arrow, longest syntax first so parsing can try them in order.
+    pub const ALL: [MessageArrow; 10] = [
+        MessageArrow::BidirectionalDotted,
+        MessageArrow::BidirectionalSolid,
+        MessageArrow::DottedArrow,
+        MessageArrow::SolidArrow,
+        MessageArrow::DottedCross,
+        MessageArrow::DottedAsync,
+        MessageArrow::Dotted,
+        MessageArrow::SolidCross,
+        MessageArrow::SolidAsync,
+        MessageArrow::Solid,
+    ];
+
+    /// The arrow as written.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageArrow::Solid => "->",
+            MessageArrow::Dotted => "-->",
+            MessageArrow::SolidArrow => "->>",
+            MessageArrow::DottedArrow => "-->>",
+            MessageArrow::SolidCross => "-x",
+            MessageArrow::DottedCross => "--x",
+            MessageArrow::SolidAsync => "-)",
+            MessageArrow::DottedAsync => "--)",
+            MessageArrow::BidirectionalSolid => "<<->>",
+            MessageArrow::BidirectionalDotted => "<<-->>",
+        }
+    }
+}
+
+/// A note beside or over participants.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SequenceNote {
+    pub placement: NotePlacement,
+    /// One participant, or two for a note spanning them (`over A,B`).
+    pub participants: Vec<String>,
+    pub text: String,
+}
+
+/// Where a note sits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotePlacement {
+    LeftOf,
+    RightOf,
+    Over,
+}
+
+impl NotePlacement {
+    /// The placement as written, e.g. `left of`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotePlacement::LeftOf => "left of",
+            NotePlacement::RightOf => "right of",
+            NotePlacement::Over => "over",
+        }
+    }
+}
+
+/// A `loop`, `alt`, `opt`, `par`, `critical`, `break` or `rect` block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SequenceBlock {
+    pub kind: BlockKind,
+    /// The first section; further ones follow `else`, `and` or `option`.
+    pub sections: Vec<BlockSection>,
+}
+
+/// One section of a block, with the label written after its keyword.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockSection {
+    #[serde(default)]
+    pub label: String,
+    pub statements: Vec<SequenceStatement>,
+}
+
+/// Sequence block keyword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockKind {
+    Loop,
+    Alt,
+    Opt,
+    Par,
+    Critical,
+    Break,
+    /// A background highlight; its label is the colour.
+    Rect,
+}
+
+impl BlockKind {
+    /// The opening keyword.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockKind::Loop => "loop",
+            BlockKind::Alt => "alt",
+            BlockKind::Opt => "opt",
+            BlockKind::Par => "par",
+            BlockKind::Critical => "critical",
+            BlockKind::Break => "break",
+            BlockKind::Rect => "rect",
+        }
+    }
+
+    /// Parse an opening keyword.
+    pub fn parse(keyword: &str) -> Option<Self> {
+        [
+            BlockKind::Loop,
+            BlockKind::Alt,
+            BlockKind::Opt,
+            BlockKind::Par,
+            BlockKind::Critical,
+            BlockKind::Break,
+            BlockKind::Rect,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == keyword)
+    }
+
+    /// The keyword that starts a further section, if the block has them.
+    pub fn section_keyword(&self) -> Option<&'static str> {
+        match self {
+            BlockKind::Alt => Some("else"),
+            BlockKind::Par => Some("and"),
+            BlockKind::Critical => Some("option"),
+            _ => None,
+        }
+    }
+}
+
+/// A class diagram: classes and the relationships between them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClassDiagram {
+    pub classes: Vec<Class>,
+    pub relations: Vec<ClassRelation>,
+}
+
+impl ClassDiagram {
+    /// Find a class by name.
+    pub fn class(&self, name: &str) -> Option<&Class> {
+        self.classes.iter().find(|c| c.name == name)
+    }
+
+    /// Find a class by name, adding an empty one if there is none.
+    pub fn class_mut(&mut self, name: &str) -> &mut Class {
+        let index = match self.classes.iter().position(|c| c.name == name) {
+            Some(index) => index,
+            None => {
+                self.classes.push(Class::new(name));
+                self.classes.len() - 1
+            }
+        };
+        &mut self.classes[index]
+    }
+}
+
+/// A class and its members.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Class {
+    pub name: String,
+    /// Generic parameters, written `Name~T~`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generic: Option<String>,
+    /// Display label from `class Name["label"]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// `<<interface>>`, `<<abstract>>`, ... without the brackets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<String>,
+    #[serde(default)]
+    pub members: Vec<ClassMember>,
+}
+
+impl Class {
+    /// Create a class without members.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// An attribute or method of a class.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClassMember {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    pub name: String,
+    /// The attribute's type, or the method's return type.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub type_name: Option<String>,
+    /// The method's parameters as written; `None` for attributes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub classifier: Option<Classifier>,
+}
+
+impl ClassMember {
+    /// Whether the member is a method.
+    pub fn is_method(&self) -> bool {
+        self.params.is_some()
+    }
+}
+
+/// Member visibility.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// `+`
+    Public,
+    /// `-`
+    Private,
+    /// `#`
+    Protected,
+    /// `~`
+    Package,
+}
+
+impl Visibility {
+    /// Parse a visibility marker.
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(Visibility::Public),
+            '-' => Some(Visibility::Private),
+            '#' => Some(Visibility::Protected),
+            '~' => Some(Visibility::Package),
+            _ => None,
+        }
+    }
+
+    /// The marker as written.
+    pub fn as_char(&self) -> char {
+        match self {
+            Visibility::Public => '+',
+            Visibility::Private => '-',
+            Visibility::Protected => '#',
+            Visibility::Package => '~',
+        }
+    }
+}
+
+/// Member classifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Classifier {
+    /// `$`
+    Static,
+    /// `*`
+    Abstract,
+}
+
+impl Classifier {
+    /// Parse a classifier marker.
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '$' => Some(Classifier::Static),
+            '*' => Some(Classifier::Abstract),
+            _ => None,
+        }
+    }
+
+    /// The marker as written.
+    pub fn as_char(&self) -> char {
+        match self {
+            Classifier::Static => '$',
+            Classifier::Abstract => '*',
+        }
+    }
+}
+
+/// A relationship between two classes, e.g. `A "1" *-- "many" B : has`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClassRelation {
+    pub from: String,
+    pub to: String,
+    /// Marker at the `from` end.
+    #[serde(default)]
+    pub from_end: RelationEnd,
+    /// Marker at the `to` end.
+    #[serde(default)]
+    pub to_end: RelationEnd,
+    /// `..` rather than `--`.
+    #[serde(default)]
+    pub dashed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_cardinality: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_cardinality: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// The marker at one end of a class relationship.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationEnd {
+    #[default]
+    None,
+    /// `<|` / `|>`
+    Inheritance,
+    /// `*`
+    Composition,
+    /// `o`
+    Aggregation,
+    /// `<` / `>`
+    Arrow,
+    /// `()`
+    Lollipop,
+}
+
+impl RelationEnd {
+    const ALL: [RelationEnd; 6] = [
+        RelationEnd::None,
+        RelationEnd::Inheritance,
+        RelationEnd::Composition,
+        RelationEnd::Aggregation,
+        RelationEnd::Arrow,
+        RelationEnd::Lollipop,
+    ];
+
+    /// The marker as written before the line.
+    pub fn left(&self) -> &'static str {
+        match self {
+            RelationEnd::None => "",
+            RelationEnd::Inheritance => "<|",
+            RelationEnd::Composition => "*",
+            RelationEnd::Aggregation => "o",
+            RelationEnd::Arrow => "<",
+            RelationEnd::Lollipop => "()",
+        }
+    }
+
+    /// The marker as written after the line.
+    pub fn right(&self) -> &'static str {
+        match self {
+            RelationEnd::Inheritance => "|>",
+            RelationEnd::Arrow => ">",
+            other => other.left(),
+        }
+    }
+
+    /// Parse a marker written before the line.
+    pub fn parse_left(marker: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|end| end.left() == marker)
+    }
+
+    /// Parse a marker written after the line.
+    pub fn parse_right(marker: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|end| end.right() == marker)
+    }
+}
+
+/// An entity relationship diagram.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ErDiagram {
+    pub entities: Vec<Entity>,
+    pub relationships: Vec<ErRelationship>,
+}
+
+impl ErDiagram {
+    /// Find an entity by name.
+    pub fn entity(&self, name: &str) -> Option<&Entity> {
+        self.entities.iter().find(|e| e.name == name)
+    }
+
+    /// Find an entity by name, adding an empty one if there is none.
+    pub fn entity_mut(&mut self, name: &str) -> &mut Entity {
+        let index = match self.entities.iter().position(|e| e.name == name) {
+            Some(index) => index,
+            None => {
+                self.entities.push(Entity::new(name));
+                self.entities.len() - 1
+            }
+        };
+        &mut self.entities[index]
+    }
+}
+
+/// An entity and its attributes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    pub name: String,
+    /// Display name from `name[Alias]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default)]
+    pub attributes: Vec<ErAttribute>,
+}
+
+impl Entity {
+    /// Create an entity without attributes.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// An entity attribute, e.g. `string email UK "login"`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ErAttribute {
+    pub type_name: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<AttributeKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// A key constraint on an attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttributeKey {
+    #[serde(rename = "PK")]
+    Primary,
+    #[serde(rename = "FK")]
+    Foreign,
+    #[serde(rename = "UK")]
+    Unique,
+}
+
+impl AttributeKey {
+    /// Parse `PK`, `FK` or `UK`.
+    pub fn parse(key: &str) -> Option<Self> {
+        match key {
+            "PK" => Some(AttributeKey::Primary),
+            "FK" => Some(AttributeKey::Foreign),
+            "UK" => Some(AttributeKey::Unique),
+            _ => None,
+        }
+    }
+
+    /// The key as written.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeKey::Primary => "PK",
+            AttributeKey::Foreign => "FK",
+            AttributeKey::Unique => "UK",
+        }
+    }
+}
+
+/// A relationship between two entities, e.g. `CUSTOMER ||--o{ ORDER : places`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErRelationship {
+    pub from: String,
+    pub to: String,
+    /// How many `from` entities each `to` entity relates to.
+    pub from_cardinality: Cardinality,
+    /// How many `to` entities each `from` entity relates to.
+    pub to_cardinality: Cardinality,
+    /// `--` rather than `..`.
+    pub identifying: bool,
+    pub label: String,
+}
+
+/// Crow's foot cardinality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cardinality {
+    ZeroOrOne,
+    ExactlyOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+impl Cardinality {
+    const ALL: [Cardinality; 4] = [
+        Cardinality::ZeroOrOne,
+        Cardinality::ExactlyOne,
+        Cardinality::ZeroOrMore,
+        Cardinality::OneOrMore,
+    ];
+
+    /// The symbol as written before the line.
+    pub fn left(&self) -> &'static str {
+        match self {
+            Cardinality::ZeroOrOne => "|o",
+            Cardinality::ExactlyOne => "||",
+            Cardinality::ZeroOrMore => "}o",
+            Cardinality::OneOrMore => "}|",
+        }
+    }
+
+    /// The symbol as written after the line.
+    pub fn right(&self) -> &'static str {
+        match self {
+            Cardinality::ZeroOrOne => "o|",
+            Cardinality::ExactlyOne => "||",
+            Cardinality::ZeroOrMore => "o{",
+            Cardinality::OneOrMore => "|{",
+        }
+    }
+
+    /// Parse a symbol written before the line.
+    pub fn parse_left(symbol: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.left() == symbol)
+    }
+
+    /// Parse a symbol written after the line.
+    pub fn parse_right(symbol: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.right() == symbol)
+    }
+}
+
 /// Blob reference.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlobRef {
@@ -350,4 +1776,75 @@ mod tests {
         let json = serde_json::to_string(&node).unwrap();
         assert!(json.contains("\"mermaid_id\":\"A\""));
     }
+
+    #[test]
+    fn test_diagram_header() {
+        let header = DiagramHeader::parse("flowchart LR;").unwrap();
+        assert_eq!(header.keyword, DiagramKeyword::Flowchart);
+        assert_eq!(header.direction, Some(Direction::LeftRight));
+        assert_eq!(header.to_string(), "flowchart LR");
+
+        let bare = DiagramHeader::parse("graph").unwrap();
+        assert_eq!(bare.direction, None);
+        assert_eq!(bare.to_string(), "graph");
+
+        assert!(DiagramHeader::parse("graphic[Node]").is_none());
+        assert!(DiagramHeader::parse("graph --> B").is_none());
+    }
+
+    #[test]
+    fn test_node_style_css() {
+        let style = NodeStyle::from_css("fill:#f9f, stroke:#333,stroke-width:4px,stroke-dasharray: 5 5,font-family:a\\,b;");
+
+        assert_eq!(style.fill, Some("#f9f".to_string()));
+        assert_eq!(style.stroke_width, Some("4px".to_string()));
+        assert_eq!(style.extra.get("stroke-dasharray"), Some(&"5 5".to_string()));
+        assert_eq!(style.extra.get("font-family"), Some(&"a\\,b".to_string()));
+        assert_eq!(
+            style.to_css(),
+            "fill:#f9f,stroke:#333,stroke-width:4px,stroke-dasharray:5 5,font-family:a\\,b"
+        );
+    }
+
+    #[test]
+    fn test_edge_style_css() {
+        let mut style = EdgeStyle::from_css("stroke:#f00, stroke-width:2px,fill:none");
+        assert_eq!(style.stroke, Some("#f00".to_string()));
+        assert_eq!(style.to_css(), "stroke:#f00,stroke-width:2px,fill:none");
+
+        style.line = Some(LineKind::Thick);
+        let mut other = EdgeStyle::default();
+        other.set_css(&style);
+        assert_eq!(other.to_css(), style.to_css());
+        assert_eq!(other.line, None);
+    }
+
+    #[test]
+    fn test_node_link_diagram_target() {
+        let link = |href: &str| NodeLink {
+            href: Some(href.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(link("other.mmd").diagram_target(), Some(("other.mmd", None)));
+        assert_eq!(
+            link("../flows/login.mmd#Start").diagram_target(),
+            Some(("../flows/login.mmd", Some("Start")))
+        );
+        assert_eq!(
+            link("file:///tmp/a.mmd").diagram_target(),
+            Some(("/tmp/a.mmd", None))
+        );
+        assert_eq!(link("https://example.com/a.mmd").diagram_target(), None);
+        assert_eq!(link("notes.md").diagram_target(), None);
+        assert_eq!(NodeLink::default().diagram_target(), None);
+    }
+
+    #[test]
+    fn test_node_shape_from_name() {
+        assert_eq!(NodeShape::from_name("diam"), NodeShape::Rhombus);
+        assert_eq!(NodeShape::from_name("lean-l"), NodeShape::ParallelogramAlt);
+        assert_eq!(NodeShape::from_name("doc"), NodeShape::Custom("doc".to_string()));
+        assert_eq!(NodeShape::Custom("doc".to_string()).delimiters(), None);
+    }
 }
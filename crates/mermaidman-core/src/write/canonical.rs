@@ -1,11 +1,42 @@
 //! Canonical JSON serialization with stable key ordering.
 
-use crate::types::{Edge, Node};
+use crate::migrate::SCHEMA_VERSION;
+use crate::parse::config_mapping;
+use crate::types::{AiRecord, AiReview, DocumentConfig, Edge, Node, Participant, UID};
 use indexmap::IndexMap;
 use serde_json::Value;
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::collections::HashSet;
+
+/// Edge style keys carried by the Mermaid link syntax rather than the directive.
+const LINK_STYLE_KEYS: &[&str] = &["dashed", "arrow", "arrow_start", "line", "length"];
+
+/// Edge style keys carried by Mermaid `linkStyle` statements rather than the directive.
+const LINK_CSS_KEYS: &[&str] = &["stroke", "stroke_width", "color", "extra"];
+
+/// Node style keys carried by Mermaid `style` statements rather than the directive.
+const NODE_CSS_KEYS: &[&str] = &["fill", "stroke", "stroke_width", "color", "extra"];
+
+/// How a directive's JSON body is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DirectiveLayout {
+    /// The whole directive on one line.
+    #[default]
+    Compact,
+    /// The body pretty-printed over `%%` continuation lines.
+    Expanded,
+    /// Expanded when the node carries a nested diagram, markdown or code, or
+    /// the compact line would be longer than `max_width`; compact otherwise.
+    Auto { max_width: usize },
+}
 
 /// Serialize a node directive to canonical JSON.
 pub fn canonical_node_directive(node: &Node) -> String {
+    serde_json::to_string(&node_directive_map(node)).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// Directive fields of a node, in canonical key order.
+fn node_directive_map(node: &Node) -> IndexMap<&'static str, Value> {
     let mut map: IndexMap<&str, Value> = IndexMap::new();
     
     // Fixed key order for diff-friendliness
@@ -25,7 +56,10 @@ pub fn canonical_node_directive(node: &Node) -> String {
     
     // Add other fields as needed
     if let Some(ref style) = node.style {
-        if let Ok(style_json) = serde_json::to_value(style) {
+        if let Ok(mut style_json) = serde_json::to_value(style) {
+            if let Some(obj) = style_json.as_object_mut() {
+                obj.retain(|key, _| !NODE_CSS_KEYS.contains(&key.as_str()));
+            }
             if !style_json.as_object().map(|o| o.is_empty()).unwrap_or(true) {
                 map.insert("style", style_json);
             }
@@ -58,8 +92,7 @@ pub fn canonical_node_directive(node: &Node) -> String {
         map.insert("meta", meta.clone());
     }
 
-    // Serialize with stable ordering
-    serde_json::to_string(&map).unwrap_or_else(|_| "{}".to_string())
+    map
 }
 
 /// Serialize an edge directive to canonical JSON.
@@ -75,7 +108,12 @@ pub fn canonical_edge_directive(edge: &Edge) -> String {
     }
     
     if let Some(ref style) = edge.style {
-        if let Ok(style_json) = serde_json::to_value(style) {
+        if let Ok(mut style_json) = serde_json::to_value(style) {
+            if let Some(obj) = style_json.as_object_mut() {
+                obj.retain(|key, _| {
+                    !LINK_STYLE_KEYS.contains(&key.as_str()) && !LINK_CSS_KEYS.contains(&key.as_str())
+                });
+            }
             if !style_json.as_object().map(|o| o.is_empty()).unwrap_or(true) {
                 map.insert("style", style_json);
             }
@@ -89,16 +127,174 @@ pub fn canonical_edge_directive(edge: &Edge) -> String {
     serde_json::to_string(&map).unwrap_or_else(|_| "{}".to_string())
 }
 
+/// Serialize a participant directive to canonical JSON.
+pub fn canonical_participant_directive(participant: &Participant) -> String {
+    let mut map: IndexMap<&str, Value> = IndexMap::new();
+
+    map.insert("uid", Value::String(participant.uid.0.clone()));
+    if let Some(x) = participant.x.and_then(serde_json::Number::from_f64) {
+        map.insert("x", Value::Number(x));
+    }
+    if let Some(y) = participant.y.and_then(serde_json::Number::from_f64) {
+        map.insert("y", Value::Number(y));
+    }
+    if let Some(ref meta) = participant.meta {
+        map.insert("meta", meta.clone());
+    }
+
+    serde_json::to_string(&map).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// Format a complete participant directive line.
+pub fn format_participant_directive(participant: &Participant) -> String {
+    format!("%% @participant: {} {}", participant.id, canonical_participant_directive(participant))
+}
+
 /// Format a complete directive line.
 pub fn format_node_directive(node: &Node) -> String {
     format!("%% @node: {} {}", node.mermaid_id, canonical_node_directive(node))
 }
 
+/// Format a node directive with the given layout.
+///
+/// An expanded directive opens with `%% @node: ID {` and continues on `%%`
+/// lines until the closing brace.
+pub fn format_node_directive_with(node: &Node, layout: DirectiveLayout) -> String {
+    let line = format_node_directive(node);
+    let expand = match layout {
+        DirectiveLayout::Compact => false,
+        DirectiveLayout::Expanded => true,
+        DirectiveLayout::Auto { max_width } => {
+            node.diagram.is_some()
+                || node.markdown.is_some()
+                || node.code.is_some()
+                || line.chars().count() > max_width
+        }
+    };
+    if !expand {
+        return line;
+    }
+
+    let body = serde_json::to_string_pretty(&node_directive_map(node)).unwrap_or_else(|_| "{}".to_string());
+    let mut lines = body.lines();
+    let mut out = format!("%% @node: {} {}", node.mermaid_id, lines.next().unwrap_or("{"));
+    for line in lines {
+        out.push_str("\n%% ");
+        out.push_str(line);
+    }
+    out
+}
+
 /// Format a complete edge directive line.
 pub fn format_edge_directive(edge: &Edge, mermaid_id: &str) -> String {
     format!("%% @edge: {} {}", mermaid_id, canonical_edge_directive(edge))
 }
 
+/// Hands out the token after `@edge:` for new edge directives.
+///
+/// An edge with a Mermaid edge ID uses it; others get the first `eN` that no
+/// edge ID or existing directive uses, so parallel edges stay distinguishable.
+pub(crate) struct EdgeTokens {
+    taken: HashSet<String>,
+    next: usize,
+}
+
+impl EdgeTokens {
+    pub(crate) fn new<'a>(taken: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            taken: taken.into_iter().map(str::to_string).collect(),
+            next: 1,
+        }
+    }
+
+    pub(crate) fn token(&mut self, edge: &Edge) -> String {
+        if let Some(ref id) = edge.mermaid_id {
+            return id.clone();
+        }
+        loop {
+            let token = format!("e{}", self.next);
+            self.next += 1;
+            if self.taken.insert(token.clone()) {
+                return token;
+            }
+        }
+    }
+}
+
+/// Serialize an AI provenance record to canonical JSON.
+pub fn canonical_ai_directive(record: &AiRecord) -> String {
+    let mut map: IndexMap<&str, Value> = IndexMap::new();
+    
+    map.insert("action", Value::String(record.action.clone()));
+    
+    if let Some(ref provider) = record.provider {
+        map.insert("provider", Value::String(provider.clone()));
+    }
+    if let Some(timestamp) = record.timestamp {
+        map.insert("timestamp", Value::from(timestamp));
+    }
+    if record.status != AiReview::Pending {
+        if let Ok(status) = serde_json::to_value(record.status) {
+            map.insert("status", status);
+        }
+    }
+    if let Some(ref hash) = record.before_hash {
+        map.insert("before_hash", Value::String(hash.0.clone()));
+    }
+    if let Some(ref hash) = record.after_hash {
+        map.insert("after_hash", Value::String(hash.0.clone()));
+    }
+    
+    if let Some(ref meta) = record.meta {
+        map.insert("meta", meta.clone());
+    }
+
+    serde_json::to_string(&map).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// Format a complete AI directive line for a node.
+pub fn format_ai_directive(uid: &UID, record: &AiRecord) -> String {
+    format!("%% @ai: {} {}", uid, canonical_ai_directive(record))
+}
+
+/// Format the schema line that opens the directive section.
+pub fn format_schema_directive() -> String {
+    format!("%% @mermaidman: {{\"version\":{}}}", SCHEMA_VERSION)
+}
+
+/// Format a frontmatter block with the document title and `config`.
+///
+/// Other keys of `base`, an existing block's mapping, are kept in place.
+/// `None` if the block would be empty.
+pub fn format_frontmatter(mut base: Mapping, title: Option<&str>, config: Mapping) -> Option<String> {
+    match title {
+        Some(title) => base.insert("title".into(), title.into()),
+        None => base.shift_remove("title"),
+    };
+    if config.is_empty() {
+        base.shift_remove("config");
+    } else {
+        base.insert("config".into(), YamlValue::Mapping(config));
+    }
+    if base.is_empty() {
+        return None;
+    }
+
+    let yaml = serde_yaml::to_string(&base).ok()?;
+    Some(format!("---\n{}---", yaml))
+}
+
+/// Format a `%%{init}%%` line setting the Mermaid config keys in `config`.
+pub fn format_init_directive(config: &Mapping) -> String {
+    let json = serde_json::to_string(config).unwrap_or_else(|_| "{}".to_string());
+    format!("%%{{init: {}}}%%", json)
+}
+
+/// Format the frontmatter for a document config, as new documents get it.
+pub fn format_document_config(config: &DocumentConfig) -> Option<String> {
+    format_frontmatter(Mapping::new(), config.title.as_deref(), config_mapping(config))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,4 +327,33 @@ mod tests {
         assert!(line.starts_with("%% @node: A "));
         assert!(line.contains("\"uid\":\"n_001\""));
     }
+
+    #[test]
+    fn test_format_node_directive_layouts() {
+        let mut node = Node::new("A");
+        node.uid = UID::from_str("n_1");
+        node.markdown = Some("# Notes\n\n\"quoted\" {braces}".to_string());
+
+        let compact = format_node_directive_with(&node, DirectiveLayout::Compact);
+        assert_eq!(compact, format_node_directive(&node));
+
+        let expanded = format_node_directive_with(&node, DirectiveLayout::Auto { max_width: 120 });
+        assert_eq!(expanded.lines().next(), Some("%% @node: A {"));
+        assert!(expanded.lines().all(|l| l.starts_with("%% ")));
+        assert_eq!(expanded.lines().last(), Some("%% }"));
+
+        // Plain nodes stay on one line unless they are too long
+        let plain = Node::new("B");
+        let line = format_node_directive_with(&plain, DirectiveLayout::Auto { max_width: 120 });
+        assert_eq!(line.lines().count(), 1);
+        let long = format_node_directive_with(&plain, DirectiveLayout::Auto { max_width: 10 });
+        assert!(long.lines().count() > 1);
+
+        // Expanded directives parse back to the same node
+        let doc = format!("graph TD\nA\n{}\n", expanded);
+        let result = crate::parse::parse_document(&doc).unwrap();
+        assert_eq!(result.nodes[0].uid, node.uid);
+        assert_eq!(result.nodes[0].markdown, node.markdown);
+        assert!(result.raw_lines.is_empty());
+    }
 }
@@ -0,0 +1,286 @@
+//! Class diagram text generation.
+
+use crate::store::GraphStore;
+use crate::types::{Class, ClassDiagram, ClassMember, ClassRelation, Edge, Group, Node, RelationEnd, UID};
+use std::collections::HashSet;
+
+/// Indentation of class members.
+const INDENT: &str = "    ";
+
+/// Append a class diagram's statements: a declaration for each class node,
+/// with the members the model has for it, namespaces, then the
+/// relationships.
+///
+/// The store's nodes and edges decide what is written, so classes and
+/// relationships deleted on the canvas are left out and ones added there
+/// are written as plain classes and associations. Labels come from the nodes
+/// and edges; namespaces are the store's groups.
+pub(crate) fn push_class_lines(lines: &mut Vec<String>, store: &GraphStore) {
+    let empty = ClassDiagram::default();
+    let diagram = store.class_diagram.as_ref().unwrap_or(&empty);
+    if let Some(direction) = store.header.direction {
+        lines.push(format!("direction {}", direction));
+    }
+
+    let grouped: HashSet<&UID> = store.groups.values().flat_map(|g| &g.members).collect();
+    for node in store.active_nodes().filter(|n| !grouped.contains(&n.uid)) {
+        push_class(lines, store, node, diagram.class(&node.mermaid_id), "");
+    }
+    for group in store.root_groups() {
+        push_namespace(lines, store, diagram, group, 0);
+    }
+
+    let (paired, added) = pair_relations(store, diagram.relations.iter().map(|r| (r.from.as_str(), r.to.as_str())));
+    for (relation, edge) in diagram.relations.iter().zip(paired) {
+        if let Some(edge) = edge {
+            lines.push(format_relation(relation, edge.label.as_deref()));
+        }
+    }
+    for (from, to, edge) in added {
+        let relation = ClassRelation {
+            from: from.to_string(),
+            to: to.to_string(),
+            to_end: RelationEnd::Arrow,
+            ..Default::default()
+        };
+        lines.push(format_relation(&relation, edge.label.as_deref()));
+    }
+
+    // Lines from class bodies and namespaces went with them
+    for raw in &store.raw_lines {
+        let in_group = raw.group.as_ref().is_some_and(|g| store.groups.contains_key(g));
+        if raw.owner.is_none() && !in_group {
+            lines.push(raw.text.clone());
+        }
+    }
+}
+
+/// Append a namespace block with its classes, nested namespaces and raw lines.
+fn push_namespace(lines: &mut Vec<String>, store: &GraphStore, diagram: &ClassDiagram, group: &Group, depth: usize) {
+    let indent = INDENT.repeat(depth);
+    let inner = INDENT.repeat(depth + 1);
+    lines.push(format!("{}namespace {} {{", indent, group.id));
+    for node in store.active_nodes().filter(|n| group.members.contains(&n.uid)) {
+        push_class(lines, store, node, diagram.class(&node.mermaid_id), &inner);
+    }
+    for child in group.children.iter().filter_map(|id| store.get_group(id)) {
+        push_namespace(lines, store, diagram, child, depth + 1);
+    }
+    for raw in store.raw_lines.iter().filter(|r| r.owner.is_none() && r.group.as_ref() == Some(&group.id)) {
+        lines.push(format!("{}{}", inner, raw.text));
+    }
+    lines.push(format!("{}}}", indent));
+}
+
+/// A store edge with the IDs of the nodes it joins.
+pub(crate) type EdgeEnds<'a> = (&'a str, &'a str, &'a Edge);
+
+/// Pair each relationship, given by its ends, with the next unused store edge
+/// between the same nodes. Relationships without one get `None`; the edges
+/// left over are returned with their ends.
+pub(crate) fn pair_relations<'a, 'r>(
+    store: &'a GraphStore,
+    relations: impl Iterator<Item = (&'r str, &'r str)>,
+) -> (Vec<Option<&'a Edge>>, Vec<EdgeEnds<'a>>) {
+    let active = |uid| store.get_node(uid).filter(|n| !n.deleted).map(|n| n.mermaid_id.as_str());
+    let edges: Vec<EdgeEnds> = store
+        .active_edges()
+        .filter_map(|e| Some((active(&e.source)?, active(&e.target)?, e)))
+        .collect();
+
+    let mut used = vec![false; edges.len()];
+    let paired = relations
+        .map(|(from, to)| {
+            let index = (0..edges.len()).find(|&i| !used[i] && edges[i].0 == from && edges[i].1 == to)?;
+            used[index] = true;
+            Some(edges[index].2)
+        })
+        .collect();
+    let added = edges.into_iter().zip(used).filter(|(_, used)| !used).map(|(edge, _)| edge).collect();
+    (paired, added)
+}
+
+/// Append a class declaration at `indent`, with a body if it has
+/// annotations, members or raw lines.
+fn push_class(lines: &mut Vec<String>, store: &GraphStore, node: &Node, class: Option<&Class>, indent: &str) {
+    let mut head = format!("{}class {}", indent, node.mermaid_id);
+    if let Some(generic) = class.and_then(|c| c.generic.as_deref()) {
+        head.push_str(&format!("~{}~", generic));
+    }
+    if let Some(label) = node.label.as_deref().filter(|l| *l != node.mermaid_id && !l.contains('"')) {
+        head.push_str(&format!("[\"{}\"]", label));
+    }
+
+    let empty = Class::default();
+    let class = class.unwrap_or(&empty);
+    let raw: Vec<_> = store.raw_lines.iter().filter(|r| r.owner.as_ref() == Some(&node.mermaid_id)).collect();
+    if class.annotations.is_empty() && class.members.is_empty() && raw.is_empty() {
+        lines.push(head);
+        return;
+    }
+    lines.push(format!("{} {{", head));
+    for annotation in &class.annotations {
+        lines.push(format!("{}{}<<{}>>", indent, INDENT, annotation));
+    }
+    for member in &class.members {
+        lines.push(format!("{}{}{}", indent, INDENT, format_member(member)));
+    }
+    for raw in raw {
+        lines.push(format!("{}{}{}", indent, INDENT, raw.text));
+    }
+    lines.push(format!("{}}}", indent));
+}
+
+/// Format a member: `+String name`, `-count$`, `+area(r: float)* float`.
+pub fn format_member(member: &ClassMember) -> String {
+    let visibility = member.visibility.map(|v| v.as_char().to_string()).unwrap_or_default();
+    let classifier = member.classifier.map(|c| c.as_char().to_string()).unwrap_or_default();
+
+    match member.params {
+        Some(ref params) => {
+            let returns = member.type_name.as_deref().map(|t| format!(" {}", t)).unwrap_or_default();
+            format!("{}{}({}){}{}", visibility, member.name, params, classifier, returns)
+        }
+        None => {
+            let type_name = member.type_name.as_deref().map(|t| format!("{} ", t)).unwrap_or_default();
+            format!("{}{}{}{}", visibility, type_name, member.name, classifier)
+        }
+    }
+}
+
+/// Format a relationship statement with `label`.
+fn format_relation(relation: &ClassRelation, label: Option<&str>) -> String {
+    let mut line = relation.from.clone();
+    if let Some(ref cardinality) = relation.from_cardinality {
+        line.push_str(&format!(" \"{}\"", cardinality));
+    }
+    let link = if relation.dashed { ".." } else { "--" };
+    line.push_str(&format!(" {}{}{} ", relation.from_end.left(), link, relation.to_end.right()));
+    if let Some(ref cardinality) = relation.to_cardinality {
+        line.push_str(&format!("\"{}\" ", cardinality));
+    }
+    line.push_str(&relation.to);
+    if let Some(label) = label {
+        line.push_str(&format!(" : {}", label));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_document;
+    use crate::store::GraphStore;
+    use crate::types::{Edge, Node};
+    use crate::write::{generate_mermaidman, update_document};
+
+    const DOC: &str = "classDiagram
+    class Shape~T~ {
+        <<interface>>
+        +String name
+        -int sides$
+        +area(scale: float)* float
+    }
+    class Circle[\"Round thing\"]
+    Circle : +float radius
+    Shape <|-- Circle
+    Drawing \"1\" *-- \"many\" Shape : contains
+";
+
+    #[test]
+    fn test_generate_class_diagram() {
+        let mut store = GraphStore::from_parse_result(parse_document(DOC).unwrap());
+        let circle = store.get_node_by_mermaid_id("Circle").unwrap().uid.clone();
+        let canvas = Node::new("Canvas");
+        store.upsert_edge(Edge::new(circle.clone(), canvas.uid.clone()));
+        store.upsert_node(canvas);
+
+        let doc = generate_mermaidman(&store);
+        assert!(doc.starts_with(
+            "classDiagram
+
+class Shape~T~ {
+    <<interface>>
+    +String name
+    -int sides$
+    +area(scale: float)* float
+}
+class Circle[\"Round thing\"] {
+    +float radius
+}
+class Drawing
+class Canvas
+Shape <|-- Circle
+Drawing \"1\" *-- \"many\" Shape : contains
+Circle --> Canvas
+"
+        ));
+
+        let reparsed = parse_document(&doc).unwrap();
+        assert!(reparsed.diagnostics.is_empty());
+        let before = store.class_diagram.as_ref().unwrap();
+        let after = reparsed.class_diagram.as_ref().unwrap();
+        assert_eq!(after.classes[..3], before.classes[..]);
+        assert_eq!(after.relations[..2], before.relations[..]);
+        assert_eq!(reparsed.edges.len(), 3);
+
+        // Deleting a class drops it and its relationships
+        store.delete_node(&circle);
+        let doc = generate_mermaidman(&store);
+        assert!(!doc.contains("Circle"));
+    }
+
+    #[test]
+    fn test_move_class_rewrites_only_its_directive() {
+        let store = GraphStore::from_parse_result(parse_document(DOC).unwrap());
+        let saved = update_document(DOC, &store);
+        assert!(saved.starts_with(DOC));
+
+        let mut store = GraphStore::from_parse_result(parse_document(&saved).unwrap());
+        let uid = store.get_node_by_mermaid_id("Drawing").unwrap().uid.clone();
+        store.move_node(&uid, 120.0, 0.0);
+        let moved = update_document(&saved, &store);
+        assert!(moved.starts_with(DOC));
+        assert_eq!(moved.lines().count(), saved.lines().count());
+        assert!(moved.contains("\"x\":120.0"));
+    }
+
+    #[test]
+    fn test_namespaces_and_body_comments_survive_regeneration() {
+        let doc = "classDiagram
+namespace Shapes {
+    class Square {
+        %% sides are equal
+        +int side
+    }
+    class Circle
+}
+Square <|-- Circle
+";
+        let mut store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        let circle = store.get_node_by_mermaid_id("Circle").unwrap().uid.clone();
+        let canvas = Node::new("Canvas");
+        store.upsert_edge(Edge::new(circle, canvas.uid.clone()));
+        store.upsert_node(canvas);
+
+        let saved = update_document(doc, &store);
+        assert!(saved.starts_with(
+            "classDiagram
+
+class Canvas
+namespace Shapes {
+    class Square {
+        +int side
+        %% sides are equal
+    }
+    class Circle
+}
+Square <|-- Circle
+Circle --> Canvas
+"
+        ), "{}", saved);
+
+        let reparsed = GraphStore::from_parse_result(parse_document(&saved).unwrap());
+        assert_eq!(reparsed.get_group("Shapes").unwrap().members.len(), 2);
+        assert_eq!(update_document(&saved, &reparsed), saved);
+    }
+}
@@ -0,0 +1,1623 @@
+//! Lossless writing: apply graph store changes to the original text as minimal edits.
+//!
+//! The parse's source map is the concrete syntax tree: every statement,
+//! subgraph line and directive keeps its span. Only the pieces whose rendering
+//! changed are rewritten, so comments, blank lines, statement order,
+//! indentation and the user's own declarations survive a save.
+
+use crate::migrate::{SCHEMA_VERSION, UNVERSIONED};
+use crate::parse::{
+    config_mapping, frontmatter_mapping, parse_ai_directive, parse_ai_directive_with,
+    parse_document, parse_edge_directive, parse_edge_directive_with, parse_init_directive,
+    parse_node_directive, parse_node_directive_with, DirectiveBlock, ParseResult, Span,
+};
+use crate::store::GraphStore;
+use crate::types::{Edge, Node, StateKind, EID, UID};
+use crate::write::canonical::{
+    format_ai_directive, format_edge_directive, format_frontmatter, format_init_directive,
+    format_node_directive, format_node_directive_with, format_participant_directive,
+    format_schema_directive, DirectiveLayout, EdgeTokens,
+};
+use crate::write::mermaid::{
+    format_acc_descr, format_acc_title, format_edge_line, format_link, format_node_decl, format_subgraph_header,
+    generate_mermaidman_with, generate_topology, push_group_lines, push_style_lines,
+};
+use crate::write::state::{format_transition, transition_scope};
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::collections::{HashMap, HashSet};
+
+/// Replace `start..end` of the original with `text`; an insertion when they are equal.
+#[derive(Debug)]
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+/// Update `original` so it describes `store`, touching only what changed.
+///
+/// Nodes and edges are matched to the original by UID, then by Mermaid ID and
+/// endpoints. A document written with an older directive schema is upgraded
+/// when anything else changes: directives that migration altered are rewritten
+/// and the `%% @mermaidman` line is set to the current version. Falls back to [`generate_mermaidman`](crate::write::generate_mermaidman) when subgraph membership
+/// changed in a way that cannot be expressed as local edits.
+///
+/// Sequence, state, class and ER diagrams are only edited in place while their
+/// statements are unchanged, or for state diagrams while only transitions
+/// were added, removed or relabelled; otherwise they are regenerated.
+pub fn update_document(original: &str, store: &GraphStore) -> String {
+    update_document_with(original, store, DirectiveLayout::Compact)
+}
+
+/// Like [`update_document`], laying out new and rewritten node directives with
+/// `layout`. Directives already spread over several lines stay expanded.
+pub fn update_document_with(original: &str, store: &GraphStore, layout: DirectiveLayout) -> String {
+    let Ok(parsed) = parse_document(original) else {
+        return generate_mermaidman_with(store, layout);
+    };
+
+    if parsed.sequence.is_some() || store.sequence.is_some() {
+        return update_sequence(original, &parsed, store).unwrap_or_else(|| generate_mermaidman_with(store, layout));
+    }
+
+    // State, class and ER diagram statements are regenerated when they
+    // change; otherwise only the config and directives are edited. Changed
+    // state transitions are edited in place when that says the same thing.
+    if !parsed.header.keyword.is_flowchart() || !store.header.keyword.is_flowchart() {
+        let topology = generate_topology(store);
+        if generate_topology(&GraphStore::from_parse_result(parsed.clone())) == topology {
+            let edits = Updater::new(original, &parsed, store, layout).directive_edits();
+            return apply_edits(original, edits).unwrap_or_else(|| generate_mermaidman_with(store, layout));
+        }
+        if parsed.header.keyword.is_state() && store.header.keyword == parsed.header.keyword {
+            let edits = Updater::new(original, &parsed, store, layout).transition_edits();
+            let updated = apply_edits(original, edits).filter(|text| {
+                parse_document(text).is_ok_and(|p| generate_topology(&GraphStore::from_parse_result(p)) == topology)
+            });
+            if let Some(updated) = updated {
+                return updated;
+            }
+        }
+        return generate_mermaidman_with(store, layout);
+    }
+
+    Updater::new(original, &parsed, store, layout)
+        .edits()
+        .and_then(|edits| apply_edits(original, edits))
+        .unwrap_or_else(|| generate_mermaidman_with(store, layout))
+}
+
+/// Update a sequence diagram in place when only participant layout changed:
+/// directives are rewritten or added. `None` for any other change, which
+/// regenerates the diagram.
+fn update_sequence(original: &str, parsed: &ParseResult, store: &GraphStore) -> Option<String> {
+    let (old, new) = (parsed.sequence.as_ref()?, store.sequence.as_ref()?);
+    let same_participants = old.participants.len() == new.participants.len()
+        && old.participants.iter().zip(&new.participants).all(|(a, b)| {
+            (&a.id, &a.alias, a.kind, a.declared) == (&b.id, &b.alias, b.kind, b.declared)
+        });
+    let unchanged = old.statements == new.statements && old.boxes == new.boxes && parsed.config == store.config;
+    if !same_participants || !unchanged {
+        return None;
+    }
+
+    let map = &parsed.source_map;
+    let mut edits = Vec::new();
+    let mut added = Vec::new();
+    for ((old, new), source) in old.participants.iter().zip(&new.participants).zip(&map.participants) {
+        let line = format_participant_directive(new);
+        match source.directive {
+            Some(span) if format_participant_directive(old) != line => edits.push(Edit {
+                start: span.start,
+                end: span.end,
+                text: line,
+            }),
+            Some(_) => {}
+            None => added.push(line),
+        }
+    }
+
+    if !added.is_empty() {
+        if map.schema.is_none() {
+            added.insert(0, format_schema_directive());
+        }
+        let last = map
+            .participants
+            .iter()
+            .filter_map(|p| p.directive)
+            .chain(map.schema)
+            .map(|span| span.end)
+            .max();
+        let body = added.join("\n");
+        let (pos, text) = match last {
+            Some(end) => (line_end(original, end), format!("\n{}", body)),
+            None if original.ends_with("\n\n") => (original.len(), format!("{}\n", body)),
+            None if original.ends_with('\n') => (original.len(), format!("\n{}\n", body)),
+            None => (original.len(), format!("\n\n{}", body)),
+        };
+        edits.push(Edit { start: pos, end: pos, text });
+    }
+
+    apply_edits(original, edits)
+}
+
+/// Apply non-overlapping edits; `None` if any overlap.
+fn apply_edits(text: &str, mut edits: Vec<Edit>) -> Option<String> {
+    // Stable, so insertions at the same offset keep their order
+    edits.sort_by_key(|e| (e.start, e.end));
+
+    let mut out = String::with_capacity(text.len());
+    let mut pos = 0;
+    for edit in edits {
+        if edit.start < pos {
+            return None;
+        }
+        out.push_str(&text[pos..edit.start]);
+        out.push_str(&edit.text);
+        pos = edit.end;
+    }
+    out.push_str(&text[pos..]);
+
+    Some(out)
+}
+
+/// Offset of the start of the line containing `pos`.
+fn line_start(text: &str, pos: usize) -> usize {
+    text[..pos].rfind('\n').map_or(0, |i| i + 1)
+}
+
+/// Offset of the line break ending the line containing `pos` (or the end of text).
+fn line_end(text: &str, pos: usize) -> usize {
+    text[pos..].find('\n').map_or(text.len(), |i| pos + i)
+}
+
+/// A directive's source joined onto one line if it spans several.
+fn joined_directive(source: &str) -> String {
+    let mut lines = source.lines();
+    let Some(mut block) = lines.next().and_then(DirectiveBlock::start) else {
+        return source.to_string();
+    };
+    for line in lines {
+        block.push(line);
+    }
+    block.text().to_string()
+}
+
+/// Whether `inner` lies within `outer`.
+fn contains(outer: &Span, inner: &Span) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+/// Whether a node occurrence declares a shape (`A[Label]`), not just references `A`.
+fn declares_shape(source: &str, id: &str) -> bool {
+    source
+        .strip_prefix(id)
+        .is_some_and(|rest| !rest.is_empty() && !rest.starts_with(":::"))
+}
+
+/// The `:::class` suffix of a node occurrence, if any.
+fn class_suffix(source: &str) -> &str {
+    source
+        .rfind(":::")
+        .map(|i| &source[i..])
+        .filter(|suffix| {
+            let class = &suffix[3..];
+            !class.is_empty() && class.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        })
+        .unwrap_or("")
+}
+
+/// The leaf values of a config mapping with their key paths; nested
+/// mappings are walked, an empty one is a leaf.
+fn config_leaves(mapping: &Mapping) -> Vec<(Vec<YamlValue>, YamlValue)> {
+    let mut leaves = Vec::new();
+    for (key, value) in mapping {
+        match value {
+            YamlValue::Mapping(inner) if !inner.is_empty() => {
+                for (mut path, leaf) in config_leaves(inner) {
+                    path.insert(0, key.clone());
+                    leaves.push((path, leaf));
+                }
+            }
+            _ => leaves.push((vec![key.clone()], value.clone())),
+        }
+    }
+    leaves
+}
+
+/// The value at `path` in a config mapping.
+fn config_get<'m>(mapping: &'m Mapping, path: &[YamlValue]) -> Option<&'m YamlValue> {
+    let (last, parents) = path.split_last()?;
+    let mut mapping = mapping;
+    for key in parents {
+        mapping = mapping.get(key)?.as_mapping()?;
+    }
+    mapping.get(last)
+}
+
+/// Set `path` in a config mapping, creating (or replacing) parents as needed.
+fn config_set(mapping: &mut Mapping, path: &[YamlValue], value: YamlValue) {
+    let Some((last, parents)) = path.split_last() else {
+        return;
+    };
+    let mut mapping = mapping;
+    for key in parents {
+        let entry = mapping.entry(key.clone()).or_insert_with(|| YamlValue::Mapping(Mapping::new()));
+        if !entry.is_mapping() {
+            *entry = YamlValue::Mapping(Mapping::new());
+        }
+        mapping = entry.as_mapping_mut().expect("just made a mapping");
+    }
+    mapping.insert(last.clone(), value);
+}
+
+/// Remove `path` from a config mapping, dropping parents it leaves empty.
+/// Whether it was set.
+fn config_remove(mapping: &mut Mapping, path: &[YamlValue]) -> bool {
+    match path {
+        [] => false,
+        [key] => mapping.shift_remove(key).is_some(),
+        [key, rest @ ..] => {
+            let Some(YamlValue::Mapping(inner)) = mapping.get_mut(key) else {
+                return false;
+            };
+            let removed = config_remove(inner, rest);
+            if removed && inner.is_empty() {
+                mapping.shift_remove(key);
+            }
+            removed
+        }
+    }
+}
+
+struct Updater<'a> {
+    text: &'a str,
+    parsed: &'a ParseResult,
+    store: &'a GraphStore,
+    /// The active store node for each parsed node; `None` once it is gone.
+    nodes: Vec<Option<&'a Node>>,
+    /// The active store edge for each parsed edge, if it and both endpoints survive.
+    edges: Vec<Option<&'a Edge>>,
+    /// Store UID for each matched parsed node UID.
+    uids: HashMap<&'a UID, &'a UID>,
+    /// Where lines added to the topology go, and their indentation.
+    anchor: (Option<usize>, String),
+    edits: Vec<Edit>,
+    /// Statements to remove, resolved into edits at the end.
+    removals: Vec<Span>,
+    layout: DirectiveLayout,
+}
+
+impl<'a> Updater<'a> {
+    fn new(text: &'a str, parsed: &'a ParseResult, store: &'a GraphStore, layout: DirectiveLayout) -> Self {
+        let mut updater = Self {
+            text,
+            parsed,
+            store,
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            uids: HashMap::new(),
+            anchor: (None, String::new()),
+            edits: Vec::new(),
+            removals: Vec::new(),
+            layout,
+        };
+        updater.match_nodes();
+        updater.match_edges();
+        updater.anchor = updater.find_anchor();
+        updater
+    }
+
+    /// The directive schema version the original was written with.
+    fn version(&self) -> u32 {
+        self.parsed.schema_version.unwrap_or(UNVERSIONED)
+    }
+
+    /// Whether the directive at `span` is from an older schema and migrating
+    /// it changed its body, so it must be rewritten in the current shape.
+    fn is_outdated(&self, span: &Span) -> bool {
+        let version = self.version();
+        if version >= SCHEMA_VERSION {
+            return false;
+        }
+        let text = joined_directive(self.source(span));
+        if let Some(old) = parse_node_directive_with(&text, version) {
+            return parse_node_directive(&text).is_none_or(|new| new.meta != old.meta);
+        }
+        if let Some(old) = parse_edge_directive_with(&text, version) {
+            return parse_edge_directive(&text).is_none_or(|new| new.meta != old.meta || new.eid != old.eid);
+        }
+        match parse_ai_directive_with(&text, version) {
+            Some(old) => parse_ai_directive(&text).is_none_or(|new| new.meta != old.meta),
+            None => false,
+        }
+    }
+
+    fn is_active(&self, uid: &UID) -> bool {
+        self.store.get_node(uid).is_some_and(|n| !n.deleted)
+    }
+
+    /// Match parsed nodes by UID, then by Mermaid ID.
+    fn match_nodes(&mut self) {
+        let store = self.store;
+        let mut claimed: HashSet<&UID> = HashSet::new();
+        let mut matched: Vec<Option<&Node>> = self
+            .parsed
+            .nodes
+            .iter()
+            .map(|p| {
+                let node = store.get_node(&p.uid)?;
+                claimed.insert(&node.uid);
+                Some(node)
+            })
+            .collect();
+
+        for (slot, p) in matched.iter_mut().zip(&self.parsed.nodes) {
+            if slot.is_some() {
+                continue;
+            }
+            let node = store
+                .alias
+                .get_uid(&p.mermaid_id)
+                .filter(|uid| !claimed.contains(uid))
+                .and_then(|uid| store.get_node(uid));
+            if let Some(node) = node {
+                claimed.insert(&node.uid);
+                *slot = Some(node);
+            }
+        }
+
+        for (p, node) in self.parsed.nodes.iter().zip(&matched) {
+            if let Some(node) = node {
+                self.uids.insert(&p.uid, &node.uid);
+            }
+        }
+        self.nodes = matched.into_iter().map(|n| n.filter(|n| !n.deleted)).collect();
+    }
+
+    /// Match parsed edges by EID or Mermaid edge ID, then in order among
+    /// edges with the same endpoints.
+    fn match_edges(&mut self) {
+        let store = self.store;
+        let endpoints = |e: &Edge| (self.uids.get(&e.source).copied(), self.uids.get(&e.target).copied());
+        let mut claimed: HashSet<&EID> = HashSet::new();
+
+        let mut matched: Vec<Option<&Edge>> = self
+            .parsed
+            .edges
+            .iter()
+            .map(|p| {
+                let edge = store.get_edge(&p.eid).or_else(|| {
+                    let id = p.mermaid_id.as_ref()?;
+                    store.active_edges().find(|e| e.mermaid_id.as_ref() == Some(id))
+                })?;
+                if endpoints(p) != (Some(&edge.source), Some(&edge.target)) || !claimed.insert(&edge.eid) {
+                    return None;
+                }
+                Some(edge)
+            })
+            .collect();
+
+        for (slot, p) in matched.iter_mut().zip(&self.parsed.edges) {
+            if slot.is_some() {
+                continue;
+            }
+            let edge = store.active_edges().find(|e| {
+                !claimed.contains(&e.eid) && endpoints(p) == (Some(&e.source), Some(&e.target))
+            });
+            if let Some(edge) = edge {
+                claimed.insert(&edge.eid);
+                *slot = Some(edge);
+            }
+        }
+
+        self.edges = matched
+            .into_iter()
+            .map(|e| {
+                e.filter(|e| !e.deleted && self.is_active(&e.source) && self.is_active(&e.target))
+            })
+            .collect();
+    }
+
+    fn source(&self, span: &Span) -> &'a str {
+        &self.text[span.start..span.end]
+    }
+
+    /// Edge statements: runs of parsed edges sharing a span.
+    fn statements(&self) -> Vec<(Span, Vec<usize>)> {
+        let mut statements: Vec<(Span, Vec<usize>)> = Vec::new();
+        for (i, source) in self.parsed.source_map.edges.iter().enumerate() {
+            match statements.last_mut() {
+                Some((span, indices)) if *span == source.span => indices.push(i),
+                _ => statements.push((source.span, vec![i])),
+            }
+        }
+        statements
+    }
+
+    fn in_edge_statement(&self, span: &Span) -> bool {
+        self.parsed.source_map.edges.iter().any(|e| contains(&e.span, span))
+    }
+
+    /// Where new topology lines go: after the last root-level statement,
+    /// indented like the last root-level node or edge statement.
+    fn find_anchor(&self) -> (Option<usize>, String) {
+        let map = &self.parsed.source_map;
+        let blocks: Vec<(usize, usize)> = map
+            .groups
+            .iter()
+            .map(|g| (g.span.start, g.end.map_or(self.text.len(), |e| e.end)))
+            .collect();
+        let at_root = |span: &Span| !blocks.iter().any(|&(start, end)| start <= span.start && span.end <= end);
+
+        let statements: Vec<Span> = map
+            .edges
+            .iter()
+            .map(|e| e.span)
+            .chain(map.nodes.iter().flat_map(|n| n.spans.iter().copied()))
+            .filter(|span| at_root(span))
+            .collect();
+        let root_ends = map
+            .groups
+            .iter()
+            .zip(&self.parsed.groups)
+            .filter(|(_, g)| g.parent.is_none())
+            .filter_map(|(source, _)| source.end);
+
+        let last = statements
+            .iter()
+            .copied()
+            .chain(map.header)
+            .chain(root_ends)
+            .max_by_key(|span| span.end);
+        let indent = statements
+            .iter()
+            .max_by_key(|span| span.end)
+            .map(|span| self.indent_of(span))
+            .unwrap_or_default();
+
+        (last.map(|span| line_end(self.text, span.end)), indent)
+    }
+
+    /// Leading whitespace of the line `span` starts on.
+    fn indent_of(&self, span: &Span) -> String {
+        let line = &self.text[line_start(self.text, span.start)..];
+        line[..line.len() - line.trim_start_matches([' ', '\t']).len()].to_string()
+    }
+
+    fn replace(&mut self, span: Span, text: String) {
+        self.edits.push(Edit {
+            start: span.start,
+            end: span.end,
+            text,
+        });
+    }
+
+    fn insert(&mut self, pos: usize, text: String) {
+        self.edits.push(Edit {
+            start: pos,
+            end: pos,
+            text,
+        });
+    }
+
+    /// Remove a statement; see [`Updater::flush_removals`].
+    fn remove(&mut self, span: Span) {
+        self.removals.push(span);
+    }
+
+    /// Turn removed statements into edits: a line left with nothing but `;`
+    /// separators goes entirely, otherwise each statement goes with its `;`.
+    fn flush_removals(&mut self) {
+        let text = self.text;
+        let mut spans = std::mem::take(&mut self.removals);
+        spans.sort_by_key(|span| span.start);
+        spans.dedup();
+
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        let mut i = 0;
+        while i < spans.len() {
+            // Statements sharing a line
+            let start = line_start(text, spans[i].start);
+            let mut end = line_end(text, spans[i].end);
+            let mut j = i + 1;
+            while j < spans.len() && spans[j].start < end {
+                end = end.max(line_end(text, spans[j].end));
+                j += 1;
+            }
+            let line = &spans[i..j];
+
+            let mut pos = start;
+            let mut leftover = String::new();
+            for span in line {
+                leftover.push_str(&text[pos..span.start]);
+                pos = span.end;
+            }
+            leftover.push_str(&text[pos..end]);
+
+            if leftover.chars().all(|c| c.is_whitespace() || c == ';') {
+                ranges.push(if start > 0 {
+                    (start - 1, end)
+                } else {
+                    (start, (end + 1).min(text.len()))
+                });
+            } else {
+                for span in line {
+                    let after = &text[span.end..end];
+                    let range = match after.trim_start().strip_prefix(';') {
+                        Some(rest) => {
+                            let rest = rest.trim_start_matches([' ', '\t']);
+                            (span.start, end - rest.len())
+                        }
+                        None => {
+                            let before = text[start..span.start].trim_end();
+                            match before.strip_suffix(';') {
+                                Some(head) => (start + head.len(), span.end),
+                                None => (span.start, span.end),
+                            }
+                        }
+                    };
+                    match ranges.last_mut() {
+                        Some(last) if range.0 <= last.1 => last.1 = last.1.max(range.1),
+                        _ => ranges.push(range),
+                    }
+                }
+            }
+            i = j;
+        }
+
+        for (start, end) in ranges {
+            self.edits.push(Edit {
+                start,
+                end,
+                text: String::new(),
+            });
+        }
+    }
+
+    /// Replace a statement with `lines`, keeping its indentation; removes it if empty.
+    fn replace_statement(&mut self, span: Span, lines: Vec<String>) {
+        if lines.is_empty() {
+            self.remove(span);
+            return;
+        }
+
+        let lead = &self.text[line_start(self.text, span.start)..span.start];
+        let separator = if lead.trim().is_empty() {
+            format!("\n{}", lead)
+        } else {
+            "; ".to_string()
+        };
+        self.replace(span, lines.join(&separator));
+    }
+
+    /// Insert lines after the last root-level topology statement.
+    fn insert_at_anchor(&mut self, lines: Vec<String>) {
+        if lines.is_empty() {
+            return;
+        }
+
+        let (pos, indent) = self.anchor.clone();
+        let body = lines
+            .iter()
+            .map(|line| format!("{}{}", indent, line))
+            .collect::<Vec<_>>()
+            .join("\n");
+        match pos {
+            Some(pos) => self.insert(pos, format!("\n{}", body)),
+            None => self.insert(self.body_start(), format!("{}\n", body)),
+        }
+    }
+
+    fn edits(mut self) -> Option<Vec<Edit>> {
+        if !self.groups_are_local() {
+            return None;
+        }
+
+        self.update_config();
+        self.update_header();
+        self.update_accessibility();
+        let emitted = self.update_statements();
+        self.update_groups();
+        self.update_styles(&emitted);
+        self.update_directives();
+        self.flush_removals();
+
+        Some(self.edits)
+    }
+
+    /// Edits for a document whose statements are unchanged: config and
+    /// directives only.
+    fn directive_edits(mut self) -> Vec<Edit> {
+        self.update_config();
+        self.update_directives();
+        self.flush_removals();
+        self.edits
+    }
+
+    /// Edits for a state diagram whose transitions changed: removed ones are
+    /// deleted, relabelled ones rewritten and new ones added at the end of
+    /// the innermost composite state holding both ends, so comments and `--`
+    /// separators stay where they are. A state only a removed transition
+    /// mentioned is declared in its place.
+    fn transition_edits(mut self) -> Vec<Edit> {
+        let doc = self.parsed;
+        let mut removed: Vec<Span> = Vec::new();
+        for (i, parsed) in doc.edges.iter().enumerate() {
+            let span = doc.source_map.edges[i].span;
+            match self.edges[i] {
+                None => removed.push(span),
+                Some(edge) if edge.label != parsed.label => {
+                    let ends = (self.store.get_node(&edge.source), self.store.get_node(&edge.target));
+                    if let (Some(source), Some(target)) = ends {
+                        self.replace(span, format_transition(edge, source, target));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        // Keyed by the start of the removed statement
+        let mut declarations: HashMap<usize, Vec<String>> = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let spans = &doc.source_map.nodes[i].spans;
+            let Some(node) = node.filter(|n| StateKind::of(&n.shape) == StateKind::State) else {
+                continue;
+            };
+            let gone = spans.iter().all(|s| removed.iter().any(|r| contains(r, s)));
+            if let Some(span) = spans.first().filter(|_| gone) {
+                let statement = removed.iter().copied().find(|r| contains(r, span)).expect("removed statement");
+                declarations.entry(statement.start).or_default().push(node.mermaid_id.clone());
+            }
+        }
+        for span in removed {
+            match declarations.remove(&span.start) {
+                Some(lines) => self.replace_statement(span, lines),
+                None => self.remove(span),
+            }
+        }
+
+        let mut root = Vec::new();
+        let new_edges: Vec<&Edge> = self.new_edges().collect();
+        for edge in new_edges {
+            let ends = (self.store.get_node(&edge.source), self.store.get_node(&edge.target));
+            let (Some(source), Some(target)) = ends else {
+                continue;
+            };
+            let line = format_transition(edge, source, target);
+            match transition_scope(self.store, edge).flatten() {
+                None => root.push(line),
+                Some(scope) => {
+                    let Some(group) = doc.source_map.group(scope) else {
+                        continue;
+                    };
+                    if let Some(end) = group.end {
+                        let inner = format!("{}    ", self.indent_of(&group.span));
+                        self.insert(line_start(self.text, end.start), format!("{}{}\n", inner, line));
+                    }
+                }
+            }
+        }
+        self.insert_at_anchor(root);
+
+        self.update_config();
+        self.update_directives();
+        self.flush_removals();
+        self.edits
+    }
+
+    /// Whether subgraph changes can be made in place: existing groups keep
+    /// their nesting and only gain new nodes, and new groups hold only new nodes.
+    fn groups_are_local(&self) -> bool {
+        let matched: HashSet<&UID> = self.nodes.iter().flatten().map(|n| &n.uid).collect();
+
+        for (old, source) in self.parsed.groups.iter().zip(&self.parsed.source_map.groups) {
+            let Some(group) = self.store.get_group(&old.id) else {
+                return false;
+            };
+            if group.parent != old.parent || group.children != old.children {
+                return false;
+            }
+
+            let before: HashSet<&UID> = old
+                .members
+                .iter()
+                .filter_map(|uid| self.uids.get(uid).copied())
+                .filter(|uid| self.is_active(uid))
+                .collect();
+            let after: HashSet<&UID> = group
+                .members
+                .iter()
+                .filter(|uid| matched.contains(uid) && self.is_active(uid))
+                .collect();
+            if before != after {
+                return false;
+            }
+
+            let gains_nodes = group.members.iter().any(|uid| !matched.contains(uid) && self.is_active(uid));
+            if gains_nodes && source.end.is_none() {
+                return false;
+            }
+        }
+
+        self.new_groups().all(|group| {
+            let nested_in_new = group
+                .parent
+                .as_ref()
+                .is_none_or(|parent| self.parsed.source_map.group(parent).is_none());
+            nested_in_new && !group.members.iter().any(|uid| matched.contains(uid))
+        })
+    }
+
+    fn new_groups(&self) -> impl Iterator<Item = &'a crate::types::Group> + '_ {
+        self.store
+            .groups
+            .values()
+            .filter(|g| self.parsed.source_map.group(&g.id).is_none())
+    }
+
+    /// Where the diagram starts: after the frontmatter, if there is one.
+    fn body_start(&self) -> usize {
+        self.parsed
+            .source_map
+            .frontmatter
+            .map_or(0, |span| (line_end(self.text, span.end) + 1).min(self.text.len()))
+    }
+
+    /// Rewrite the frontmatter and `%%{init}%%` lines whose config changed.
+    ///
+    /// Keys are placed per leaf path (`flowchart.curve`, not `flowchart`): a
+    /// changed key is written where it is set, init lines first as they win,
+    /// otherwise next to its closest set parent. New keys go to the frontmatter
+    /// unless the document only has init lines; the title always goes there.
+    /// Blocks without a changed key are left as written.
+    fn update_config(&mut self) {
+        let config = &self.store.config;
+        if config == &self.parsed.config {
+            return;
+        }
+
+        // Unparsable frontmatter is left alone
+        let map = &self.parsed.source_map;
+        let base = match map.frontmatter {
+            Some(span) => match frontmatter_mapping(self.source(&span)) {
+                Ok(base) => Some((span, base)),
+                Err(_) => return,
+            },
+            None => None,
+        };
+        let mut front = match base.as_ref().and_then(|(_, base)| base.get("config")) {
+            Some(YamlValue::Mapping(front)) => front.clone(),
+            _ => Mapping::new(),
+        };
+        let mut init: Vec<(Span, Mapping)> = map
+            .init
+            .iter()
+            .filter_map(|span| Some((*span, parse_init_directive(self.source(span))?.ok()?)))
+            .collect();
+        let title_changed = config.title != self.parsed.config.title;
+        let mut front_touched = title_changed;
+        let mut init_touched = vec![false; init.len()];
+        if title_changed {
+            for (i, (_, mapping)) in init.iter_mut().enumerate() {
+                init_touched[i] |= mapping.shift_remove("title").is_some();
+            }
+        }
+
+        let old = config_leaves(&config_mapping(&self.parsed.config));
+        let new = config_leaves(&config_mapping(config));
+        for (path, value) in &new {
+            if old.iter().any(|(p, v)| p == path && v == value) {
+                continue;
+            }
+            // Init lines win over the frontmatter, so the last one setting the key owns it
+            let owner = (1..=path.len()).rev().find_map(|depth| {
+                let prefix = &path[..depth];
+                let line = init.iter().rposition(|(_, mapping)| config_get(mapping, prefix).is_some());
+                line.map(Some).or(config_get(&front, prefix).map(|_| None))
+            });
+            let owner = owner.unwrap_or_else(|| (base.is_none() && !init.is_empty()).then_some(0));
+            match owner {
+                Some(i) => {
+                    config_set(&mut init[i].1, path, value.clone());
+                    init_touched[i] = true;
+                }
+                None => {
+                    config_set(&mut front, path, value.clone());
+                    front_touched = true;
+                }
+            }
+        }
+        for (path, _) in &old {
+            if new.iter().any(|(p, _)| p == path) {
+                continue;
+            }
+            front_touched |= config_remove(&mut front, path);
+            for (i, (_, mapping)) in init.iter_mut().enumerate() {
+                init_touched[i] |= config_remove(mapping, path);
+            }
+        }
+
+        if front_touched {
+            let (span, base) = base.map_or((None, Mapping::new()), |(span, base)| (Some(span), base));
+            let title = if title_changed {
+                config.title.clone()
+            } else {
+                base.get("title").and_then(YamlValue::as_str).map(str::to_string)
+            };
+            match (span, format_frontmatter(base, title.as_deref(), front)) {
+                (Some(span), Some(block)) => self.replace(span, block),
+                (Some(span), None) => self.remove(span),
+                (None, Some(block)) => self.insert(0, format!("{}\n", block)),
+                (None, None) => {}
+            }
+        }
+        for ((span, mapping), touched) in init.into_iter().zip(init_touched) {
+            match (touched, mapping.is_empty()) {
+                (false, _) => {}
+                (true, true) => self.remove(span),
+                (true, false) => self.replace(span, format_init_directive(&mapping)),
+            }
+        }
+    }
+
+    fn update_header(&mut self) {
+        let header = self.store.header.to_string();
+        match self.parsed.source_map.header {
+            Some(span) if self.source(&span) != header && self.parsed.header != self.store.header => {
+                self.replace(span, header);
+            }
+            None if self.parsed.header != self.store.header => {
+                self.insert(self.body_start(), format!("{}\n", header))
+            }
+            _ => {}
+        }
+    }
+
+    /// Rewrite, remove or add `accTitle` and `accDescr`; new ones go after the header.
+    fn update_accessibility(&mut self) {
+        let map = &self.parsed.source_map;
+        let (old, new) = (&self.parsed.accessibility, &self.store.accessibility);
+        let fields = [
+            (map.acc_title, old.title != new.title, new.title.as_deref().map(format_acc_title)),
+            (
+                map.acc_descr,
+                old.description != new.description,
+                new.description.as_deref().map(format_acc_descr),
+            ),
+        ];
+
+        let mut added = Vec::new();
+        for (span, changed, text) in fields {
+            match (span, text) {
+                _ if !changed => {}
+                (Some(span), Some(text)) => self.replace(span, text),
+                (Some(span), None) => self.remove(span),
+                (None, Some(text)) => added.push(text),
+                (None, None) => {}
+            }
+        }
+
+        if added.is_empty() {
+            return;
+        }
+        let body = added.join("\n");
+        match map.header {
+            Some(span) => self.insert(line_end(self.text, span.end), format!("\n{}", body)),
+            None => self.insert(self.body_start(), format!("{}\n", body)),
+        }
+    }
+
+    /// Rewrite changed edge statements and node declarations, and add new
+    /// edges and nodes. Returns the edges in their new document order.
+    fn update_statements(&mut self) -> Vec<&'a Edge> {
+        let statements = self.statements();
+        let unchanged = |i: usize| {
+            let parsed = &self.parsed.edges[i];
+            self.edges[i].is_some_and(|edge| {
+                edge.label == parsed.label
+                    && edge.mermaid_id == parsed.mermaid_id
+                    && format_link(edge.style.as_ref()) == format_link(parsed.style.as_ref())
+            })
+        };
+        let affected: Vec<usize> = (0..statements.len())
+            .filter(|&k| !statements[k].1.iter().all(|&i| unchanged(i)))
+            .collect();
+        let affected_at = |span: &Span| affected.iter().copied().find(|&k| contains(&statements[k].0, span));
+
+        // Nodes rewritten statements must declare in full, or at least mention
+        let mut declare: HashMap<usize, Vec<&'a Node>> = HashMap::new();
+        let mut mention: HashMap<usize, Vec<&'a Node>> = HashMap::new();
+        let survivors: HashSet<&UID> = affected
+            .iter()
+            .flat_map(|&k| statements[k].1.iter().filter_map(|&i| self.edges[i]))
+            .chain(self.new_edges())
+            .flat_map(|e| [&e.source, &e.target])
+            .collect();
+
+        let doc = self.parsed;
+        for (i, parsed) in doc.nodes.iter().enumerate() {
+            let spans = &doc.source_map.nodes[i].spans;
+            let Some(node) = self.nodes[i] else {
+                let declarations: Vec<Span> = spans.iter().copied().filter(|s| !self.in_edge_statement(s)).collect();
+                for span in declarations {
+                    self.remove(span);
+                }
+                continue;
+            };
+
+            let id = &parsed.mermaid_id;
+            let relabel = node.label != parsed.label || node.shape != parsed.shape;
+            let decl = format_node_decl(node);
+            let declared = spans.iter().rev().find(|s| declares_shape(self.source(s), id)).copied();
+            let target = match declared {
+                Some(span) => Some(span),
+                None if relabel && decl != node.mermaid_id => spans.first().copied(),
+                None => None,
+            };
+
+            let mut replaced = None;
+            if let Some(span) = target {
+                match affected_at(&span) {
+                    Some(k) => declare.entry(k).or_default().push(node),
+                    None if relabel => {
+                        let suffix = class_suffix(self.source(&span));
+                        self.replace(span, format!("{}{}", decl, suffix));
+                        replaced = Some(span);
+                    }
+                    None => {}
+                }
+            }
+
+            if node.mermaid_id != *id {
+                for span in spans.iter().filter(|s| Some(**s) != replaced && affected_at(s).is_none()) {
+                    let id_span = Span {
+                        end: span.start + id.len(),
+                        ..*span
+                    };
+                    self.replace(id_span, node.mermaid_id.clone());
+                }
+            }
+
+            let elsewhere = spans.iter().any(|s| affected_at(s).is_none());
+            if !elsewhere && !survivors.contains(&node.uid) {
+                if let Some(k) = spans.iter().find_map(&affected_at) {
+                    mention.entry(k).or_default().push(node);
+                }
+            }
+        }
+
+        let mut emitted = Vec::new();
+        for (k, (span, indices)) in statements.iter().enumerate() {
+            let kept: Vec<&Edge> = indices.iter().filter_map(|&i| self.edges[i]).collect();
+            if !affected.contains(&k) {
+                emitted.extend(kept);
+                continue;
+            }
+
+            let full: Vec<&Node> = declare.remove(&k).unwrap_or_default();
+            let mut rendered: HashSet<&UID> = HashSet::new();
+            let mut lines = Vec::new();
+            let mut edge_lines = Vec::new();
+            for edge in &kept {
+                let mut decl = |uid: &'a UID| {
+                    let node = self.store.get_node(uid).expect("surviving edge endpoint");
+                    let first = rendered.insert(uid);
+                    if first && full.iter().any(|n| &n.uid == uid) {
+                        format_node_decl(node)
+                    } else {
+                        node.mermaid_id.clone()
+                    }
+                };
+                let (src, tgt) = (decl(&edge.source), decl(&edge.target));
+                edge_lines.push(format_edge_line(edge, &src, &tgt));
+            }
+            for node in full.iter().chain(mention.remove(&k).unwrap_or_default().iter()) {
+                if !rendered.contains(&node.uid) {
+                    lines.push(format_node_decl(node));
+                    rendered.insert(&node.uid);
+                }
+            }
+            lines.extend(edge_lines);
+
+            self.replace_statement(*span, lines);
+            emitted.extend(kept);
+        }
+
+        // New edges and nodes go after the last root-level statement
+        let matched: HashSet<&UID> = self.nodes.iter().flatten().map(|n| &n.uid).collect();
+        let grouped: HashSet<&UID> = self.store.groups.values().flat_map(|g| g.members.iter()).collect();
+        let mut declared: HashSet<&UID> = HashSet::new();
+        let mut lines = Vec::new();
+
+        let new_edges: Vec<&Edge> = self.new_edges().collect();
+        for edge in &new_edges {
+            let mut decl = |uid: &'a UID| {
+                let node = self.store.get_node(uid).expect("active edge endpoint");
+                if !matched.contains(uid) && !grouped.contains(uid) && declared.insert(uid) {
+                    format_node_decl(node)
+                } else {
+                    node.mermaid_id.clone()
+                }
+            };
+            let (src, tgt) = (decl(&edge.source), decl(&edge.target));
+            lines.push(format_edge_line(edge, &src, &tgt));
+        }
+        for node in self.store.active_nodes() {
+            let uid = &node.uid;
+            if !matched.contains(uid) && !grouped.contains(uid) && !declared.contains(uid) {
+                lines.push(format_node_decl(node));
+            }
+        }
+        self.insert_at_anchor(lines);
+
+        emitted.extend(new_edges);
+        emitted
+    }
+
+    /// Active store edges with active endpoints that are not in the original.
+    fn new_edges(&self) -> impl Iterator<Item = &'a Edge> + '_ {
+        let matched: HashSet<&EID> = self.edges.iter().flatten().map(|e| &e.eid).collect();
+        self.store.active_edges().filter(move |e| {
+            !matched.contains(&e.eid) && self.is_active(&e.source) && self.is_active(&e.target)
+        })
+    }
+
+    /// Update subgraph titles and directions, declare new members and add new groups.
+    fn update_groups(&mut self) {
+        let matched: HashSet<&UID> = self.nodes.iter().flatten().map(|n| &n.uid).collect();
+
+        for (old, source) in self.parsed.groups.iter().zip(&self.parsed.source_map.groups) {
+            let Some(group) = self.store.get_group(&old.id) else {
+                continue;
+            };
+
+            let header = format_subgraph_header(group);
+            if header != format_subgraph_header(old) {
+                self.replace(source.span, header);
+            }
+
+            let inner = format!("{}    ", self.indent_of(&source.span));
+            match (source.direction, group.direction) {
+                (Some(span), Some(direction)) if group.direction != old.direction => {
+                    self.replace(span, format!("direction {}", direction));
+                }
+                (Some(span), None) => self.remove(span),
+                (None, Some(direction)) => {
+                    let pos = line_end(self.text, source.span.end);
+                    self.insert(pos, format!("\n{}direction {}", inner, direction));
+                }
+                _ => {}
+            }
+
+            let Some(end) = source.end else {
+                continue;
+            };
+            let pos = line_start(self.text, end.start);
+            for uid in &group.members {
+                if let Some(node) = self.store.get_node(uid).filter(|n| !n.deleted && !matched.contains(uid)) {
+                    self.insert(pos, format!("{}{}\n", inner, format_node_decl(node)));
+                }
+            }
+        }
+
+        let mut lines = Vec::new();
+        for group in self.new_groups().filter(|g| g.parent.is_none()) {
+            push_group_lines(&mut lines, self.store, group, 0);
+        }
+        self.insert_at_anchor(lines);
+    }
+
+    /// Rewrite the styling statements as one block if what they say changed.
+    fn update_styles(&mut self, emitted: &[&Edge]) {
+        let before = GraphStore::from_parse_result(self.parsed.clone());
+        let before_edges: Vec<&Edge> = self.parsed.edges.iter().collect();
+        let mut old_lines = Vec::new();
+        push_style_lines(&mut old_lines, &before, &before_edges);
+
+        let mut lines = Vec::new();
+        push_style_lines(&mut lines, self.store, emitted);
+        if lines == old_lines {
+            return;
+        }
+
+        match self.parsed.source_map.styles.split_first() {
+            Some((first, rest)) => {
+                self.replace_statement(*first, lines);
+                for span in rest {
+                    self.remove(*span);
+                }
+            }
+            None => self.insert_at_anchor(lines),
+        }
+    }
+
+    /// Rewrite changed directives, remove those of deleted nodes and edges,
+    /// and add directives for everything that has none, AI records included.
+    fn update_directives(&mut self) {
+        let map = &self.parsed.source_map;
+        let mut lines = Vec::new();
+        // Unchanged directives that only need rewriting if the schema is upgraded
+        let mut outdated = Vec::new();
+
+        for (i, parsed) in self.parsed.nodes.iter().enumerate() {
+            match (self.nodes[i], map.nodes[i].directive) {
+                (Some(node), Some(span)) => {
+                    let layout = if span.start_line == span.end_line {
+                        self.layout
+                    } else {
+                        DirectiveLayout::Expanded
+                    };
+                    if format_node_directive(node) != format_node_directive(parsed) {
+                        self.replace(span, format_node_directive_with(node, layout));
+                    } else if self.is_outdated(&span) {
+                        outdated.push((span, format_node_directive_with(node, layout)));
+                    }
+                }
+                (None, Some(span)) => self.remove(span),
+                (Some(node), None) => lines.push(format_node_directive_with(node, self.layout)),
+                (None, None) => {}
+            }
+        }
+
+        let matched: HashSet<&UID> = self.nodes.iter().flatten().map(|n| &n.uid).collect();
+        for node in self.store.active_nodes().filter(|n| !matched.contains(&n.uid)) {
+            lines.push(format_node_directive_with(node, self.layout));
+        }
+
+        let directives: Vec<Option<(Span, String)>> = map
+            .edges
+            .iter()
+            .map(|e| {
+                let span = e.directive?;
+                let token = parse_edge_directive(self.source(&span))?.id;
+                Some((span, token))
+            })
+            .collect();
+        // New directives avoid the tokens and edge IDs already in use
+        let mut tokens = EdgeTokens::new(
+            directives
+                .iter()
+                .flatten()
+                .map(|(_, token)| token.as_str())
+                .chain(self.parsed.edges.iter().chain(self.store.active_edges()).filter_map(|e| e.mermaid_id.as_deref())),
+        );
+
+        for (i, parsed) in self.parsed.edges.iter().enumerate() {
+            match (self.edges[i], &directives[i]) {
+                (Some(edge), Some((span, token))) => {
+                    // The token follows the edge's Mermaid ID, if it has one
+                    let directive = format_edge_directive(edge, edge.mermaid_id.as_ref().unwrap_or(token));
+                    if directive != format_edge_directive(parsed, token) {
+                        self.replace(*span, directive);
+                    } else if self.is_outdated(span) {
+                        outdated.push((*span, directive));
+                    }
+                }
+                (None, Some((span, _))) => self.remove(*span),
+                (Some(edge), None) => lines.push(format_edge_directive(edge, &tokens.token(edge))),
+                (None, None) => {}
+            }
+        }
+        for edge in self.new_edges().collect::<Vec<_>>() {
+            lines.push(format_edge_directive(edge, &tokens.token(edge)));
+        }
+
+        // A node's n-th AI record keeps the n-th `%% @ai` line for it
+        let mut written: HashMap<&UID, usize> = HashMap::new();
+        for source in &map.provenance {
+            let index = written.get(&source.uid).copied().unwrap_or(0);
+            written.insert(&source.uid, index + 1);
+            let old = &self.parsed.provenance[&source.uid][index];
+            let node = self
+                .parsed
+                .nodes
+                .iter()
+                .position(|p| p.uid == source.uid)
+                .and_then(|i| self.nodes[i]);
+            match node.and_then(|n| Some((n, self.store.provenance_of(&n.uid).get(index)?))) {
+                Some((node, record)) => {
+                    let line = format_ai_directive(&node.uid, record);
+                    if line != format_ai_directive(&source.uid, old) {
+                        self.replace(source.span, line);
+                    } else if self.is_outdated(&source.span) {
+                        outdated.push((source.span, line));
+                    }
+                }
+                None => self.remove(source.span),
+            }
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            let Some(node) = node else { continue };
+            let count = written.get(&self.parsed.nodes[i].uid).copied().unwrap_or(0);
+            for record in self.store.provenance_of(&node.uid).iter().skip(count) {
+                lines.push(format_ai_directive(&node.uid, record));
+            }
+        }
+        for node in self.store.active_nodes().filter(|n| !matched.contains(&n.uid)) {
+            for record in self.store.provenance_of(&node.uid) {
+                lines.push(format_ai_directive(&node.uid, record));
+            }
+        }
+
+        let existing: Vec<Span> = map
+            .nodes
+            .iter()
+            .filter_map(|n| n.directive)
+            .chain(map.edges.iter().filter_map(|e| e.directive))
+            .chain(map.provenance.iter().map(|a| a.span))
+            .collect();
+
+        // A document that changes is brought up to the current schema
+        let touched = !self.edits.is_empty() || !self.removals.is_empty() || !lines.is_empty();
+        if touched && self.version() < SCHEMA_VERSION {
+            for (span, text) in outdated {
+                self.replace(span, text);
+            }
+            let first = existing.iter().map(|span| line_start(self.text, span.start)).min();
+            match (map.schema, first) {
+                (Some(span), _) => self.replace(span, format_schema_directive()),
+                // Before the line break ahead of the first directive, which
+                // removing that directive also deletes
+                (None, Some(0)) => self.insert(0, format!("{}\n", format_schema_directive())),
+                (None, Some(start)) => self.insert(start - 1, format!("\n{}", format_schema_directive())),
+                (None, None) if !lines.is_empty() => lines.insert(0, format_schema_directive()),
+                (None, None) => {}
+            }
+        }
+
+        if lines.is_empty() {
+            return;
+        }
+
+        let last = existing.iter().map(|span| span.end).max();
+        let body = lines.join("\n");
+        match last {
+            Some(end) => self.insert(line_end(self.text, end), format!("\n{}", body)),
+            None if self.text.ends_with("\n\n") => self.insert(self.text.len(), format!("{}\n", body)),
+            None if self.text.ends_with('\n') => self.insert(self.text.len(), format!("\n{}\n", body)),
+            None => self.insert(self.text.len(), format!("\n\n{}", body)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reconcile::reconcile;
+    use crate::write::generate_mermaidman;
+
+    const DOC: &str = r#"%% Team workflow
+flowchart LR
+
+    %% entry points
+    Start([Begin]) --> Review{Approved?}
+    Review -->|yes| Ship;  Review -->|no| Start
+
+subgraph lane [Release lane]
+  Ship[Ship it]
+end
+
+    classDef hot fill:#f96
+    class Ship hot
+
+%% @mermaidman: {"version":3}
+%% @node: Start {"uid":"n_start","x":0,"y":0}
+%% @node: Review {"uid":"n_review","x":100,"y":0}
+%% @node: Ship {"uid":"n_ship","x":200,"y":0}
+%% @edge: e1 {"eid":"e1","source":"n_start","target":"n_review"}
+%% @edge: e2 {"eid":"e2","source":"n_review","target":"n_ship"}
+%% @edge: e3 {"eid":"e3","source":"n_review","target":"n_start"}
+"#;
+
+    fn store() -> GraphStore {
+        GraphStore::from_parse_result(parse_document(DOC).unwrap())
+    }
+
+    /// Lines of `after` that are not in `before`, and the reverse.
+    fn diff(before: &str, after: &str) -> (Vec<String>, Vec<String>) {
+        let added = after.lines().filter(|l| !before.lines().any(|b| b == *l)).map(String::from).collect();
+        let removed = before.lines().filter(|l| !after.lines().any(|a| a == *l)).map(String::from).collect();
+        (added, removed)
+    }
+
+    #[test]
+    fn test_unchanged_store_is_byte_identical() {
+        assert_eq!(update_document(DOC, &store()), DOC);
+    }
+
+    #[test]
+    fn test_move_rewrites_only_its_directive() {
+        let mut store = store();
+        store.move_node(&UID::from_str("n_review"), 150.0, 40.0);
+
+        let (added, removed) = diff(DOC, &update_document(DOC, &store));
+        assert_eq!(removed, vec![r#"%% @node: Review {"uid":"n_review","x":100,"y":0}"#]);
+        assert_eq!(added.len(), 1);
+        assert!(added[0].starts_with(r#"%% @node: Review {"uid":"n_review","x":150.0,"y":40.0"#));
+    }
+
+    #[test]
+    fn test_relabel_and_rename_keep_formatting() {
+        let mut store = store();
+        store.get_node_mut(&UID::from_str("n_start")).unwrap().label = Some("Kick off".to_string());
+        store.rename_node(&UID::from_str("n_ship"), "Release");
+
+        let output = update_document(DOC, &store);
+        assert!(output.contains("    Start([Kick off]) --> Review{Approved?}\n"));
+        assert!(output.contains("    Review -->|yes| Release;  Review -->|no| Start\n"));
+        assert!(output.contains("  Release[Ship it]\n"));
+        assert!(output.contains("    class Release hot\n"));
+        assert!(output.starts_with("%% Team workflow\nflowchart LR\n\n    %% entry points\n"));
+
+        let reparsed = parse_document(&output).unwrap();
+        assert!(reparsed.diagnostics.is_empty());
+        assert_eq!(reparsed.groups[0].members, vec![UID::from_str("n_ship")]);
+    }
+
+    #[test]
+    fn test_add_and_delete_edges() {
+        let mut store = store();
+        store.delete_edge(&EID::from_str("e3"));
+        let mut node = Node::new("Notify");
+        node.uid = UID::from_str("n_notify");
+        node.label = Some("Tell team".to_string());
+        store.upsert_node(node);
+        let mut edge = Edge::new(UID::from_str("n_ship"), UID::from_str("n_notify"));
+        edge.eid = EID::from_str("e_notify");
+        store.upsert_edge(edge);
+
+        let output = update_document(DOC, &store);
+        assert!(output.contains("    Review -->|yes| Ship\n"));
+        assert!(output.contains("end\n    Ship --> Notify[Tell team]\n"));
+        assert!(!output.contains("n_start\",\"target\":\"n_start"));
+        assert!(!output.contains("%% @edge: e3"));
+        assert!(output.contains(r#"%% @edge: e4 {"eid":"e_notify","source":"n_ship","target":"n_notify"}"#));
+        assert!(output.contains("%% @node: Notify {\"uid\":\"n_notify\"}"));
+
+        let reparsed = parse_document(&output).unwrap();
+        assert_eq!(reparsed.edges.len(), 3);
+        assert!(reparsed.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_delete_node_keeps_its_neighbours() {
+        let mut store = store();
+        let review = UID::from_str("n_review");
+        for eid in ["e1", "e2", "e3"] {
+            store.delete_edge(&EID::from_str(eid));
+        }
+        store.delete_node(&review);
+
+        let output = update_document(DOC, &store);
+        assert!(!output.contains("Review"));
+        // `Start` was only declared in a removed statement
+        assert!(output.contains("    Start([Begin])\n"));
+
+        let reparsed = parse_document(&output).unwrap();
+        let ids: Vec<_> = reparsed.nodes.iter().map(|n| n.mermaid_id.as_str()).collect();
+        assert_eq!(ids, vec!["Start", "Ship"]);
+        assert!(reparsed.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_reconciled_edit_touches_one_line() {
+        let edited = DOC.replace("Review -->|yes| Ship", "Review -->|ok| Ship");
+        let result = reconcile(&edited, &store()).unwrap();
+
+        // The statement, and the directive that records the label
+        let (added, removed) = diff(DOC, &update_document(DOC, &result.store));
+        assert_eq!(removed[0], "    Review -->|yes| Ship;  Review -->|no| Start");
+        assert_eq!(added[0], "    Review -->|ok| Ship;  Review -->|no| Start");
+        assert_eq!(removed.len(), 2);
+        assert!(added[1].starts_with("%% @edge: e2 ") && added[1].contains(r#""label":"ok""#));
+    }
+
+    #[test]
+    fn test_adds_missing_directives_without_reformatting() {
+        let input = "graph TD\n  A[One] --> B\n";
+        let store = GraphStore::from_parse_result(parse_document(input).unwrap());
+
+        let output = update_document(input, &store);
+        assert!(output.starts_with("graph TD\n  A[One] --> B\n\n%% @mermaidman: {\"version\":3}\n%% @node: A {\"uid\":"));
+        assert_eq!(update_document(&output, &store), output);
+    }
+
+    #[test]
+    fn test_expanded_directive_stays_expanded() {
+        let input = "graph TD\nA\n\n%% @mermaidman: {\"version\":3}\n%% @node: A {\n%%   \"uid\": \"n_1\",\n%%   \"x\": 1\n%% }\n%% trailing comment\n";
+        let mut store = GraphStore::from_parse_result(parse_document(input).unwrap());
+        assert_eq!(update_document(input, &store), input);
+
+        store.move_node(&UID::from_str("n_1"), 5.0, 6.0);
+        let output = update_document(input, &store);
+        assert!(output.contains("\n%% @node: A {\n%%   \"uid\": \"n_1\",\n%%   \"x\": 5.0,"));
+        assert!(output.ends_with("%% }\n%% trailing comment\n"));
+    }
+
+    #[test]
+    fn test_upgrades_older_schema_on_change() {
+        let input = "graph TD\nA --> B\n\n%% @node: A {\"uid\":\"n_a\",\"meta\":{\"uid\":\"n_a\",\"x\":1}}\n%% @node: B {\"uid\":\"n_b\"}\n%% @edge: e1 {\"eid\":\"e_store\",\"source\":\"n_a\",\"target\":\"n_b\"}\n";
+        let mut store = GraphStore::from_parse_result(parse_document(input).unwrap());
+        assert_eq!(update_document(input, &store), input);
+        // Unversioned readers never matched edge directives, so the `eid` is kept
+        assert!(store.get_edge(&EID::from_str("e_store")).is_some());
+
+        store.move_node(&UID::from_str("n_b"), 5.0, 6.0);
+        let output = update_document(input, &store);
+        let (added, removed) = diff(input, &output);
+        assert_eq!(added[0], "%% @mermaidman: {\"version\":3}");
+        // The nested copy in A's directive is dropped, along with B's move
+        assert_eq!(removed.len(), 2);
+        assert!(!added[1].contains("\"x\":1"));
+        assert!(output.contains(r#"%% @edge: e1 {"eid":"e_store","source":"n_a","target":"n_b"}"#));
+
+        let reparsed = parse_document(&output).unwrap();
+        assert_eq!(reparsed.schema_version, Some(SCHEMA_VERSION));
+        assert!(reparsed.diagnostics.is_empty());
+
+        // Version 2 readers took the EID from the token, so it is written out
+        let input = input.replace("\n%% @node: A", "\n%% @mermaidman: {\"version\":2}\n%% @node: A");
+        let mut store = GraphStore::from_parse_result(parse_document(&input).unwrap());
+        assert!(store.get_edge(&EID::from_str("e1")).is_some());
+        store.move_node(&UID::from_str("n_b"), 5.0, 6.0);
+        let output = update_document(&input, &store);
+        assert!(output.contains(r#"%% @edge: e1 {"eid":"e1","source":"n_a","target":"n_b"}"#));
+    }
+
+    #[test]
+    fn test_unmatched_link_style_is_kept() {
+        let input = "graph TD\nA --> B\nlinkStyle 5 stroke:red\n";
+        let parsed = parse_document(input).unwrap();
+        assert_eq!(parsed.diagnostics[0].code, crate::diagnostic::DiagnosticCode::UnparsedStatement);
+        let mut store = GraphStore::from_parse_result(parsed);
+        assert!(generate_mermaidman(&store).contains("\nlinkStyle 5 stroke:red\n"));
+
+        // Restyling an edge adds a statement and leaves the raw one alone
+        let mut edge = store.active_edges().next().unwrap().clone();
+        edge.style = Some(crate::types::EdgeStyle::from_css("stroke:blue"));
+        store.upsert_edge(edge);
+        let output = update_document(input, &store);
+        assert!(output.starts_with("graph TD\nA --> B\nlinkStyle 0 stroke:blue\nlinkStyle 5 stroke:red\n"), "{}", output);
+    }
+
+    #[test]
+    fn test_ai_records_are_edited_in_place() {
+        use crate::types::{AiRecord, AiReview};
+
+        let mut store = store();
+        let review = UID::from_str("n_review");
+        let mut record = AiRecord::new("summarize");
+        record.timestamp = Some(1);
+        store.record_ai(&review, record);
+
+        let output = update_document(DOC, &store);
+        let (added, removed) = diff(DOC, &output);
+        assert!(removed.is_empty());
+        assert_eq!(added, vec![r#"%% @ai: n_review {"action":"summarize","timestamp":1}"#]);
+        assert!(output.ends_with("\n%% @ai: n_review {\"action\":\"summarize\",\"timestamp\":1}\n"));
+
+        let mut reopened = GraphStore::from_parse_result(parse_document(&output).unwrap());
+        assert_eq!(update_document(&output, &reopened), output);
+        reopened.set_ai_review(&review, 0, AiReview::Accepted);
+        let (added, _) = diff(&output, &update_document(&output, &reopened));
+        assert_eq!(added, vec![r#"%% @ai: n_review {"action":"summarize","timestamp":1,"status":"accepted"}"#]);
+
+        for eid in ["e1", "e2", "e3"] {
+            reopened.delete_edge(&EID::from_str(eid));
+        }
+        reopened.delete_node(&review);
+        assert!(!update_document(&output, &reopened).contains("@ai"));
+    }
+
+    #[test]
+    fn test_parallel_edges_keep_their_directives() {
+        let doc = "graph TD\n    A -->|retry| B\n    A -->|done| B\n\n%% @mermaidman: {\"version\":3}\n%% @node: A {\"uid\":\"n_a\"}\n%% @node: B {\"uid\":\"n_b\"}\n%% @edge: e1 {\"eid\":\"e1\",\"source\":\"n_a\",\"target\":\"n_b\",\"label\":\"retry\"}\n%% @edge: e2 {\"eid\":\"e2\",\"source\":\"n_a\",\"target\":\"n_b\",\"label\":\"done\"}\n";
+        let mut store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        assert_eq!(update_document(doc, &store), doc);
+
+        store.get_edge_mut(&EID::from_str("e2")).unwrap().meta = Some(serde_json::json!({"n": 2}));
+        let mut edge = Edge::new(UID::from_str("n_a"), UID::from_str("n_b"));
+        edge.eid = EID::from_str("e_late");
+        edge.mermaid_id = Some("late".to_string());
+        store.upsert_edge(edge);
+
+        let output = update_document(doc, &store);
+        let (added, removed) = diff(doc, &output);
+        assert_eq!(removed, vec![r#"%% @edge: e2 {"eid":"e2","source":"n_a","target":"n_b","label":"done"}"#]);
+        assert_eq!(
+            added,
+            vec![
+                "    A late@--> B",
+                r#"%% @edge: e2 {"eid":"e2","source":"n_a","target":"n_b","label":"done","meta":{"n":2}}"#,
+                r#"%% @edge: late {"eid":"e_late","source":"n_a","target":"n_b"}"#,
+            ]
+        );
+
+        let reparsed = parse_document(&output).unwrap();
+        assert_eq!(reparsed.edges[1].meta, Some(serde_json::json!({"n": 2})));
+        assert_eq!(reparsed.edges[2].mermaid_id, Some("late".to_string()));
+        assert!(reparsed.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_config_is_edited_where_it_is_set() {
+        let doc = "---\ntitle: Release\nconfig:\n  theme: dark\n---\n%%{init: { 'flowchart': { 'curve': 'basis' } } }%%\nflowchart LR\nA --> B\n\n%% @mermaidman: {\"version\":3}\n%% @node: A {\"uid\":\"n_a\"}\n%% @node: B {\"uid\":\"n_b\"}\n%% @edge: e1 {\"eid\":\"e1\",\"source\":\"n_a\",\"target\":\"n_b\"}\n";
+        let mut store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        assert_eq!(update_document(doc, &store), doc);
+
+        store.config.flowchart.as_mut().unwrap().curve = Some("step".to_string());
+        let (added, removed) = diff(doc, &update_document(doc, &store));
+        assert_eq!(added, [r#"%%{init: {"flowchart":{"curve":"step"}}}%%"#]);
+        assert_eq!(removed, ["%%{init: { 'flowchart': { 'curve': 'basis' } } }%%"]);
+
+        store.config.theme = Some("forest".to_string());
+        store.config.title = None;
+        let updated = update_document(doc, &store);
+        assert!(updated.starts_with("---\nconfig:\n  theme: forest\n---\n%%{init:"));
+        assert_eq!(parse_document(&updated).unwrap().config, store.config);
+
+        // A document without config gets frontmatter above its header
+        let plain = "flowchart LR\nA\n\n%% @mermaidman: {\"version\":3}\n%% @node: A {\"uid\":\"n_a\"}\n";
+        let mut store = GraphStore::from_parse_result(parse_document(plain).unwrap());
+        store.config.theme = Some("neutral".to_string());
+        assert_eq!(update_document(plain, &store), format!("---\nconfig:\n  theme: neutral\n---\n{}", plain));
+    }
+
+    #[test]
+    fn test_config_keys_are_placed_per_leaf() {
+        let doc = "---\n# keep this comment\nconfig:\n  theme: dark\n  flowchart:\n    curve: basis\n---\n%%{init: { 'flowchart': { 'rankSpacing': 80 } } }%%\nflowchart LR\nA --> B\n\n%% @mermaidman: {\"version\":3}\n%% @node: A {\"uid\":\"n_a\"}\n%% @node: B {\"uid\":\"n_b\"}\n%% @edge: e1 {\"eid\":\"e1\",\"source\":\"n_a\",\"target\":\"n_b\"}\n";
+        let parsed = GraphStore::from_parse_result(parse_document(doc).unwrap());
+
+        // A key set in the init line leaves the frontmatter untouched
+        let mut store = parsed.clone();
+        store.config.flowchart.as_mut().unwrap().rank_spacing = Some(90);
+        let (added, removed) = diff(doc, &update_document(doc, &store));
+        assert_eq!(added, [r#"%%{init: {"flowchart":{"rankSpacing":90}}}%%"#]);
+        assert_eq!(removed, ["%%{init: { 'flowchart': { 'rankSpacing': 80 } } }%%"]);
+
+        // A key of the frontmatter stays there, next to its sibling from the same mapping
+        let mut store = parsed.clone();
+        store.config.theme = Some("forest".to_string());
+        let updated = update_document(doc, &store);
+        assert!(updated.starts_with("---\nconfig:\n  theme: forest\n  flowchart:\n    curve: basis\n---\n%%{init: { 'flowchart': { 'rankSpacing': 80 } } }%%\n"));
+        assert_eq!(parse_document(&updated).unwrap().config, store.config);
+
+        // A new key joins the block that already sets its parent
+        let mut store = parsed.clone();
+        store.config.flowchart.as_mut().unwrap().node_spacing = Some(40);
+        let (added, _) = diff(doc, &update_document(doc, &store));
+        assert_eq!(added, [r#"%%{init: {"flowchart":{"rankSpacing":80,"nodeSpacing":40}}}%%"#]);
+
+        // A removed key leaves the other blocks alone
+        let mut store = parsed;
+        store.config.flowchart.as_mut().unwrap().rank_spacing = None;
+        let updated = update_document(doc, &store);
+        assert!(updated.starts_with("---\n# keep this comment\nconfig:\n  theme: dark\n  flowchart:\n    curve: basis\n---\nflowchart LR\n"));
+        assert_eq!(parse_document(&updated).unwrap().config, store.config);
+    }
+
+    #[test]
+    fn test_accessibility_is_edited_in_place() {
+        let doc = DOC.replacen("flowchart LR\n", "flowchart LR\naccDescr {\n  Who ships what\n}\n", 1);
+        let mut store = GraphStore::from_parse_result(parse_document(&doc).unwrap());
+        assert_eq!(update_document(&doc, &store), doc);
+
+        store.accessibility.title = Some("Release workflow".to_string());
+        store.accessibility.description = Some("Who ships what\nand when".to_string());
+        let updated = update_document(&doc, &store);
+        assert!(updated.contains("flowchart LR\naccTitle: Release workflow\naccDescr {\n    Who ships what\n    and when\n}\n\n    %% entry points"));
+        assert_eq!(parse_document(&updated).unwrap().accessibility, store.accessibility);
+
+        store.accessibility = Default::default();
+        assert_eq!(update_document(&doc, &store), DOC);
+    }
+
+    #[test]
+    fn test_regroup_falls_back_to_generation() {
+        let mut store = store();
+        store.groups.clear();
+
+        assert_eq!(update_document(DOC, &store), generate_mermaidman(&store));
+    }
+}
@@ -0,0 +1,168 @@
+//! Entity relationship diagram text generation.
+
+use crate::store::GraphStore;
+use crate::types::{Cardinality, Entity, ErAttribute, ErDiagram, Node};
+use crate::write::class::pair_relations;
+
+/// Indentation of entity attributes.
+const INDENT: &str = "    ";
+
+/// Append an ER diagram's statements: each entity node, with the attributes
+/// the model has for it, then the relationships.
+///
+/// As for class diagrams, the store's nodes and edges decide what is
+/// written; relationships added on the canvas are written one-to-one.
+pub(crate) fn push_er_lines(lines: &mut Vec<String>, store: &GraphStore) {
+    let empty = ErDiagram::default();
+    let diagram = store.er_diagram.as_ref().unwrap_or(&empty);
+    if let Some(direction) = store.header.direction {
+        lines.push(format!("direction {}", direction));
+    }
+
+    for node in store.active_nodes() {
+        push_entity(lines, store, node, diagram.entity(&node.mermaid_id));
+    }
+
+    let ends = diagram.relationships.iter().map(|r| (r.from.as_str(), r.to.as_str()));
+    let (paired, added) = pair_relations(store, ends);
+    for (relationship, edge) in diagram.relationships.iter().zip(paired) {
+        let Some(edge) = edge else { continue };
+        lines.push(format!(
+            "{} {}{}{} {} : {}",
+            relationship.from,
+            relationship.from_cardinality.left(),
+            if relationship.identifying { "--" } else { ".." },
+            relationship.to_cardinality.right(),
+            relationship.to,
+            format_label(edge.label.as_deref().unwrap_or_default())
+        ));
+    }
+    for (from, to, edge) in added {
+        lines.push(format!(
+            "{} {}--{} {} : {}",
+            from,
+            Cardinality::ExactlyOne.left(),
+            Cardinality::ExactlyOne.right(),
+            to,
+            format_label(edge.label.as_deref().unwrap_or_default())
+        ));
+    }
+
+    // Lines from entity bodies went with their entity
+    for raw in store.raw_lines.iter().filter(|r| r.owner.is_none()) {
+        lines.push(raw.text.clone());
+    }
+}
+
+/// Append an entity, with a body if it has attributes or raw lines.
+fn push_entity(lines: &mut Vec<String>, store: &GraphStore, node: &Node, entity: Option<&Entity>) {
+    let mut head = node.mermaid_id.clone();
+    if let Some(alias) = node.label.as_deref().filter(|l| *l != node.mermaid_id && !l.contains(['[', ']'])) {
+        head.push_str(&format!("[{}]", alias));
+    }
+
+    let attributes = entity.map(|e| e.attributes.as_slice()).unwrap_or_default();
+    let raw: Vec<_> = store.raw_lines.iter().filter(|r| r.owner.as_ref() == Some(&node.mermaid_id)).collect();
+    if attributes.is_empty() && raw.is_empty() {
+        lines.push(head);
+        return;
+    }
+    lines.push(format!("{} {{", head));
+    for attribute in attributes {
+        lines.push(format!("{}{}", INDENT, format_attribute(attribute)));
+    }
+    for raw in raw {
+        lines.push(format!("{}{}", INDENT, raw.text));
+    }
+    lines.push("}".to_string());
+}
+
+/// Format an attribute: `string email PK, UK "login"`.
+pub fn format_attribute(attribute: &ErAttribute) -> String {
+    let mut line = format!("{} {}", attribute.type_name, attribute.name);
+    if !attribute.keys.is_empty() {
+        let keys: Vec<_> = attribute.keys.iter().map(|k| k.as_str()).collect();
+        line.push_str(&format!(" {}", keys.join(", ")));
+    }
+    if let Some(ref comment) = attribute.comment {
+        line.push_str(&format!(" \"{}\"", comment));
+    }
+    line
+}
+
+/// A relationship label, quoted unless it is a single word.
+fn format_label(label: &str) -> String {
+    if !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        label.to_string()
+    } else {
+        format!("\"{}\"", label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_document;
+    use crate::store::GraphStore;
+    use crate::write::{generate_mermaidman, update_document};
+
+    const DOC: &str = "erDiagram
+    CUSTOMER[Customer] {
+        string email PK, UK \"login\"
+        varchar(64) name
+    }
+    CUSTOMER ||--o{ ORDER : places
+    ORDER }|..|{ LINE-ITEM : \"is part of\"
+";
+
+    #[test]
+    fn test_generate_er_diagram() {
+        let mut store = GraphStore::from_parse_result(parse_document(DOC).unwrap());
+        let order = store.get_node_by_mermaid_id("ORDER").unwrap().uid.clone();
+        store.move_node(&order, 300.0, 0.0);
+
+        let doc = generate_mermaidman(&store);
+        assert!(doc.starts_with(
+            "erDiagram
+
+CUSTOMER[Customer] {
+    string email PK, UK \"login\"
+    varchar(64) name
+}
+ORDER
+LINE-ITEM
+CUSTOMER ||--o{ ORDER : places
+ORDER }|..|{ LINE-ITEM : \"is part of\"
+"
+        ));
+
+        let reparsed = GraphStore::from_parse_result(parse_document(&doc).unwrap());
+        assert_eq!(reparsed.er_diagram, store.er_diagram);
+        assert_eq!(reparsed.get_node(&order).unwrap().x, Some(300.0));
+    }
+
+    #[test]
+    fn test_unchanged_er_diagram_keeps_its_text() {
+        let store = GraphStore::from_parse_result(parse_document(DOC).unwrap());
+        let saved = update_document(DOC, &store);
+        assert!(saved.starts_with(DOC));
+        assert!(saved.contains("%% @node: LINE-ITEM {\"uid\":"));
+
+        let store = GraphStore::from_parse_result(parse_document(&saved).unwrap());
+        assert_eq!(update_document(&saved, &store), saved);
+    }
+
+    #[test]
+    fn test_unparsed_body_lines_stay_in_their_entity() {
+        let doc = "erDiagram\nCUSTOMER {\n    decimal(10, 2) balance\n    %% cached\n    string name\n}\n%% end\n";
+        let mut store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        let customer = store.get_node_by_mermaid_id("CUSTOMER").unwrap().uid.clone();
+        let order = crate::types::Node::new("ORDER");
+        store.upsert_edge(crate::types::Edge::new(customer, order.uid.clone()));
+        store.upsert_node(order);
+
+        let saved = update_document(doc, &store);
+        assert!(saved.starts_with(
+            "erDiagram\n\nCUSTOMER {\n    string name\n    decimal(10, 2) balance\n    %% cached\n}\nORDER\nCUSTOMER ||--|| ORDER : \"\"\n%% end\n"
+        ), "{}", saved);
+    }
+}
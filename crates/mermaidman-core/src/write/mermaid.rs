@@ -1,21 +1,122 @@
 //! Mermaid text generation from graph store.
 
+use crate::parse::{encode_label, escape_label};
 use crate::store::GraphStore;
-use crate::write::canonical::{format_edge_directive, format_node_directive};
+use crate::types::{Accessibility, ArrowKind, DiagramKeyword, Edge, EdgeStyle, Group, LineKind, NodeLink, NodeShape};
+use crate::write::canonical::{
+    format_ai_directive, format_document_config, format_edge_directive, format_node_directive_with,
+    format_schema_directive, DirectiveLayout, EdgeTokens,
+};
+use crate::write::class::push_class_lines;
+use crate::write::er::push_er_lines;
+use crate::write::sequence::{generate_sequence, push_sequence_lines};
+use crate::write::state::push_state_lines;
+use indexmap::IndexMap;
 
 /// Generate a complete Mermaidman document from a graph store.
-pub fn generate_mermaidman(store: &GraphStore, direction: &str) -> String {
+pub fn generate_mermaidman(store: &GraphStore) -> String {
+    generate_mermaidman_with(store, DirectiveLayout::Compact)
+}
+
+/// Generate a complete Mermaidman document, laying out node directives with `layout`.
+pub fn generate_mermaidman_with(store: &GraphStore, layout: DirectiveLayout) -> String {
+    if let Some(ref sequence) = store.sequence {
+        return generate_sequence(store, sequence);
+    }
+
     let mut lines = Vec::new();
     
+    // Title and config
+    lines.extend(format_document_config(&store.config));
+    
     // Header
-    lines.push(format!("graph {}", direction));
+    lines.push(store.header.to_string());
+    push_accessibility_lines(&mut lines, &store.accessibility);
+    lines.push(String::new());
+    
+    // Topology: groups, edges and orphan nodes
+    push_body_lines(&mut lines, store);
+    
     lines.push(String::new());
     
-    // Topology: nodes and edges
+    // Directives
     let nodes: Vec<_> = store.active_nodes().collect();
     let edges: Vec<_> = store.active_edges().collect();
     
-    // Generate edge lines (which implicitly declare nodes)
+    if !nodes.is_empty() {
+        lines.push(format_schema_directive());
+    }
+    for node in &nodes {
+        lines.push(format_node_directive_with(node, layout));
+    }
+    
+    let mut tokens = EdgeTokens::new(edges.iter().filter_map(|e| e.mermaid_id.as_deref()));
+    for edge in &edges {
+        lines.push(format_edge_directive(edge, &tokens.token(edge)));
+    }
+    
+    for node in &nodes {
+        for record in store.provenance_of(&node.uid) {
+            lines.push(format_ai_directive(&node.uid, record));
+        }
+    }
+    
+    lines.join("\n")
+}
+
+/// Append the `accTitle` and `accDescr` statements, if set.
+fn push_accessibility_lines(lines: &mut Vec<String>, accessibility: &Accessibility) {
+    lines.extend(accessibility.title.as_deref().map(format_acc_title));
+    lines.extend(accessibility.description.as_deref().map(format_acc_descr));
+}
+
+/// Format an `accTitle` statement; it cannot span lines.
+pub(crate) fn format_acc_title(title: &str) -> String {
+    format!("accTitle: {}", title.lines().map(str::trim).collect::<Vec<_>>().join(" "))
+}
+
+/// Format an `accDescr` statement, as a block if the description spans lines.
+pub(crate) fn format_acc_descr(description: &str) -> String {
+    if description.contains('\n') {
+        let body: Vec<String> = description
+            .lines()
+            .map(|line| match line.trim() {
+                "" => String::new(),
+                line => format!("    {}", line),
+            })
+            .collect();
+        format!("accDescr {{\n{}\n}}", body.join("\n"))
+    } else {
+        format!("accDescr: {}", description.trim())
+    }
+}
+
+/// Append the diagram's statements in the syntax its header calls for.
+fn push_body_lines(lines: &mut Vec<String>, store: &GraphStore) {
+    if store.header.keyword.is_state() {
+        push_state_lines(lines, store);
+    } else if store.header.keyword == DiagramKeyword::ClassDiagram {
+        push_class_lines(lines, store);
+    } else if store.header.keyword == DiagramKeyword::ErDiagram {
+        push_er_lines(lines, store);
+    } else {
+        push_topology_lines(lines, store);
+    }
+}
+
+/// Append subgraph blocks, edge lines, orphan node declarations, styling
+/// statements and raw lines.
+fn push_topology_lines(lines: &mut Vec<String>, store: &GraphStore) {
+    let edges: Vec<_> = store.active_edges().collect();
+    
+    // Subgraphs declare their member nodes so membership survives the round trip
+    for group in store.root_groups() {
+        push_group_lines(lines, store, group, 0);
+    }
+    
+    // Generate edge lines (which implicitly declare nodes); `linkStyle` indices
+    // follow this emitted order
+    let mut emitted = Vec::new();
     for edge in &edges {
         let src = store.get_node(&edge.source);
         let tgt = store.get_node(&edge.target);
@@ -24,92 +125,235 @@ pub fn generate_mermaidman(store: &GraphStore, direction: &str) -> String {
             let src_decl = format_node_decl(src_node);
             let tgt_decl = format_node_decl(tgt_node);
             
-            let arrow = if let Some(ref label) = edge.label {
-                format!("--|{}|-->", label)
-            } else {
-                "-->".to_string()
-            };
-            
-            lines.push(format!("{} {} {}", src_decl, arrow, tgt_decl));
+            lines.push(format_edge_line(edge, &src_decl, &tgt_decl));
+            emitted.push(*edge);
         }
     }
     
-    // Add any orphan nodes (not in any edge)
-    let connected: std::collections::HashSet<_> = edges
+    // Add any orphan nodes (not in any edge or group)
+    let declared: std::collections::HashSet<_> = edges
         .iter()
         .flat_map(|e| [&e.source, &e.target])
+        .chain(store.groups.values().flat_map(|g| g.members.iter()))
         .collect();
     
-    for node in &nodes {
-        if !connected.contains(&node.uid) {
+    for node in store.active_nodes() {
+        if !declared.contains(&node.uid) {
             lines.push(format_node_decl(node));
         }
     }
     
-    lines.push(String::new());
+    push_style_lines(lines, store, &emitted);
     
-    // Directives
-    for node in &nodes {
-        lines.push(format_node_directive(node));
+    // Raw lines outside any (surviving) group go last, verbatim
+    for raw in &store.raw_lines {
+        let in_group = raw.group.as_ref().is_some_and(|g| store.groups.contains_key(g));
+        if !in_group {
+            lines.push(raw.text.clone());
+        }
     }
+}
+
+/// Format an edge statement between two node declarations or IDs.
+pub(crate) fn format_edge_line(edge: &Edge, src: &str, tgt: &str) -> String {
+    let mut link = format_link(edge.style.as_ref());
+    if let Some(ref id) = edge.mermaid_id {
+        link = format!("{}@{}", id, link);
+    }
+    let arrow = if let Some(ref label) = edge.label {
+        format!("{}|{}|", link, encode_label(label))
+    } else {
+        link
+    };
     
-    let mut edge_counter = 0;
-    for edge in &edges {
-        edge_counter += 1;
-        lines.push(format_edge_directive(edge, &format!("e{}", edge_counter)));
+    format!("{} {} {}", src, arrow, tgt)
+}
+
+/// Append `classDef`, `class`, `style`, `click` and `linkStyle` statements.
+///
+/// `edges` are the edges in the order their lines were emitted.
+pub(crate) fn push_style_lines(lines: &mut Vec<String>, store: &GraphStore, edges: &[&Edge]) {
+    for class_def in store.class_defs.values() {
+        lines.push(format!("classDef {} {}", class_def.name, class_def.style.to_css()));
     }
     
-    lines.join("\n")
+    // One `class` statement per class, listing its nodes in store order
+    let mut assignments: IndexMap<&str, Vec<&str>> = IndexMap::new();
+    for node in store.active_nodes() {
+        for class in &node.classes {
+            assignments.entry(class).or_default().push(&node.mermaid_id);
+        }
+    }
+    for (class, ids) in assignments {
+        lines.push(format!("class {} {}", ids.join(","), class));
+    }
+    
+    for node in store.active_nodes() {
+        let css = node.style.as_ref().map(|s| s.to_css()).unwrap_or_default();
+        if !css.is_empty() {
+            lines.push(format!("style {} {}", node.mermaid_id, css));
+        }
+    }
+    
+    for node in store.active_nodes() {
+        if let Some(ref link) = node.link {
+            if let Some(click) = format_click(&node.mermaid_id, link) {
+                lines.push(click);
+            }
+        }
+    }
+    
+    if let Some(ref style) = store.default_link_style {
+        let css = style.to_css();
+        if !css.is_empty() {
+            lines.push(format!("linkStyle default {}", css));
+        }
+    }
+    
+    // Edges sharing the same CSS are listed in one statement
+    let mut link_styles: IndexMap<String, Vec<String>> = IndexMap::new();
+    for (index, edge) in edges.iter().enumerate() {
+        let css = edge.style.as_ref().map(|s| s.to_css()).unwrap_or_default();
+        if !css.is_empty() {
+            link_styles.entry(css).or_default().push(index.to_string());
+        }
+    }
+    for (css, indices) in link_styles {
+        lines.push(format!("linkStyle {} {}", indices.join(","), css));
+    }
 }
 
-/// Format a node declaration (ID + label in brackets).
-fn format_node_decl(node: &crate::types::Node) -> String {
-    if let Some(ref label) = node.label {
-        if label != &node.mermaid_id {
-            return format!("{}[{}]", node.mermaid_id, label);
+/// Format the `subgraph` line of a group.
+pub(crate) fn format_subgraph_header(group: &Group) -> String {
+    match group.title {
+        Some(ref title) if title != &group.id => {
+            format!("subgraph {} [{}]", group.id, encode_label(title))
         }
+        _ => format!("subgraph {}", group.id),
     }
-    node.mermaid_id.clone()
 }
 
-/// Generate just the topology portion (no directives).
-pub fn generate_topology(store: &GraphStore, direction: &str) -> String {
-    let mut lines = Vec::new();
+/// Append a `subgraph ... end` block and its nested groups.
+pub(crate) fn push_group_lines(lines: &mut Vec<String>, store: &GraphStore, group: &Group, depth: usize) {
+    let indent = "    ".repeat(depth);
     
-    lines.push(format!("graph {}", direction));
+    lines.push(format!("{}{}", indent, format_subgraph_header(group)));
     
-    let edges: Vec<_> = store.active_edges().collect();
+    if let Some(ref direction) = group.direction {
+        lines.push(format!("{}    direction {}", indent, direction));
+    }
     
-    for edge in &edges {
-        let src = store.get_node(&edge.source);
-        let tgt = store.get_node(&edge.target);
-        
-        if let (Some(src_node), Some(tgt_node)) = (src, tgt) {
-            let src_decl = format_node_decl(src_node);
-            let tgt_decl = format_node_decl(tgt_node);
-            
-            let arrow = if let Some(ref label) = edge.label {
-                format!("--|{}|-->", label)
-            } else {
-                "-->".to_string()
-            };
-            
-            lines.push(format!("{} {} {}", src_decl, arrow, tgt_decl));
+    for uid in &group.members {
+        if let Some(node) = store.get_node(uid).filter(|n| !n.deleted) {
+            lines.push(format!("{}    {}", indent, format_node_decl(node)));
         }
     }
     
-    // Orphan nodes
-    let connected: std::collections::HashSet<_> = edges
-        .iter()
-        .flat_map(|e| [&e.source, &e.target])
-        .collect();
+    for raw in store.raw_lines.iter().filter(|r| r.group.as_deref() == Some(group.id.as_str())) {
+        lines.push(format!("{}    {}", indent, raw.text));
+    }
     
-    for node in store.active_nodes() {
-        if !connected.contains(&node.uid) {
-            lines.push(format_node_decl(node));
+    for child in &group.children {
+        if let Some(child_group) = store.get_group(child) {
+            push_group_lines(lines, store, child_group, depth + 1);
         }
     }
     
+    lines.push(format!("{}end", indent));
+}
+
+/// Format a `click` statement; `None` if the link has neither href nor callback.
+fn format_click(mermaid_id: &str, link: &NodeLink) -> Option<String> {
+    let mut click = match (&link.href, &link.callback) {
+        (Some(href), _) => format!("click {} href \"{}\"", mermaid_id, escape_label(href)),
+        (None, Some(callback)) if callback.contains('(') => {
+            format!("click {} call {}", mermaid_id, callback)
+        }
+        (None, Some(callback)) => format!("click {} {}", mermaid_id, callback),
+        (None, None) => return None,
+    };
+    
+    if let Some(ref tooltip) = link.tooltip {
+        click.push_str(&format!(" \"{}\"", escape_label(tooltip)));
+    }
+    if let (Some(_), Some(target)) = (&link.href, &link.target) {
+        click.push_str(&format!(" {}", target));
+    }
+    
+    Some(click)
+}
+
+/// Format the Mermaid link syntax for an edge style (`-->`, `-.->`, `<==>`, ...).
+pub(crate) fn format_link(style: Option<&EdgeStyle>) -> String {
+    let default_style = EdgeStyle::default();
+    let style = style.unwrap_or(&default_style);
+    let line = style.line_kind();
+    let length = style.length.unwrap_or(1).max(1) as usize;
+    
+    if line == LineKind::Invisible {
+        return "~".repeat(length + 2);
+    }
+    
+    let head = |arrow: ArrowKind, arrowhead: &'static str| match arrow {
+        ArrowKind::Default => arrowhead,
+        ArrowKind::None => "",
+        ArrowKind::Circle => "o",
+        ArrowKind::Cross => "x",
+    };
+    let start = head(style.arrow_start.unwrap_or(ArrowKind::None), "<");
+    let end = head(style.arrow.unwrap_or_default(), ">");
+    
+    let body = match line {
+        LineKind::Dotted => format!("-{}-", ".".repeat(length)),
+        LineKind::Thick if end.is_empty() => "=".repeat(length + 2),
+        LineKind::Thick => "=".repeat(length + 1),
+        _ if end.is_empty() => "-".repeat(length + 2),
+        _ => "-".repeat(length + 1),
+    };
+    
+    format!("{}{}{}", start, body, end)
+}
+
+/// Format a node declaration (ID + label in shape delimiters).
+pub(crate) fn format_node_decl(node: &crate::types::Node) -> String {
+    if node.shape == NodeShape::Rect {
+        if let Some(ref label) = node.label {
+            if label != &node.mermaid_id {
+                return format!("{}[{}]", node.mermaid_id, encode_label(label));
+            }
+        }
+        return node.mermaid_id.clone();
+    }
+    
+    if let NodeShape::Custom(ref name) = node.shape {
+        return match node.label {
+            Some(ref label) => format!(
+                "{}@{{ shape: {}, label: \"{}\" }}",
+                node.mermaid_id,
+                name,
+                escape_label(label)
+            ),
+            None => format!("{}@{{ shape: {} }}", node.mermaid_id, name),
+        };
+    }
+    
+    let label = node.label.as_deref().unwrap_or(&node.mermaid_id);
+    let (open, close) = node.shape.delimiters().unwrap_or(("[", "]"));
+    format!("{}{}{}{}", node.mermaid_id, open, encode_label(label), close)
+}
+
+/// Generate just the topology portion (no directives).
+pub fn generate_topology(store: &GraphStore) -> String {
+    let mut lines = Vec::new();
+    
+    lines.push(store.header.to_string());
+    if let Some(ref sequence) = store.sequence {
+        push_sequence_lines(&mut lines, sequence);
+        return lines.join("\n");
+    }
+    push_accessibility_lines(&mut lines, &store.accessibility);
+    push_body_lines(&mut lines, store);
+    
     lines.join("\n")
 }
 
@@ -144,7 +388,7 @@ mod tests {
         );
         store.upsert_edge(edge);
         
-        let doc = generate_mermaidman(&store, "TD");
+        let doc = generate_mermaidman(&store);
         
         assert!(doc.contains("graph TD"));
         assert!(doc.contains("A[Start]"));
@@ -153,4 +397,440 @@ mod tests {
         assert!(doc.contains("%% @node: A"));
         assert!(doc.contains("%% @node: B"));
     }
+
+    #[test]
+    fn test_generate_preserves_shapes() {
+        let input = r#"graph TD
+A{Decide} --> B((Circle))
+B --> C[/Lean\]
+C --> D@{ shape: doc, label: "Report" }
+E(((Done)))
+"#;
+
+        let parsed = crate::parse::parse_document(input).unwrap();
+        let store = GraphStore::from_parse_result(parsed);
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains("A{Decide} --> B((Circle))"));
+        assert!(doc.contains("C[/Lean\\]"));
+        assert!(doc.contains("D@{ shape: doc, label: \"Report\" }"));
+        assert!(doc.contains("E(((Done)))"));
+
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        let shapes: Vec<_> = reparsed.nodes.iter().map(|n| n.shape.clone()).collect();
+        let original: Vec<_> = store.active_nodes().map(|n| n.shape.clone()).collect();
+        assert_eq!(shapes, original);
+    }
+
+    #[test]
+    fn test_generate_preserves_arrows() {
+        let links = [
+            "-->", "---", "-.->", "-.-", "-..->", "==>", "===", "====>", "~~~",
+            "--o", "--x", "<-->", "o--o", "x--x", "<-.->", "<==>", "--->",
+        ];
+        let input = links
+            .iter()
+            .enumerate()
+            .map(|(i, link)| format!("A{} {} B{}", i, link, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let parsed = crate::parse::parse_document(&format!("flowchart LR\n{}", input)).unwrap();
+        let store = GraphStore::from_parse_result(parsed);
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.starts_with("flowchart LR\n"));
+        for (i, link) in links.iter().enumerate() {
+            assert!(doc.contains(&format!("A{} {} B{}", i, link, i)), "lost {}", link);
+        }
+    }
+
+    #[test]
+    fn test_generate_labelled_dotted_edge() {
+        let input = "graph TD\nA -.->|optional| B\n";
+
+        let parsed = crate::parse::parse_document(input).unwrap();
+        let store = GraphStore::from_parse_result(parsed);
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains("A -.->|optional| B"));
+        assert!(!doc.contains("\"line\""));
+    }
+
+    #[test]
+    fn test_generate_config_and_accessibility() {
+        let input = "%%{init: {'theme': 'dark', 'flowchart': {'curve': 'basis'}}}%%\ngraph TD\nA --> B\n";
+        let mut store = GraphStore::from_parse_result(crate::parse::parse_document(input).unwrap());
+        store.config.title = Some("Flow".to_string());
+        store.accessibility.description = Some("Two\nlines".to_string());
+
+        let doc = generate_mermaidman(&store);
+        assert!(doc.starts_with("---\ntitle: Flow\nconfig:\n  theme: dark\n  flowchart:\n    curve: basis\n---\ngraph TD\naccDescr {\n    Two\n    lines\n}\n"));
+
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        assert_eq!(reparsed.config, store.config);
+        assert_eq!(reparsed.accessibility, store.accessibility);
+        assert!(reparsed.raw_lines.is_empty());
+    }
+
+    #[test]
+    fn test_generate_parallel_edges_round_trip() {
+        let input = "graph TD\nA e2@-->|first| B\nA -->|second| B\nA -->|third| B\n";
+
+        let parsed = crate::parse::parse_document(input).unwrap();
+        let mut store = GraphStore::from_parse_result(parsed);
+        for (i, edge) in store.edges.values_mut().enumerate() {
+            edge.meta = Some(serde_json::json!({ "n": i }));
+        }
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains("A e2@-->|first| B\nA -->|second| B\n"));
+        assert!(doc.contains("%% @edge: e2 {"));
+        assert!(doc.contains("%% @edge: e1 {") && doc.contains("%% @edge: e3 {"));
+
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        let labels: Vec<_> = reparsed.edges.iter().map(|e| e.label.clone().unwrap()).collect();
+        let meta: Vec<_> = reparsed.edges.iter().map(|e| e.meta.clone().unwrap()).collect();
+        assert_eq!(labels, ["first", "second", "third"]);
+        assert_eq!(meta, [serde_json::json!({"n": 0}), serde_json::json!({"n": 1}), serde_json::json!({"n": 2})]);
+        assert!(reparsed.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_generate_standard_label_form() {
+        let input = "graph TD\nA -- yes --> B\nB --|legacy|--> C\nC == strong ==> D\n";
+
+        let parsed = crate::parse::parse_document(input).unwrap();
+        let store = GraphStore::from_parse_result(parsed);
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains("A -->|yes| B"));
+        assert!(doc.contains("B -->|legacy| C"));
+        assert!(doc.contains("C ==>|strong| D"));
+    }
+
+    #[test]
+    fn test_generate_subgraphs_round_trip() {
+        let input = r#"graph TD
+subgraph lane [Lane]
+    direction LR
+    A[Start]
+    subgraph inner
+        B[Inner]
+    end
+end
+A --> B
+C --> A
+"#;
+
+        let parsed = crate::parse::parse_document(input).unwrap();
+        let store = GraphStore::from_parse_result(parsed);
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains("subgraph lane [Lane]\n    direction LR\n    A[Start]\n    subgraph inner\n        B[Inner]\n    end\nend"));
+
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        assert_eq!(reparsed.groups.len(), 2);
+        assert_eq!(reparsed.groups[0].members.len(), 1);
+        assert_eq!(reparsed.groups[1].parent, Some("lane".to_string()));
+        assert_eq!(reparsed.edges.len(), 2);
+        assert!(reparsed.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_generate_class_and_style_round_trip() {
+        let input = r#"graph TD
+A:::hot --> B
+classDef hot fill:#f96,stroke:#333
+classDef cold fill:#9cf
+class B,C cold
+style B stroke-width:4px,stroke-dasharray: 5 5
+"#;
+
+        let parsed = crate::parse::parse_document(input).unwrap();
+        let mut store = GraphStore::from_parse_result(parsed);
+        if let Some(node) = store.nodes.values_mut().find(|n| n.mermaid_id == "B") {
+            if let Some(style) = node.style.as_mut() {
+                style.border = Some("2px".to_string());
+            }
+        }
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains("classDef hot fill:#f96,stroke:#333\nclassDef cold fill:#9cf"));
+        assert!(doc.contains("class A hot\nclass B cold"));
+        assert!(doc.contains("style B stroke-width:4px,stroke-dasharray:5 5"));
+        // Only canvas-only style keys stay in the directive
+        assert!(doc.contains(r#""style":{"border":"2px"}"#));
+        assert!(!doc.contains("stroke_width"));
+
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        assert_eq!(reparsed.class_defs.len(), 2);
+        assert_eq!(reparsed.nodes[0].classes, vec!["hot"]);
+        assert_eq!(reparsed.nodes[1].classes, vec!["cold"]);
+        assert_eq!(
+            reparsed.nodes[1].style.as_ref().unwrap().stroke_width,
+            Some("4px".to_string())
+        );
+    }
+
+    #[test]
+    fn test_generate_link_styles_follow_emitted_order() {
+        let input = r#"graph TD
+A --> B
+B --> C
+C --> D
+linkStyle default stroke:#999
+linkStyle 0,2 stroke:#f00
+linkStyle 1 stroke:#0f0
+"#;
+
+        let parsed = crate::parse::parse_document(input).unwrap();
+        let mut store = GraphStore::from_parse_result(parsed);
+
+        // Move the first edge to the end, as a canvas edit might
+        let (eid, edge) = store.edges.shift_remove_index(0).unwrap();
+        store.edges.insert(eid, edge);
+
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains("B --> C\nC --> D\nA --> B"));
+        assert!(doc.contains("linkStyle default stroke:#999\nlinkStyle 0 stroke:#0f0\nlinkStyle 1,2 stroke:#f00"));
+        assert!(!doc.contains(r#""style""#));
+
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        let css: Vec<_> = reparsed
+            .edges
+            .iter()
+            .map(|e| e.style.as_ref().unwrap().to_css())
+            .collect();
+        assert_eq!(css, vec!["stroke:#0f0", "stroke:#f00", "stroke:#f00"]);
+    }
+
+    #[test]
+    fn test_generate_click_round_trip() {
+        let input = r#"graph TD
+A --> B --> C
+click A "other.mmd#Start" "Open" _blank
+click B call notify(B) "Notify"
+click C showDetails
+"#;
+
+        let parsed = crate::parse::parse_document(input).unwrap();
+        let store = GraphStore::from_parse_result(parsed);
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains(r#"click A href "other.mmd#Start" "Open" _blank"#));
+        assert!(doc.contains(r#"click B call notify(B) "Notify""#));
+        assert!(doc.contains("click C showDetails\n"));
+
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        let links: Vec<_> = reparsed.nodes.iter().map(|n| n.link.clone()).collect();
+        let original: Vec<_> = store.active_nodes().map(|n| n.link.clone()).collect();
+        assert_eq!(links, original);
+    }
+
+    #[test]
+    fn test_generate_escapes_click_hrefs() {
+        let mut store = GraphStore::new();
+        let mut node = Node::new("A");
+        node.link = Some(NodeLink {
+            href: Some(r#"search?q="x" #35;"#.to_string()),
+            tooltip: Some(r#"Find "x""#.to_string()),
+            ..Default::default()
+        });
+        store.upsert_node(node);
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.contains(r#"click A href "search?q=#quot;x#quot; #35;35;" "Find #quot;x#quot;""#));
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        assert_eq!(reparsed.nodes[0].link, store.active_nodes().next().unwrap().link);
+    }
+
+    #[test]
+    fn test_generate_escapes_labels() {
+        let labels = [
+            "Hello [world]",
+            "line1<br/>line2",
+            "`**bold** text`",
+            "multi\nline",
+            r#"say "hi" #35;"#,
+            "x }} y",
+            "a | b",
+            " padded ",
+        ];
+
+        let mut store = GraphStore::new();
+        let mut previous: Option<UID> = None;
+        for (i, label) in labels.iter().enumerate() {
+            let mut node = Node::new(&format!("N{}", i));
+            node.label = Some(label.to_string());
+            node.shape = if i % 2 == 0 { NodeShape::Rect } else { NodeShape::Hexagon };
+            if let Some(prev) = previous.replace(node.uid.clone()) {
+                let mut edge = Edge::new(prev, node.uid.clone());
+                edge.label = Some(label.to_string());
+                store.upsert_edge(edge);
+            }
+            store.upsert_node(node);
+        }
+        let mut custom = Node::new("C");
+        custom.shape = NodeShape::Custom("cloud".to_string());
+        custom.label = Some(r#"a "b", c"#.to_string());
+        store.upsert_node(custom);
+
+        let doc = generate_mermaidman(&store);
+        assert!(doc.contains(r#"N0["Hello [world]"]"#));
+        assert!(doc.contains(r#"N4["say #quot;hi#quot; #35;35;"]"#));
+
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        let parsed_labels: Vec<_> = reparsed.nodes.iter().map(|n| n.label.clone().unwrap()).collect();
+        let mut expected: Vec<_> = labels.iter().map(|l| l.to_string()).collect();
+        expected.push(r#"a "b", c"#.to_string());
+        assert_eq!(parsed_labels, expected);
+
+        let edge_labels: Vec<_> = reparsed.edges.iter().map(|e| e.label.clone().unwrap()).collect();
+        assert_eq!(edge_labels, expected[1..labels.len()].to_vec());
+    }
+
+    #[test]
+    fn test_generate_keeps_raw_lines() {
+        let input = r#"graph TD
+%% keep me
+A --> B
+A -> B
+subgraph lane
+    C --> D
+    C ==>> D
+end
+"#;
+
+        let store = GraphStore::from_parse_result(crate::parse::parse_document(input).unwrap());
+        let doc = generate_mermaidman(&store);
+        assert!(doc.contains("\n%% keep me\n"));
+        assert!(doc.contains("\nA -> B\n"));
+        assert!(doc.contains("    C ==>> D\nend"));
+
+        // Writing again neither drops nor duplicates them
+        let reparsed = crate::parse::parse_document(&doc).unwrap();
+        assert_eq!(reparsed.raw_lines.len(), store.raw_lines.len());
+        let topology = |doc: &str| doc.split("%% @node").next().unwrap().to_string();
+        let again = generate_mermaidman(&GraphStore::from_parse_result(reparsed));
+        assert_eq!(topology(&again), topology(&doc));
+    }
+
+    #[test]
+    fn test_directive_fields_are_a_fixed_point() {
+        use crate::types::{CodeMeta, DiagramMeta, MediaMeta, NodeKind, NodeStyle};
+
+        let mut store = GraphStore::new();
+        let mut code = Node::new("A");
+        code.uid = UID::from_str("n_a");
+        code.x = Some(10.5);
+        code.y = Some(-3.25);
+        code.kind = NodeKind::Code;
+        code.code = Some(CodeMeta {
+            language: Some("rust".to_string()),
+            content: Some("fn main() {}\n".to_string()),
+            ..Default::default()
+        });
+        code.style = Some(NodeStyle {
+            fill: Some("#f9f".to_string()),
+            border: Some("2px dashed".to_string()),
+            ..Default::default()
+        });
+        code.meta = Some(serde_json::json!({"tags": ["draft"]}));
+
+        let mut media = Node::new("B");
+        media.uid = UID::from_str("n_b");
+        media.kind = NodeKind::Media;
+        media.media = Some(MediaMeta {
+            src: "docs.pdf".to_string(),
+            ..Default::default()
+        });
+        media.diagram = Some(DiagramMeta {
+            title: Some("Inner".to_string()),
+            mermaidman: Some("graph TD\nX-->Y\n".to_string()),
+        });
+        media.markdown = Some("# Notes".to_string());
+
+        store.upsert_node(code);
+        store.upsert_node(media);
+        let mut record = crate::types::AiRecord::new("format");
+        record.provider = Some("gemini".to_string());
+        record.meta = Some(serde_json::json!({"model": "2.0"}));
+        store.record_ai(&UID::from_str("n_a"), record.clone());
+        let mut edge = Edge::with_eid(EID::from_str("e1"), UID::from_str("n_a"), UID::from_str("n_b"));
+        edge.label = Some("uses".to_string());
+        edge.meta = Some(serde_json::json!({"weight": 2}));
+        store.upsert_edge(edge);
+
+        let doc = generate_mermaidman(&store);
+        let parsed = GraphStore::from_parse_result(crate::parse::parse_document(&doc).unwrap());
+        assert_eq!(generate_mermaidman(&parsed), doc);
+
+        let a = parsed.get_node(&UID::from_str("n_a")).unwrap();
+        assert_eq!((a.x, a.y), (Some(10.5), Some(-3.25)));
+        assert_eq!(a.code.as_ref().unwrap().language.as_deref(), Some("rust"));
+        assert_eq!(a.style.as_ref().unwrap().border.as_deref(), Some("2px dashed"));
+        assert_eq!(a.meta, Some(serde_json::json!({"tags": ["draft"]})));
+        let b = parsed.get_node(&UID::from_str("n_b")).unwrap();
+        assert_eq!(b.media.as_ref().unwrap().src, "docs.pdf");
+        assert_eq!(b.markdown.as_deref(), Some("# Notes"));
+        assert_eq!(b.meta, None);
+        let edge = parsed.active_edges().next().unwrap();
+        assert_eq!(edge.meta, Some(serde_json::json!({"weight": 2})));
+        assert_eq!(parsed.provenance_of(&UID::from_str("n_a")), &[record]);
+    }
+
+    /// A random edge: source and target node indices, label, meta weight and
+    /// whether it has a Mermaid edge ID.
+    type ArbEdge = (usize, usize, Option<String>, Option<i64>, bool);
+
+    /// A random graph: node labels, then edges.
+    fn arb_graph() -> impl proptest::strategy::Strategy<Value = (Vec<Option<String>>, Vec<ArbEdge>)> {
+        use proptest::prelude::*;
+
+        let label = "[A-Za-z0-9][A-Za-z0-9 ,.?!()#|\"-]{0,10}[A-Za-z0-9]";
+        proptest::collection::vec(proptest::option::of(label), 1..6).prop_flat_map(move |nodes| {
+            let n = nodes.len();
+            let edge = (0..n, 0..n, proptest::option::of(label), proptest::option::of(any::<i64>()), any::<bool>());
+            (Just(nodes), proptest::collection::vec(edge, 0..10))
+        })
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_edges_survive_save_and_open((nodes, edges) in arb_graph()) {
+            let mut store = GraphStore::new();
+            for (i, label) in nodes.iter().enumerate() {
+                let mut node = Node::new(&format!("N{}", i));
+                node.uid = UID::from_str(&format!("n_{}", i));
+                node.label = label.clone();
+                store.upsert_node(node);
+            }
+            for (i, (src, tgt, label, weight, has_id)) in edges.iter().enumerate() {
+                let mut edge = Edge::new(UID::from_str(&format!("n_{}", src)), UID::from_str(&format!("n_{}", tgt)));
+                // Edge IDs that look like the writer's own `eN` tokens
+                edge.mermaid_id = has_id.then(|| format!("e{}", i * 2));
+                edge.label = label.clone();
+                edge.meta = weight.map(|w| serde_json::json!({ "weight": w }));
+                store.upsert_edge(edge);
+            }
+
+            let doc = generate_mermaidman(&store);
+            let parsed = crate::parse::parse_document(&doc).unwrap();
+            proptest::prop_assert!(parsed.diagnostics.is_empty(), "{:?}", parsed.diagnostics);
+            let reopened = GraphStore::from_parse_result(parsed);
+
+            let summary = |store: &GraphStore| -> Vec<_> {
+                store
+                    .active_edges()
+                    .map(|e| (e.eid.clone(), e.source.clone(), e.target.clone(), e.label.clone(), e.meta.clone()))
+                    .collect()
+            };
+            proptest::prop_assert_eq!(summary(&reopened), summary(&store));
+            proptest::prop_assert_eq!(crate::write::update_document(&doc, &reopened), doc);
+        }
+    }
 }
@@ -1,7 +1,13 @@
 //! Canonical serialization for Mermaidman documents.
 
 mod canonical;
+mod class;
+mod cst;
+mod er;
 mod mermaid;
+mod sequence;
+mod state;
 
 pub use canonical::*;
+pub use cst::*;
 pub use mermaid::*;
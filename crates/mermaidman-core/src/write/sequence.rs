@@ -0,0 +1,207 @@
+//! Sequence diagram text generation.
+
+use crate::store::GraphStore;
+use crate::types::{Participant, SequenceDiagram, SequenceStatement};
+use crate::write::canonical::{format_document_config, format_participant_directive, format_schema_directive};
+
+/// Indentation per nesting level; top-level statements get one.
+const INDENT: &str = "    ";
+
+/// Generate a complete sequence diagram document.
+pub(crate) fn generate_sequence(store: &GraphStore, sequence: &SequenceDiagram) -> String {
+    let mut lines = Vec::new();
+
+    lines.extend(format_document_config(&store.config));
+    lines.push(store.header.to_string());
+    push_sequence_lines(&mut lines, sequence);
+    lines.push(String::new());
+
+    if !sequence.participants.is_empty() {
+        lines.push(format_schema_directive());
+    }
+    for participant in &sequence.participants {
+        lines.push(format_participant_directive(participant));
+    }
+
+    lines.join("\n")
+}
+
+/// Append participant declarations and statements.
+///
+/// Participants up to the last declared one are declared, so the display
+/// order does not depend on where each is first mentioned. A box is written
+/// where its first participant is declared, with all of its participants.
+pub(crate) fn push_sequence_lines(lines: &mut Vec<String>, sequence: &SequenceDiagram) {
+    let declared = sequence
+        .participants
+        .iter()
+        .rposition(|p| p.declared)
+        .map_or(0, |last| last + 1);
+    let mut boxed = vec![false; sequence.boxes.len()];
+    for participant in &sequence.participants[..declared] {
+        let in_box = sequence.boxes.iter().position(|b| b.participants.contains(&participant.id));
+        let Some(index) = in_box else {
+            lines.push(format!("{}{}", INDENT, format_participant(participant)));
+            continue;
+        };
+        if std::mem::replace(&mut boxed[index], true) {
+            continue;
+        }
+        let participant_box = &sequence.boxes[index];
+        lines.push(format!("{}box {}", INDENT, participant_box.label).trim_end().to_string());
+        for participant in participant_box.participants.iter().filter_map(|id| sequence.participant(id)) {
+            lines.push(format!("{}{}{}", INDENT, INDENT, format_participant(participant)));
+        }
+        lines.push(format!("{}end", INDENT));
+    }
+
+    push_statements(lines, &sequence.statements, 1);
+}
+
+/// Format a `participant`/`actor` line.
+pub(crate) fn format_participant(participant: &Participant) -> String {
+    match participant.alias {
+        Some(ref alias) => format!("{} {} as {}", participant.kind.as_str(), participant.id, alias),
+        None => format!("{} {}", participant.kind.as_str(), participant.id),
+    }
+}
+
+fn push_statements(lines: &mut Vec<String>, statements: &[SequenceStatement], depth: usize) {
+    let indent = INDENT.repeat(depth);
+
+    for statement in statements {
+        match statement {
+            SequenceStatement::Message(message) => {
+                let activation = match message.activation {
+                    Some(true) => "+",
+                    Some(false) => "-",
+                    None => "",
+                };
+                let text = match message.text.as_str() {
+                    "" => String::new(),
+                    text => format!(": {}", text),
+                };
+                lines.push(format!(
+                    "{}{}{}{}{}{}",
+                    indent,
+                    message.from,
+                    message.arrow.as_str(),
+                    activation,
+                    message.to,
+                    text
+                ));
+            }
+            SequenceStatement::Activation { participant, active } => {
+                let keyword = if *active { "activate" } else { "deactivate" };
+                lines.push(format!("{}{} {}", indent, keyword, participant));
+            }
+            SequenceStatement::Note(note) => {
+                lines.push(format!(
+                    "{}Note {} {}: {}",
+                    indent,
+                    note.placement.as_str(),
+                    note.participants.join(","),
+                    note.text
+                ));
+            }
+            SequenceStatement::Block(block) => {
+                for (i, section) in block.sections.iter().enumerate() {
+                    let keyword = match i {
+                        0 => block.kind.as_str(),
+                        _ => block.kind.section_keyword().unwrap_or("else"),
+                    };
+                    lines.push(format!("{}{} {}", indent, keyword, section.label).trim_end().to_string());
+                    push_statements(lines, &section.statements, depth + 1);
+                }
+                lines.push(format!("{}end", indent));
+            }
+            SequenceStatement::Raw { text } => lines.push(format!("{}{}", indent, text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_document;
+    use crate::store::GraphStore;
+    use crate::write::{generate_mermaidman, update_document};
+
+    const DOC: &str = "sequenceDiagram
+    Client->>+Server: GET /status
+    participant Server as API server
+    %% retries are bounded
+    loop up to 3 times
+        Server-->>Cache: lookup
+    end
+    par fast path
+        Cache--)Server: hit
+    and slow path
+        Server-xDB: query
+    end
+    Note right of Client: waits
+    Server-->>-Client: 200 OK
+";
+
+    #[test]
+    fn test_generate_sequence_round_trip() {
+        let mut store = GraphStore::from_parse_result(parse_document(DOC).unwrap());
+        assert!(store.move_participant("Server", 240.0, 0.0));
+
+        let doc = generate_mermaidman(&store);
+        assert!(doc.starts_with(
+            "sequenceDiagram\n    participant Client\n    participant Server as API server\n    Client->>+Server: GET /status\n    %% retries are bounded\n    loop up to 3 times\n        Server-->>Cache: lookup\n    end\n    par fast path"
+        ));
+        assert!(doc.contains("\n    and slow path\n        Server-xDB: query\n    end\n"));
+        assert!(doc.contains("%% @participant: Server {\"uid\":"));
+
+        // `Client` is now declared, to keep it first
+        let reparsed = parse_document(&doc).unwrap();
+        assert!(reparsed.diagnostics.is_empty());
+        assert!(reparsed.nodes.is_empty());
+        let (before, after) = (store.sequence.unwrap(), reparsed.sequence.unwrap());
+        assert_eq!(after.statements, before.statements);
+        let summary = |s: &crate::types::SequenceDiagram| -> Vec<_> {
+            s.participants.iter().map(|p| (p.uid.clone(), p.id.clone(), p.alias.clone(), p.x)).collect()
+        };
+        assert_eq!(summary(&after), summary(&before));
+    }
+
+    #[test]
+    fn test_boxes_keep_their_participants() {
+        let doc = "sequenceDiagram
+    participant A
+    box Aqua Group
+        participant B
+        actor C as Carol
+    end
+    A->>C: hi
+";
+        let parsed = parse_document(doc).unwrap();
+        assert!(parsed.diagnostics.is_empty(), "{:?}", parsed.diagnostics);
+        let sequence = parsed.sequence.as_ref().unwrap();
+        assert_eq!(sequence.boxes[0].label, "Aqua Group");
+        assert_eq!(sequence.boxes[0].participants, ["B", "C"]);
+
+        // A changed statement regenerates the diagram with the box in place
+        let mut store = GraphStore::from_parse_result(parsed);
+        let sequence = store.sequence.as_mut().unwrap();
+        let hi = sequence.statements[0].clone();
+        sequence.statements.push(hi);
+        let saved = update_document(doc, &store);
+        assert!(saved.starts_with(&format!("{}    A->>C: hi\n", doc)), "{}", saved);
+
+        let reparsed = parse_document(&saved).unwrap();
+        assert_eq!(reparsed.sequence.unwrap().boxes, store.sequence.unwrap().boxes);
+    }
+
+    #[test]
+    fn test_update_sequence_keeps_unchanged_file() {
+        let store = GraphStore::from_parse_result(parse_document(DOC).unwrap());
+        let saved = update_document(DOC, &store);
+        assert!(saved.contains("%% @participant: Cache {\"uid\":"));
+
+        // Once it has its directives, an unchanged diagram is written back as is
+        let store = GraphStore::from_parse_result(parse_document(&saved).unwrap());
+        assert_eq!(update_document(&saved, &store), saved);
+    }
+}
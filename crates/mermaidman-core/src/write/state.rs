@@ -0,0 +1,485 @@
+//! State diagram text generation.
+
+use crate::store::GraphStore;
+use crate::types::{Edge, Group, Node, NodeShape, NotePlacement, StateKind, UID};
+use crate::write::mermaid::push_style_lines;
+use std::collections::HashMap;
+
+/// Indentation per composite state level.
+const INDENT: &str = "    ";
+
+/// Append a state diagram's statements: declarations, composite states,
+/// transitions and notes, then styling statements and raw lines.
+///
+/// A composite state declares its member states so membership survives the
+/// round trip; each transition is written in the innermost composite state
+/// holding both ends.
+pub(crate) fn push_state_lines(lines: &mut Vec<String>, store: &GraphStore) {
+    if let Some(direction) = store.header.direction {
+        lines.push(format!("direction {}", direction));
+    }
+
+    StateWriter::new(store).push_scope(lines, None, 0);
+
+    push_style_lines(lines, store, &[]);
+
+    // Raw lines outside any (surviving) group go last, verbatim
+    for raw in &store.raw_lines {
+        let in_group = raw.group.as_ref().is_some_and(|g| store.groups.contains_key(g));
+        if !in_group {
+            lines.push(raw.text.clone());
+        }
+    }
+}
+
+struct StateWriter<'a> {
+    store: &'a GraphStore,
+    /// The composite state each grouped node belongs to.
+    scopes: HashMap<&'a UID, &'a str>,
+}
+
+/// The composite state a transition between active states is written in,
+/// as `Some(None)` for the root; `None` if an end is missing.
+pub(crate) fn transition_scope<'a>(store: &'a GraphStore, edge: &Edge) -> Option<Option<&'a str>> {
+    StateWriter::new(store).edge_scope(edge)
+}
+
+impl<'a> StateWriter<'a> {
+    fn new(store: &'a GraphStore) -> Self {
+        let mut scopes: HashMap<&UID, &str> = HashMap::new();
+        for group in store.groups.values() {
+            for uid in &group.members {
+                scopes.insert(uid, &group.id);
+            }
+        }
+        Self { store, scopes }
+    }
+
+    /// Append the body of a scope (the root when `group` is `None`) at `depth`,
+    /// one concurrent region after another.
+    fn push_scope(&self, lines: &mut Vec<String>, group: Option<&'a Group>, depth: usize) {
+        let indent = INDENT.repeat(depth);
+        let scope = group.map(|g| g.id.as_str());
+
+        if let Some(direction) = group.and_then(|g| g.direction) {
+            lines.push(format!("{}direction {}", indent, direction));
+        }
+
+        let nodes: Vec<&Node> = self
+            .store
+            .active_nodes()
+            .filter(|n| self.scopes.get(&n.uid).copied() == scope)
+            .collect();
+        let children = match group {
+            Some(group) => group.children.iter().filter_map(|id| self.store.get_group(id)).collect(),
+            None => self.store.root_groups().collect::<Vec<_>>(),
+        };
+        let region = |uid: &UID| group.map_or(0, |g| self.region(g, uid));
+
+        let regions = group.map_or(0, |g| g.region_starts.len());
+        for current in 0..=regions {
+            if current > 0 {
+                lines.push(format!("{}--", indent));
+            }
+
+            for node in nodes.iter().filter(|n| region(&n.uid) == current) {
+                for declaration in self.declaration(node, group.is_some()) {
+                    lines.push(format!("{}{}", indent, declaration));
+                }
+            }
+
+            for child in &children {
+                let composite = self.store.alias.get_uid(&child.id);
+                if composite.map_or(0, region) != current {
+                    continue;
+                }
+                lines.push(format!("{}{}", indent, format_composite_header(child)));
+                self.push_scope(lines, Some(child), depth + 1);
+                lines.push(format!("{}}}", indent));
+            }
+
+            for edge in self.store.active_edges() {
+                if self.note_state(edge).is_some() || self.edge_scope(edge) != Some(scope) {
+                    continue;
+                }
+                if region(&edge.source).max(region(&edge.target)) != current {
+                    continue;
+                }
+                if let (Some(source), Some(target)) = (self.store.get_node(&edge.source), self.store.get_node(&edge.target)) {
+                    lines.push(format!("{}{}", indent, format_transition(edge, source, target)));
+                }
+            }
+
+            for node in nodes.iter().filter(|n| region(&n.uid) == current) {
+                if let (StateKind::Note(placement), Some(state)) = (StateKind::of(&node.shape), self.note_of(node)) {
+                    push_note(lines, node, placement, state, &indent);
+                }
+            }
+        }
+
+        if let Some(group) = group {
+            for raw in self.store.raw_lines.iter().filter(|r| r.group.as_deref() == Some(group.id.as_str())) {
+                lines.push(format!("{}{}", indent, raw.text));
+            }
+        }
+    }
+
+    /// The concurrent region of `group` holding a node, directly or through
+    /// the composite state it is nested in; 0 for nodes outside `group`.
+    fn region(&self, group: &Group, uid: &UID) -> usize {
+        let mut uid = uid;
+        let index = loop {
+            if let Some(index) = group.members.iter().position(|m| m == uid) {
+                break index;
+            }
+            match self.scopes.get(uid).and_then(|scope| self.store.alias.get_uid(scope)) {
+                Some(composite) if composite != uid => uid = composite,
+                _ => return 0,
+            }
+        };
+        group
+            .region_starts
+            .iter()
+            .filter_map(|start| group.members.iter().position(|m| m == start))
+            .filter(|&start| start <= index)
+            .count()
+    }
+
+    /// The statements declaring `node`, if it needs any: its name, then its
+    /// description lines. States in composite states are always declared;
+    /// at the root, only when the transitions do not say everything about
+    /// them.
+    fn declaration(&self, node: &Node, grouped: bool) -> Vec<String> {
+        let id = &node.mermaid_id;
+        let descriptions = self.store.state_diagram.as_ref().map_or(&[][..], |d| d.descriptions(id));
+        let described = descriptions.iter().map(|text| format!("{} : {}", id, text));
+        let name = match StateKind::of(&node.shape) {
+            StateKind::Start | StateKind::End => return Vec::new(),
+            StateKind::Choice => Some(format!("state {} <<choice>>", id)),
+            StateKind::Fork => Some(format!("state {} <<fork>>", id)),
+            StateKind::Join => Some(format!("state {} <<join>>", id)),
+            // Without its state a note is kept as a description
+            StateKind::Note(_) if self.note_of(node).is_some() => return Vec::new(),
+            StateKind::Note(_) => node.label.as_ref().map(|label| format!("{} : {}", id, label.replace('\n', " "))),
+            // A composite state is declared by its block
+            _ if self.store.groups.contains_key(id) => None,
+            kind => {
+                let rounded = kind == StateKind::State && node.shape == NodeShape::Rounded;
+                let linked = self.store.active_edges().any(|e| e.source == node.uid || e.target == node.uid);
+                match node.label {
+                    Some(ref label) if !label.contains('"') => {
+                        Some(format!("state \"{}\" as {}", label.replace('\n', " "), id))
+                    }
+                    // Names Mermaid cannot quote are kept as a description
+                    Some(ref label) => Some(format!("{} : {}", id, label.replace('\n', " "))),
+                    None if rounded => Some(format!("state {}", id)),
+                    None if (grouped || !linked) && descriptions.is_empty() => Some(id.clone()),
+                    None => None,
+                }
+            }
+        };
+        name.into_iter().chain(described).collect()
+    }
+
+    /// The state a note edge ties a note to; `None` for other edges.
+    fn note_state(&self, edge: &Edge) -> Option<&'a Node> {
+        let source = self.store.get_node(&edge.source)?;
+        if !matches!(StateKind::of(&source.shape), StateKind::Note(_)) {
+            return None;
+        }
+        self.store.get_node(&edge.target).filter(|n| !n.deleted)
+    }
+
+    /// The state a note is tied to, if it still has one.
+    fn note_of(&self, note: &Node) -> Option<&'a Node> {
+        self.store
+            .active_edges()
+            .filter(|e| e.source == note.uid)
+            .find_map(|e| self.note_state(e))
+    }
+
+    /// The innermost composite state holding both ends of `edge`, as
+    /// `Some(None)` for the root; `None` if an end is missing.
+    fn edge_scope(&self, edge: &Edge) -> Option<Option<&'a str>> {
+        let source = self.ancestors(&edge.source)?;
+        let target = self.ancestors(&edge.target)?;
+        source.into_iter().find(|scope| target.contains(scope))
+    }
+
+    /// The scopes enclosing a node, innermost first, ending with the root.
+    fn ancestors(&self, uid: &UID) -> Option<Vec<Option<&'a str>>> {
+        self.store.get_node(uid).filter(|n| !n.deleted)?;
+        let mut scopes = Vec::new();
+        let mut current = self.scopes.get(uid).copied();
+        while let Some(id) = current {
+            scopes.push(Some(id));
+            current = self.store.get_group(id).and_then(|g| g.parent.as_deref());
+        }
+        scopes.push(None);
+        Some(scopes)
+    }
+}
+
+/// Format the line opening a composite state.
+fn format_composite_header(group: &Group) -> String {
+    match group.title {
+        Some(ref title) if title != &group.id && !title.contains('"') => {
+            format!("state \"{}\" as {} {{", title, group.id)
+        }
+        _ => format!("state {} {{", group.id),
+    }
+}
+
+/// Format a transition; start and end states are written `[*]`.
+pub(crate) fn format_transition(edge: &Edge, source: &Node, target: &Node) -> String {
+    let end = |node: &Node| match StateKind::of(&node.shape) {
+        StateKind::Start | StateKind::End => "[*]".to_string(),
+        _ => node.mermaid_id.clone(),
+    };
+    match edge.label {
+        Some(ref label) => format!("{} --> {} : {}", end(source), end(target), label),
+        None => format!("{} --> {}", end(source), end(target)),
+    }
+}
+
+/// Append a note, as a block if its text spans lines.
+fn push_note(lines: &mut Vec<String>, note: &Node, placement: NotePlacement, state: &Node, indent: &str) {
+    let side = if placement == NotePlacement::LeftOf { "left" } else { "right" };
+    let text = note.label.as_deref().unwrap_or_default();
+
+    if text.contains('\n') {
+        lines.push(format!("{}note {} of {}", indent, side, state.mermaid_id));
+        for line in text.lines() {
+            lines.push(format!("{}{}{}", indent, INDENT, line.trim()));
+        }
+        lines.push(format!("{}end note", indent));
+    } else {
+        lines.push(format!("{}note {} of {} : {}", indent, side, state.mermaid_id, text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_document;
+    use crate::store::GraphStore;
+    use crate::write::{generate_mermaidman, generate_topology, update_document};
+
+    const DOC: &str = "stateDiagram-v2
+    direction LR
+    %% lifecycle
+    [*] --> Idle
+    state \"Waiting for input\" as Idle
+    Idle --> Running : start
+    state Running {
+        [*] --> Loading
+        Loading --> Ready
+        Ready --> [*]
+    }
+    state check <<choice>>
+    Running --> check
+    check --> Done : ok
+    check --> Idle
+    note left of Idle
+        waits here
+        for a while
+    end note
+    Done --> [*]
+";
+
+    #[test]
+    fn test_generate_state_diagram() {
+        let store = GraphStore::from_parse_result(parse_document(DOC).unwrap());
+        let doc = generate_mermaidman(&store);
+
+        assert!(doc.starts_with(
+            "stateDiagram-v2
+
+direction LR
+state \"Waiting for input\" as Idle
+state check <<choice>>
+state Running {
+    Loading
+    Ready
+    [*] --> Loading
+    Loading --> Ready
+    Ready --> [*]
+}
+[*] --> Idle
+Idle --> Running : start
+Running --> check
+check --> Done : ok
+check --> Idle
+Done --> [*]
+note left of Idle
+    waits here
+    for a while
+end note
+%% lifecycle
+"
+        ));
+        assert!(doc.contains("%% @node: Running_start {\"uid\":"));
+
+        let reparsed = GraphStore::from_parse_result(parse_document(&doc).unwrap());
+        assert_eq!(generate_topology(&reparsed), generate_topology(&store));
+        assert_eq!(reparsed.nodes.len(), store.nodes.len());
+        assert_eq!(reparsed.get_group("Running").unwrap().members.len(), 4);
+    }
+
+    #[test]
+    fn test_update_state_diagram_in_place() {
+        let store = GraphStore::from_parse_result(parse_document(DOC).unwrap());
+        let saved = update_document(DOC, &store);
+        assert!(saved.starts_with(DOC));
+
+        // Moving a state rewrites only its directive
+        let mut store = GraphStore::from_parse_result(parse_document(&saved).unwrap());
+        let uid = store.get_node_by_mermaid_id("Ready").unwrap().uid.clone();
+        store.move_node(&uid, 40.0, 80.0);
+        let moved = update_document(&saved, &store);
+        assert!(moved.starts_with(DOC));
+        assert_eq!(moved.lines().count(), saved.lines().count());
+        assert!(moved.contains("%% @node: Ready {\"uid\":\"") && moved.contains("\"x\":40.0"));
+
+        // A new transition is added after the last root statement
+        let done = store.get_node_by_mermaid_id("Done").unwrap().uid.clone();
+        store.upsert_edge(crate::types::Edge::new(uid, done));
+        let changed = update_document(&moved, &store);
+        assert!(changed.starts_with(&DOC.replace("Done --> [*]\n", "Done --> [*]\n    Ready --> Done\n")));
+    }
+
+    #[test]
+    fn test_transition_edits_keep_concurrent_regions() {
+        let doc = "stateDiagram-v2
+    [*] --> Active
+    state Active {
+        %% first region
+        [*] --> A1
+        A1 --> A2
+        --
+        [*] --> B1
+        B1 --> B2
+    }
+";
+        let store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        let saved = update_document(doc, &store);
+        let mut store = GraphStore::from_parse_result(parse_document(&saved).unwrap());
+        let uid = |store: &GraphStore, id: &str| store.get_node_by_mermaid_id(id).unwrap().uid.clone();
+
+        // A2 is only mentioned by the removed transition, so it is declared
+        let removed = store
+            .active_edges()
+            .find(|e| e.source == uid(&store, "A1") && e.target == uid(&store, "A2"))
+            .unwrap()
+            .eid
+            .clone();
+        store.delete_edge(&removed);
+        store.upsert_edge(crate::types::Edge::new(uid(&store, "B2"), uid(&store, "B1")));
+
+        let changed = update_document(&saved, &store);
+        assert!(changed.starts_with(
+            "stateDiagram-v2
+    [*] --> Active
+    state Active {
+        %% first region
+        [*] --> A1
+        A2
+        --
+        [*] --> B1
+        B1 --> B2
+        B2 --> B1
+    }
+"
+        ), "{}", changed);
+
+        let reparsed = GraphStore::from_parse_result(parse_document(&changed).unwrap());
+        assert_eq!(reparsed.get_node_by_mermaid_id("A2").unwrap().uid, uid(&store, "A2"));
+        assert_eq!(update_document(&changed, &reparsed), changed);
+    }
+
+    #[test]
+    fn test_regeneration_keeps_concurrent_regions() {
+        let doc = "stateDiagram-v2
+[*] --> Active
+state Active {
+    [*] --> A1
+    A1 --> A2
+    --
+    [*] --> B1
+    B1 --> B2
+}
+";
+        let mut store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        let active = store.get_node_by_mermaid_id("Active").unwrap().uid.clone();
+        let done = crate::types::Node::new("Done");
+        store.upsert_edge(crate::types::Edge::new(active, done.uid.clone()));
+        store.upsert_node(done);
+
+        let saved = generate_mermaidman(&store);
+        assert!(saved.starts_with(
+            "stateDiagram-v2
+
+state Active {
+    A1
+    A2
+    [*] --> A1
+    A1 --> A2
+    --
+    B1
+    B2
+    [*] --> B1
+    B1 --> B2
+}
+[*] --> Active
+Active --> Done
+"
+        ), "{}", saved);
+
+        let reparsed = GraphStore::from_parse_result(parse_document(&saved).unwrap());
+        assert_eq!(reparsed.get_group("Active").unwrap().region_starts.len(), 1);
+        assert_eq!(generate_topology(&reparsed), generate_topology(&store));
+    }
+
+    #[test]
+    fn test_dotted_state_ids_keep_their_identity() {
+        let doc = "stateDiagram-v2\n[*] --> a.b\na.b --> c\n";
+        let store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        let saved = update_document(doc, &store);
+        assert!(saved.contains("%% @node: a.b {\"uid\":"));
+
+        let reparsed = parse_document(&saved).unwrap();
+        assert!(reparsed.diagnostics.is_empty(), "{:?}", reparsed.diagnostics);
+        let uids = |nodes: &[crate::types::Node]| nodes.iter().map(|n| n.uid.clone()).collect::<Vec<_>>();
+        let eids = |edges: &[crate::types::Edge]| edges.iter().map(|e| e.eid.clone()).collect::<Vec<_>>();
+        let nodes: Vec<_> = store.nodes.values().cloned().collect();
+        let edges: Vec<_> = store.edges.values().cloned().collect();
+        assert_eq!(uids(&reparsed.nodes), uids(&nodes));
+        assert_eq!(eids(&reparsed.edges), eids(&edges));
+
+        let store = GraphStore::from_parse_result(reparsed);
+        assert_eq!(update_document(&saved, &store), saved);
+    }
+
+    #[test]
+    fn test_description_keeps_the_state_name() {
+        let doc = "stateDiagram-v2\nstate \"Long name\" as LN\nLN : first line\nLN : second line\n[*] --> LN\n";
+        let store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        let ln = store.get_node_by_mermaid_id("LN").unwrap();
+        assert_eq!(ln.label.as_deref(), Some("Long name"));
+        assert_eq!(store.state_diagram.as_ref().unwrap().descriptions("LN"), ["first line", "second line"]);
+
+        let generated = generate_mermaidman(&store);
+        assert!(generated.starts_with(
+            "stateDiagram-v2\n\nstate \"Long name\" as LN\nLN : first line\nLN : second line\n[*] --> LN\n"
+        ), "{}", generated);
+        let reparsed = GraphStore::from_parse_result(parse_document(&generated).unwrap());
+        assert_eq!(reparsed.state_diagram, store.state_diagram);
+    }
+
+    #[test]
+    fn test_note_keeps_its_placement() {
+        let doc = "stateDiagram-v2\nx_note_left --> y\nnote right of x_note_left : hi\n";
+        let store = GraphStore::from_parse_result(parse_document(doc).unwrap());
+        let generated = generate_mermaidman(&store);
+        assert!(generated.contains("\nnote right of x_note_left : hi\n"), "{}", generated);
+    }
+}
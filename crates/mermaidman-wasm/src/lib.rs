@@ -14,7 +14,11 @@ pub fn init() {
 
 /// Parse a Mermaidman document and return structured data.
 ///
-/// Returns a JS object with: { topology, nodes, edges, warnings }
+/// Returns a JS object with: { topology, schema_version, header, config, accessibility, nodes, edges,
+/// groups, class_defs, raw_lines, provenance, sequence, state_diagram, class_diagram, er_diagram,
+/// source_map, diagnostics }. `sequence`, `state_diagram`, `class_diagram` and `er_diagram` are set
+/// for `sequenceDiagram`, `stateDiagram`, `classDiagram` and `erDiagram` documents. `source_map` gives the byte offsets and line/column spans
+/// of each node, edge, participant, directive and raw line.
 #[wasm_bindgen]
 pub fn parse_mermaidman(input: &str) -> Result<JsValue, JsValue> {
     let result = parse::parse_document(input)
@@ -43,17 +47,31 @@ pub fn reconcile_document(
 }
 
 /// Generate Mermaidman text from a graph store.
+///
+/// The header (`graph TD`, `flowchart LR`, ...) comes from the store.
+#[wasm_bindgen]
+pub fn generate_mermaidman(store_json: &str) -> Result<String, JsValue> {
+    let store: GraphStore = serde_json::from_str(store_json)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    
+    Ok(write::generate_mermaidman(&store))
+}
+
+/// Apply a graph store to existing document text as minimal edits.
+///
+/// Comments, blank lines, statement order and indentation are kept; only
+/// statements and directives whose content changed are rewritten.
 #[wasm_bindgen]
-pub fn generate_mermaidman(store_json: &str, direction: &str) -> Result<String, JsValue> {
+pub fn update_mermaidman(original: &str, store_json: &str) -> Result<String, JsValue> {
     let store: GraphStore = serde_json::from_str(store_json)
         .map_err(|e| JsValue::from_str(&e.to_string()))?;
     
-    Ok(write::generate_mermaidman(&store, direction))
+    Ok(write::update_document(original, &store))
 }
 
 /// Update a node's position in the document text.
 ///
-/// Hot-path operation for canvas drag updates.
+/// Hot-path operation for canvas drag updates; only the node's directive changes.
 #[wasm_bindgen]
 pub fn update_node_position(
     input: &str,
@@ -65,14 +83,14 @@ pub fn update_node_position(
     let result = parse::parse_document(input)
         .map_err(|e| JsValue::from_str(&e.to_string()))?;
     
-    let mut store = GraphStore::from_parsed(result.nodes, result.edges);
+    let mut store = GraphStore::from_parse_result(result);
     
     // Find node by mermaid_id and update
     if let Some(uid) = store.alias.get_uid(node_id).cloned() {
         store.move_node(&uid, x, y);
     }
     
-    Ok(write::generate_mermaidman(&store, "TD"))
+    Ok(write::update_document(input, &store))
 }
 
 /// Create a new empty graph store.
@@ -97,4 +115,39 @@ A[Start] --> B[End]
         assert_eq!(result.nodes.len(), 2);
         assert_eq!(result.edges.len(), 1);
     }
+
+    #[test]
+    fn test_update_node_position_keeps_header() {
+        let input = r#"flowchart LR
+A[Start] --> B[End]
+"#;
+
+        let output = update_node_position(input, "A", 10.0, 20.0).unwrap();
+        assert!(output.starts_with("flowchart LR\n"));
+        assert!(output.contains("%% @node: A {\"uid\":"));
+    }
+
+    #[test]
+    fn test_update_node_position_keeps_formatting() {
+        let input = "graph TD\n    %% start here\n    A[Start] --> B[End]\n\n%% @node: A {\"uid\":\"n_001\",\"x\":0,\"y\":0}\n%% @node: B {\"uid\":\"n_002\"}\n";
+
+        let output = update_node_position(input, "A", 10.0, 20.0).unwrap();
+        assert!(output.starts_with("graph TD\n    %% start here\n    A[Start] --> B[End]\n\n"));
+        assert!(output.contains("%% @node: A {\"uid\":\"n_001\",\"x\":10.0,\"y\":20.0"));
+        assert!(output.contains("%% @node: B {\"uid\":\"n_002\"}\n"));
+    }
+
+    #[test]
+    fn test_parse_result_exposes_source_map() {
+        let input = "graph TD\nA[Start] --> B[End]\n";
+
+        let result = parse::parse_document(input).unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+
+        let span = &json["source_map"]["nodes"][1]["spans"][0];
+        assert_eq!(span["start"], 22);
+        assert_eq!(span["start_line"], 2);
+        assert_eq!(span["start_column"], 14);
+        assert_eq!(json["source_map"]["edges"][0]["span"]["end_column"], 20);
+    }
 }
@@ -0,0 +1,126 @@
+//! Document config commands: title, theme and flowchart layout options.
+
+use crate::state::AppState;
+use mermaidman_core::types::{DocId, DocumentConfig, FlowchartConfig};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+/// A document's frontmatter title and Mermaid config.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct DocumentConfigDto {
+    pub title: Option<String>,
+    /// `default`, `base`, `dark`, `forest` or `neutral`.
+    pub theme: Option<String>,
+    pub theme_variables: Vec<ThemeVariable>,
+    pub flowchart: Option<FlowchartConfigDto>,
+}
+
+/// One `themeVariables` entry; non-string values are given as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct ThemeVariable {
+    pub name: String,
+    pub value: String,
+}
+
+/// Flowchart layout options.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct FlowchartConfigDto {
+    pub curve: Option<String>,
+    pub html_labels: Option<bool>,
+    pub node_spacing: Option<u32>,
+    pub rank_spacing: Option<u32>,
+    pub padding: Option<u32>,
+    pub diagram_padding: Option<u32>,
+    pub wrapping_width: Option<u32>,
+    pub default_renderer: Option<String>,
+    pub use_max_width: Option<bool>,
+}
+
+/// Get a document's title and config.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_document_config(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+) -> Result<DocumentConfigDto, String> {
+    let docs = state.docs.lock().unwrap();
+    let store = docs
+        .get(&DocId(doc_id))
+        .ok_or_else(|| "Document not open".to_string())?;
+
+    let config = &store.config;
+    Ok(DocumentConfigDto {
+        title: config.title.clone(),
+        theme: config.theme.clone(),
+        theme_variables: config
+            .theme_variables
+            .iter()
+            .map(|(name, value)| ThemeVariable {
+                name: name.clone(),
+                value: match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                },
+            })
+            .collect(),
+        flowchart: config.flowchart.as_ref().map(|f| FlowchartConfigDto {
+            curve: f.curve.clone(),
+            html_labels: f.html_labels,
+            node_spacing: f.node_spacing,
+            rank_spacing: f.rank_spacing,
+            padding: f.padding,
+            diagram_padding: f.diagram_padding,
+            wrapping_width: f.wrapping_width,
+            default_renderer: f.default_renderer.clone(),
+            use_max_width: f.use_max_width,
+        }),
+    })
+}
+
+/// Replace a document's title and config; written to the file on the next
+/// save. Config keys the DTO does not cover are kept.
+#[tauri::command]
+#[specta::specta]
+pub async fn set_document_config(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+    config: DocumentConfigDto,
+) -> Result<(), String> {
+    let mut docs = state.docs.lock().unwrap();
+    let store = docs
+        .get_mut(&DocId(doc_id))
+        .ok_or_else(|| "Document not open".to_string())?;
+
+    let old = std::mem::take(&mut store.config);
+    store.config = DocumentConfig {
+        title: config.title,
+        theme: config.theme,
+        theme_variables: config
+            .theme_variables
+            .into_iter()
+            .map(|v| {
+                // Numbers and booleans keep their type
+                let value = serde_json::from_str(&v.value)
+                    .ok()
+                    .filter(|value: &serde_json::Value| value.is_number() || value.is_boolean())
+                    .unwrap_or(serde_json::Value::String(v.value));
+                (v.name, value)
+            })
+            .collect(),
+        flowchart: config.flowchart.map(|f| FlowchartConfig {
+            curve: f.curve,
+            html_labels: f.html_labels,
+            node_spacing: f.node_spacing,
+            rank_spacing: f.rank_spacing,
+            padding: f.padding,
+            diagram_padding: f.diagram_padding,
+            wrapping_width: f.wrapping_width,
+            default_renderer: f.default_renderer,
+            use_max_width: f.use_max_width,
+            extra: old.flowchart.map(|f| f.extra).unwrap_or_default(),
+        }),
+        extra: old.extra,
+    };
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Diagnostic types shared by document and reconcile commands.
+
+use mermaidman_core::diagnostic::{Diagnostic as CoreDiagnostic, Fix as CoreFix};
+use mermaidman_core::parse::Span as CoreSpan;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+/// Source span (byte offsets; 1-based lines and columns).
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+    pub start_line: u32,
+    pub start_column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+}
+
+/// Secondary location for a diagnostic.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct RelatedSpan {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Text replacement applied by a quick fix.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct TextEdit {
+    pub span: Span,
+    pub new_text: String,
+}
+
+/// Quick fix offered for a diagnostic.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct Fix {
+    pub title: String,
+    pub edits: Vec<TextEdit>,
+}
+
+/// Problem found in a document.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct Diagnostic {
+    pub code: String,
+    pub severity: String,
+    pub message: String,
+    pub span: Option<Span>,
+    pub related: Vec<RelatedSpan>,
+    pub fix: Option<Fix>,
+}
+
+impl From<CoreSpan> for Span {
+    fn from(span: CoreSpan) -> Self {
+        Self {
+            start: span.start as u32,
+            end: span.end as u32,
+            start_line: span.start_line as u32,
+            start_column: span.start_column as u32,
+            end_line: span.end_line as u32,
+            end_column: span.end_column as u32,
+        }
+    }
+}
+
+impl From<CoreFix> for Fix {
+    fn from(fix: CoreFix) -> Self {
+        Self {
+            title: fix.title,
+            edits: fix
+                .edits
+                .into_iter()
+                .map(|e| TextEdit {
+                    span: e.span.into(),
+                    new_text: e.new_text,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<CoreDiagnostic> for Diagnostic {
+    fn from(d: CoreDiagnostic) -> Self {
+        Self {
+            code: d.code.as_str().to_string(),
+            severity: d.severity.as_str().to_string(),
+            message: d.message,
+            span: d.span.map(Span::from),
+            related: d
+                .related
+                .into_iter()
+                .map(|r| RelatedSpan {
+                    span: r.span.into(),
+                    message: r.message,
+                })
+                .collect(),
+            fix: d.fix.map(Fix::from),
+        }
+    }
+}
+
+/// Convert core diagnostics for the frontend.
+pub fn to_dto(diagnostics: Vec<CoreDiagnostic>) -> Vec<Diagnostic> {
+    diagnostics.into_iter().map(Diagnostic::from).collect()
+}
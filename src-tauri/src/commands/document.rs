@@ -1,25 +1,27 @@
 //! Document management commands.
 
-use crate::db::Database;
+use crate::commands::diagnostics::{self, Diagnostic};
+use crate::db::{Database, OutgoingLink};
 use crate::state::AppState;
 use mermaidman_core::{parse, store::GraphStore, types::DocId, write};
 use serde::{Deserialize, Serialize};
 use specta::Type;
 use std::fs;
+use std::path::{Component, Path, PathBuf};
 
 /// Result of opening a document.
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 pub struct OpenDocResult {
     pub doc_id: String,
     pub content: String,
-    pub warnings: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 /// Result of saving a document.
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 pub struct SaveDocResult {
     pub success: bool,
-    pub warnings: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 /// Open a document from disk.
@@ -36,7 +38,8 @@ pub async fn open_doc(
     let parsed = parse::parse_document(&content).map_err(|e| e.to_string())?;
 
     // Create store from parsed data
-    let store = GraphStore::from_parsed(parsed.nodes, parsed.edges);
+    let parse_diagnostics = parsed.diagnostics.clone();
+    let store = GraphStore::from_parse_result(parsed);
 
     // Generate doc ID from path
     let doc_id = DocId::from_path(&path);
@@ -56,17 +59,18 @@ pub async fn open_doc(
                 .and_then(|s| s.to_str())
                 .unwrap_or("Untitled");
 
-            let _ = db.index_document(&doc_id, title, &content);
+            let _ = db.index_document(&doc_id, title, &content, &accessibility_text(&store));
+            let _ = index_links(db, &doc_id, &path, &store);
         }
     }
 
-    // Generate clean content (reconciled)
-    let clean_content = write::generate_mermaidman(&store, "TD");
+    // Add missing directives without reformatting the user's text
+    let clean_content = write::update_document(&content, &store);
 
     Ok(OpenDocResult {
         doc_id: doc_id.0,
         content: clean_content,
-        warnings: parsed.warnings,
+        diagnostics: diagnostics::to_dto(parse_diagnostics),
     })
 }
 
@@ -89,8 +93,12 @@ pub async fn save_doc(
         .cloned()
         .ok_or_else(|| "Document not open".to_string())?;
 
-    // Generate Mermaidman text
-    let content = write::generate_mermaidman(&store, "TD");
+    // Apply the store to the file on disk as minimal edits, so the user's
+    // formatting survives; a new file is generated in full
+    let content = match fs::read_to_string(&path) {
+        Ok(original) => write::update_document(&original, &store),
+        Err(_) => write::generate_mermaidman(&store),
+    };
 
     // Write to file
     fs::write(&path, &content).map_err(|e| e.to_string())?;
@@ -103,16 +111,29 @@ pub async fn save_doc(
                 .and_then(|s| s.to_str())
                 .unwrap_or("Untitled");
 
-            let _ = db.index_document(&doc_id, title, &content);
+            let _ = db.index_document(&doc_id, title, &content, &accessibility_text(&store));
+            let _ = index_links(db, &doc_id, &path, &store);
         }
     }
 
     Ok(SaveDocResult {
         success: true,
-        warnings: vec![],
+        diagnostics: vec![],
     })
 }
 
+/// The `accTitle` and `accDescr` text to index.
+fn accessibility_text(store: &GraphStore) -> String {
+    let accessibility = &store.accessibility;
+    let parts: Vec<&str> = accessibility
+        .title
+        .iter()
+        .chain(&accessibility.description)
+        .map(String::as_str)
+        .collect();
+    parts.join("\n")
+}
+
 /// Close a document (remove from memory).
 #[tauri::command]
 #[specta::specta]
@@ -124,3 +145,46 @@ pub async fn close_doc(
     state.docs.lock().unwrap().remove(&doc_id);
     Ok(())
 }
+
+/// Record `click` hrefs that point at other `.mmd` files in the backlinks table.
+fn index_links(
+    db: &Database,
+    doc_id: &DocId,
+    path: &str,
+    store: &GraphStore,
+) -> anyhow::Result<()> {
+    let links: Vec<OutgoingLink> = store
+        .active_nodes()
+        .filter_map(|node| {
+            let link = node.link.as_ref()?;
+            let (target_path, fragment) = link.diagram_target()?;
+
+            Some(OutgoingLink {
+                source_node: node.uid.clone(),
+                target_doc: DocId::from_path(&resolve_link_path(path, target_path)),
+                target_node: fragment.unwrap_or_default().to_string(),
+                link_text: link.tooltip.clone().or_else(|| node.label.clone()),
+            })
+        })
+        .collect();
+
+    db.replace_links(doc_id, &links)
+}
+
+/// Resolve a link path relative to the linking document's directory.
+fn resolve_link_path(doc_path: &str, link_path: &str) -> String {
+    let base = Path::new(doc_path).parent().unwrap_or_else(|| Path::new(""));
+    let mut resolved = PathBuf::new();
+
+    for component in base.join(link_path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            other => resolved.push(other),
+        }
+    }
+
+    resolved.to_string_lossy().into_owned()
+}
@@ -1,5 +1,8 @@
 //! Tauri command modules.
 
+pub mod config;
+pub mod diagnostics;
 pub mod document;
+pub mod provenance;
 pub mod reconcile;
 pub mod search;
@@ -0,0 +1,94 @@
+//! AI provenance commands.
+
+use crate::state::AppState;
+use mermaidman_core::types::{AiReview, DocId, UID};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+/// One AI action on a node.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct ProvenanceEntry {
+    pub node_uid: String,
+    /// Position in the node's log, as passed to `set_ai_review`.
+    pub index: u32,
+    pub action: String,
+    pub provider: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: Option<f64>,
+    /// `pending`, `accepted` or `rejected`.
+    pub status: String,
+    /// Whether the node's current text is the text this action wrote.
+    pub machine_written: bool,
+}
+
+/// List AI provenance for a document, or for one node of it.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_provenance(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+    node_uid: Option<String>,
+) -> Result<Vec<ProvenanceEntry>, String> {
+    let docs = state.docs.lock().unwrap();
+    let store = docs
+        .get(&DocId(doc_id))
+        .ok_or_else(|| "Document not open".to_string())?;
+
+    let mut entries = Vec::new();
+    for (uid, log) in &store.provenance {
+        if node_uid.as_ref().is_some_and(|wanted| wanted != &uid.0) {
+            continue;
+        }
+        let current = store.machine_written(uid);
+        for (index, record) in log.iter().enumerate() {
+            entries.push(ProvenanceEntry {
+                node_uid: uid.0.clone(),
+                index: index as u32,
+                action: record.action.clone(),
+                provider: record.provider.clone(),
+                timestamp: record.timestamp.map(|t| t as f64),
+                status: review_name(record.status).to_string(),
+                machine_written: current.is_some_and(|c| std::ptr::eq(c, record)),
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Accept or reject an AI action; written to the file on the next save.
+#[tauri::command]
+#[specta::specta]
+pub async fn set_ai_review(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+    node_uid: String,
+    index: u32,
+    status: String,
+) -> Result<(), String> {
+    let status = match status.as_str() {
+        "pending" => AiReview::Pending,
+        "accepted" => AiReview::Accepted,
+        "rejected" => AiReview::Rejected,
+        other => return Err(format!("Unknown review status: {}", other)),
+    };
+
+    let mut docs = state.docs.lock().unwrap();
+    let store = docs
+        .get_mut(&DocId(doc_id))
+        .ok_or_else(|| "Document not open".to_string())?;
+
+    if store.set_ai_review(&UID::from_str(&node_uid), index as usize, status) {
+        Ok(())
+    } else {
+        Err("No such AI action".to_string())
+    }
+}
+
+fn review_name(status: AiReview) -> &'static str {
+    match status {
+        AiReview::Pending => "pending",
+        AiReview::Accepted => "accepted",
+        AiReview::Rejected => "rejected",
+    }
+}
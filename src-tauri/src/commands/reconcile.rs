@@ -1,5 +1,6 @@
 //! Reconcile commands.
 
+use crate::commands::diagnostics::{self, Diagnostic};
 use crate::state::AppState;
 use mermaidman_core::{parse, reconcile, store::GraphStore, types::DocId};
 use serde::{Deserialize, Serialize};
@@ -9,7 +10,7 @@ use specta::Type;
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 pub struct ReconcileResult {
     pub content: String,
-    pub warnings: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
     pub orphaned_nodes: Vec<String>,
     pub orphaned_edges: Vec<String>,
 }
@@ -45,7 +46,7 @@ pub async fn reconcile(
 
     Ok(ReconcileResult {
         content: result.text,
-        warnings: result.warnings,
+        diagnostics: diagnostics::to_dto(result.diagnostics),
         orphaned_nodes: result.orphaned_nodes.iter().map(|u| u.0.clone()).collect(),
         orphaned_edges: result.orphaned_edges.iter().map(|e| e.0.clone()).collect(),
     })
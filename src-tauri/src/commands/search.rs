@@ -1,7 +1,7 @@
 //! Search and backlink commands.
 
 use crate::state::AppState;
-use mermaidman_core::types::UID;
+use mermaidman_core::types::{DocId, UID};
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
@@ -46,18 +46,60 @@ pub async fn search(
         .collect())
 }
 
-/// Get backlinks to a node.
+/// Get backlinks to a node of an open document.
+///
+/// Links name their target by Mermaid ID (`other.mmd#A`), so the node's
+/// current Mermaid ID is looked up and matched within its document.
 #[tauri::command]
 #[specta::specta]
 pub async fn get_backlinks(
     state: tauri::State<'_, AppState>,
+    doc_id: String,
     node_uid: String,
 ) -> Result<Vec<Backlink>, String> {
+    let doc_id = DocId(doc_id);
+    let mermaid_id = {
+        let docs = state.docs.lock().unwrap();
+        let store = docs
+            .get(&doc_id)
+            .ok_or_else(|| "Document not open".to_string())?;
+        store
+            .alias
+            .get_mermaid_id(&UID::from_str(&node_uid))
+            .cloned()
+            .ok_or_else(|| format!("Node not found: {}", node_uid))?
+    };
+
     let db_guard = state.get_db().map_err(|e| e.to_string())?;
     let db = db_guard.as_ref().ok_or("Database not initialized")?;
 
-    let uid = UID::from_str(&node_uid);
-    let results = db.get_backlinks(&uid).map_err(|e| e.to_string())?;
+    let results = db
+        .get_backlinks(&doc_id, &mermaid_id)
+        .map_err(|e| e.to_string())?;
+
+    Ok(results
+        .into_iter()
+        .map(|b| Backlink {
+            source_doc: b.source_doc.0,
+            source_node: b.source_node.0,
+            link_text: b.link_text,
+        })
+        .collect())
+}
+
+/// Get backlinks to a document from `click` links in other diagrams.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_doc_backlinks(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+) -> Result<Vec<Backlink>, String> {
+    let db_guard = state.get_db().map_err(|e| e.to_string())?;
+    let db = db_guard.as_ref().ok_or("Database not initialized")?;
+
+    let results = db
+        .get_document_backlinks(&DocId(doc_id))
+        .map_err(|e| e.to_string())?;
 
     Ok(results
         .into_iter()
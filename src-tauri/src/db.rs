@@ -33,16 +33,31 @@ impl Database {
                 path TEXT UNIQUE,
                 title TEXT,
                 content TEXT,
+                accessibility TEXT,
                 updated_at INTEGER
             )",
             [],
         )?;
 
+        // Databases from before `accessibility` get the column, and the FTS
+        // table and triggers are recreated to index it
+        let migrate = self.conn.prepare("SELECT accessibility FROM documents LIMIT 0").is_err();
+        if migrate {
+            self.conn.execute_batch(
+                "ALTER TABLE documents ADD COLUMN accessibility TEXT;
+                 DROP TRIGGER IF EXISTS documents_ai;
+                 DROP TRIGGER IF EXISTS documents_ad;
+                 DROP TRIGGER IF EXISTS documents_au;
+                 DROP TABLE IF EXISTS documents_fts;",
+            )?;
+        }
+
         // Full-text search
         self.conn.execute(
             "CREATE VIRTUAL TABLE IF NOT EXISTS documents_fts USING fts5(
                 title,
                 content,
+                accessibility,
                 content=documents,
                 content_rowid=rowid
             )",
@@ -52,30 +67,34 @@ impl Database {
         // Triggers to keep FTS in sync
         self.conn.execute(
             "CREATE TRIGGER IF NOT EXISTS documents_ai AFTER INSERT ON documents BEGIN
-                INSERT INTO documents_fts(rowid, title, content)
-                VALUES (new.rowid, new.title, new.content);
+                INSERT INTO documents_fts(rowid, title, content, accessibility)
+                VALUES (new.rowid, new.title, new.content, new.accessibility);
             END",
             [],
         )?;
 
         self.conn.execute(
             "CREATE TRIGGER IF NOT EXISTS documents_ad AFTER DELETE ON documents BEGIN
-                INSERT INTO documents_fts(documents_fts, rowid, title, content)
-                VALUES('delete', old.rowid, old.title, old.content);
+                INSERT INTO documents_fts(documents_fts, rowid, title, content, accessibility)
+                VALUES('delete', old.rowid, old.title, old.content, old.accessibility);
             END",
             [],
         )?;
 
         self.conn.execute(
             "CREATE TRIGGER IF NOT EXISTS documents_au AFTER UPDATE ON documents BEGIN
-                INSERT INTO documents_fts(documents_fts, rowid, title, content)
-                VALUES('delete', old.rowid, old.title, old.content);
-                INSERT INTO documents_fts(rowid, title, content)
-                VALUES (new.rowid, new.title, new.content);
+                INSERT INTO documents_fts(documents_fts, rowid, title, content, accessibility)
+                VALUES('delete', old.rowid, old.title, old.content, old.accessibility);
+                INSERT INTO documents_fts(rowid, title, content, accessibility)
+                VALUES (new.rowid, new.title, new.content, new.accessibility);
             END",
             [],
         )?;
 
+        if migrate {
+            self.conn.execute("INSERT INTO documents_fts(documents_fts) VALUES('rebuild')", [])?;
+        }
+
         // Nodes index
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS nodes (
@@ -123,16 +142,17 @@ impl Database {
         Ok(())
     }
 
-    /// Index a document for full-text search.
-    pub fn index_document(&self, doc_id: &DocId, title: &str, content: &str) -> Result<()> {
+    /// Index a document for full-text search; `accessibility` is its
+    /// `accTitle` and `accDescr` text.
+    pub fn index_document(&self, doc_id: &DocId, title: &str, content: &str, accessibility: &str) -> Result<()> {
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
             .as_millis() as i64;
 
         self.conn.execute(
-            "INSERT OR REPLACE INTO documents (id, title, content, updated_at)
-             VALUES (?1, ?2, ?3, ?4)",
-            params![doc_id.0, title, content, now],
+            "INSERT OR REPLACE INTO documents (id, title, content, accessibility, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![doc_id.0, title, content, accessibility, now],
         )?;
 
         Ok(())
@@ -141,7 +161,7 @@ impl Database {
     /// Search documents using FTS5.
     pub fn search(&self, query: &str, limit: u32) -> Result<Vec<SearchResult>> {
         let mut stmt = self.conn.prepare(
-            "SELECT d.id, d.title, snippet(documents_fts, 1, '<mark>', '</mark>', '...', 32) as snippet
+            "SELECT d.id, d.title, snippet(documents_fts, -1, '<mark>', '</mark>', '...', 32) as snippet
              FROM documents_fts
              JOIN documents d ON documents_fts.rowid = d.rowid
              WHERE documents_fts MATCH ?1
@@ -162,16 +182,61 @@ impl Database {
         Ok(results)
     }
 
-    /// Get backlinks to a node.
-    pub fn get_backlinks(&self, target_node: &UID) -> Result<Vec<Backlink>> {
+    /// Replace the outgoing links recorded for a document.
+    pub fn replace_links(&self, source_doc: &DocId, links: &[OutgoingLink]) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM backlinks WHERE source_doc = ?1",
+            params![source_doc.0],
+        )?;
+
+        for link in links {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO backlinks (source_doc, source_node, target_doc, target_node, link_text)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    source_doc.0,
+                    link.source_node.0,
+                    link.target_doc.0,
+                    link.target_node,
+                    link.link_text
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Get backlinks to a document (from any node to any of its nodes).
+    pub fn get_document_backlinks(&self, target_doc: &DocId) -> Result<Vec<Backlink>> {
         let mut stmt = self.conn.prepare(
             "SELECT source_doc, source_node, link_text
              FROM backlinks
-             WHERE target_node = ?1",
+             WHERE target_doc = ?1",
         )?;
 
         let results = stmt
-            .query_map(params![target_node.0], |row| {
+            .query_map(params![target_doc.0], |row| {
+                Ok(Backlink {
+                    source_doc: DocId(row.get(0)?),
+                    source_node: UID(row.get(1)?),
+                    link_text: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(results)
+    }
+
+    /// Get backlinks to a node, given by its Mermaid ID as `click` hrefs name it.
+    pub fn get_backlinks(&self, target_doc: &DocId, target_node: &str) -> Result<Vec<Backlink>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT source_doc, source_node, link_text
+             FROM backlinks
+             WHERE target_doc = ?1 AND target_node = ?2",
+        )?;
+
+        let results = stmt
+            .query_map(params![target_doc.0, target_node], |row| {
                 Ok(Backlink {
                     source_doc: DocId(row.get(0)?),
                     source_node: UID(row.get(1)?),
@@ -199,3 +264,14 @@ pub struct Backlink {
     pub source_node: UID,
     pub link_text: Option<String>,
 }
+
+/// A link from a node to another document, as stored in `backlinks`.
+#[derive(Debug, Clone)]
+pub struct OutgoingLink {
+    pub source_node: UID,
+    pub target_doc: DocId,
+    /// Mermaid ID of the target node, from the href fragment; empty when
+    /// linking the whole document.
+    pub target_node: String,
+    pub link_text: Option<String>,
+}
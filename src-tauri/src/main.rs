@@ -17,9 +17,14 @@ fn main() {
             commands::document::open_doc,
             commands::document::save_doc,
             commands::document::close_doc,
+            commands::config::get_document_config,
+            commands::config::set_document_config,
+            commands::provenance::get_provenance,
+            commands::provenance::set_ai_review,
             commands::reconcile::reconcile,
             commands::search::search,
             commands::search::get_backlinks,
+            commands::search::get_doc_backlinks,
         ])
         .setup(|app| {
             // Initialize database
@@ -37,9 +42,14 @@ fn main() {
                         document::open_doc,
                         document::save_doc,
                         document::close_doc,
+                        config::get_document_config,
+                        config::set_document_config,
+                        provenance::get_provenance,
+                        provenance::set_ai_review,
                         reconcile::reconcile,
                         search::search,
                         search::get_backlinks,
+                        search::get_doc_backlinks,
                     ]);
                 
                 builder